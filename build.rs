@@ -0,0 +1,72 @@
+//! Build script that embeds the sources of `multivers-runner` in `cargo-multivers`.
+//!
+//! It extracts the archive of the sources of the runner committed in `src/` (see `src/archive.rs`) to `OUT_DIR`,
+//! and generates a Rust file that contains its files, so that the runner can be built without downloading it.
+//! The archive is part of the package published on crates.io, so its sources are also embedded when `cargo-multivers` is built from it.
+//! If `MULTIVERS_UPDATE_RUNNER_ARCHIVE` is set, the archive is first generated again from the `multivers-runner` directory next to this build script.
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[path = "src/archive.rs"]
+mod archive;
+
+fn main() -> io::Result<()> {
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap_or_default());
+    let runner_dir = out_dir.join("multivers-runner");
+    let archive_path = manifest_dir.join(archive::ARCHIVE_PATH);
+
+    println!("cargo:rerun-if-changed={}", archive::ARCHIVE_PATH);
+    println!("cargo:rerun-if-changed=src/archive.rs");
+    println!("cargo:rerun-if-env-changed={}", archive::UPDATE_ENV);
+
+    if std::env::var_os(archive::UPDATE_ENV).is_some() {
+        let sources_dir = manifest_dir.join("multivers-runner");
+        println!("cargo:rerun-if-changed={}", sources_dir.display());
+
+        let workspace_manifest = fs::read_to_string(manifest_dir.join("Cargo.toml"))?;
+        let archive = archive::generate(&sources_dir, &workspace_manifest)?;
+        if fs::read(&archive_path).ok().as_ref() != Some(&archive) {
+            fs::write(&archive_path, archive)?;
+        }
+    }
+
+    let data = fs::read(&archive_path)?;
+    let archive = archive::read(&data)?;
+
+    let version = archive
+        .iter()
+        .find(|(path, _)| *path == "Cargo.toml")
+        .and_then(|(_, manifest)| {
+            std::str::from_utf8(manifest)
+                .ok()?
+                .lines()
+                .find_map(|line| line.strip_prefix("version = "))
+                .map(str::to_owned)
+        })
+        .ok_or_else(|| io::Error::other("Missing version of the runner in its archive"))?;
+
+    let mut files = String::new();
+    for (relative_path, content) in archive {
+        let path = runner_dir.join(relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+
+        files.push_str(&format!(
+            "    ({relative_path:?}, include_bytes!({path:?})),\n"
+        ));
+    }
+
+    fs::write(
+        out_dir.join("runner_sources.rs"),
+        format!(
+            "/// Version of the embedded runner\n\
+             const EMBEDDED_RUNNER_VERSION: &str = {version};\n\n\
+             /// Files of the embedded runner, with their path relative to its crate\n\
+             const EMBEDDED_RUNNER_SOURCES: &[(&str, &[u8])] = &[\n{files}];\n"
+        ),
+    )
+}
@@ -3,25 +3,91 @@
 //! It reads a JSON file that contains a set of paths to executables and their dependency on CPU features
 //! from the environment variable `MULTIVERS_BUILDS_DESCRIPTION_PATH`.
 //! Then, it generates a Rust file that contains the source and the patches.
+//! The builds are compressed with LZ4, or with the `compression` (and `compression_level`) of the JSON file.
+//! If `uncompressed_source` is set in the JSON file, the source is stored uncompressed and page-aligned.
+//! If `delta_layout` is `tree`, each build is patched from its nearest build instead of the source,
+//! and the sizes of the patches of both layouts are written to `delta_report.json`.
+//! The patches are generated in parallel, and cached in `OUT_DIR` to only generate again the ones of the builds that changed.
+//! With the `stub` feature, no builds are embedded: the runner reads the ones `cargo multivers --runner-stub` appends to it,
+//! so it includes the decoders of all the patches.
+//! If `external_variants` is set in the JSON file, no builds are embedded either: the runner only embeds their SHA3-256 hash,
+//! and reads them from the files `cargo multivers --external-variants` writes beside it.
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::io::{BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use quote::{format_ident, quote};
+use quote::quote;
 
 use serde::Deserialize;
 
+use rayon::prelude::*;
+
+use sha3::{Digest, Sha3_256};
+
 use proc_exit::Exit;
 
+#[path = "build/detection.rs"]
+#[allow(dead_code, reason = "the backends of the runner use the other tables")]
+mod detection;
+#[path = "build/patches.rs"]
+mod patches;
+#[path = "build/std_detect.rs"]
+mod std_detect;
+
+use patches::{Compression, Delta, DeltaLayout, DeltaReport, Patch, Patches};
+
 #[derive(Default, Deserialize)]
 struct BuildDescription {
     path: PathBuf,
     features: Vec<String>,
+    #[serde(default)]
+    cpus: Vec<String>,
+    #[serde(default)]
+    baseline: bool,
+}
+
+#[derive(Default, Deserialize)]
+struct RuleDescription {
+    #[serde(default)]
+    vendor: Option<String>,
+    #[serde(default)]
+    family: Option<u32>,
+    #[serde(default)]
+    models: Vec<u32>,
+    prefer: String,
+    over: String,
+}
+
+/// Directory of `OUT_DIR` in which the patches are cached, so that they are only generated again when a build changes
+const PATCH_CACHE_DIRECTORY: &str = "patch_cache";
+
+/// Version of the format of the cached patches, part of their key so that the patches cached in another format are not read
+const PATCH_CACHE_VERSION: u32 = 2;
+
+fn default_delta_encoders() -> Vec<Delta> {
+    vec![Delta::Gdelta]
 }
 
 #[derive(Default, Deserialize)]
 struct BuildsDescription {
     builds: Vec<BuildDescription>,
+    #[serde(default)]
+    rules: Vec<RuleDescription>,
+    #[serde(default)]
+    uncompressed_source: bool,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default)]
+    compression_level: Option<i32>,
+    #[serde(default = "default_delta_encoders")]
+    delta_encoders: Vec<Delta>,
+    #[serde(default)]
+    delta_layout: DeltaLayout,
+    /// Layout of the files of the builds beside the runner, which is only needed by `cargo multivers`
+    #[serde(default)]
+    external_variants: Option<serde::de::IgnoredAny>,
 }
 
 impl BuildsDescription {
@@ -58,9 +124,13 @@ impl BuildsDescription {
     }
 
     fn remove_source(&mut self) -> Option<BuildDescription> {
-        // The source is one requiring no or the minimum amount of features.
+        // The source is the build of the baseline CPU, if there is one.
+        if let Some(position) = self.builds.iter().position(|build| build.baseline) {
+            return Some(self.builds.remove(position));
+        }
+
+        // Otherwise, it is one requiring no or the minimum amount of features.
         // Since we sorted the builds by features, we just have to remove the last element.
-        // We should make it configurable at some point.
         self.builds.pop()
     }
 
@@ -79,8 +149,251 @@ impl BuildsDescription {
         }
     }
 
+    /// Returns the key of the patch from a source to a target in the cache
+    ///
+    /// It depends on the hashes of the source and the target, but also on the encoders, the compression, and the format of the cache.
+    fn cache_key(&self, source_hash: &[u8], target_hash: &[u8]) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update(source_hash);
+        hasher.update(target_hash);
+        hasher.update(format!(
+            "{PATCH_CACHE_VERSION} {:?} {:?} {:?}",
+            self.delta_encoders, self.compression, self.compression_level
+        ));
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Generates in parallel the patches from the parent (the source if `None`) to the target of each pair,
+    /// reusing the patches cached in `OUT_DIR` by a previous run (with the duration of their generation)
+    ///
+    /// The cached patches that are no longer used are removed.
+    fn encode_all(
+        &self,
+        out_dir: &Path,
+        source: &[u8],
+        targets: &[Vec<u8>],
+        pairs: &[(Option<usize>, usize)],
+    ) -> Result<Patches, Exit> {
+        if self.delta_encoders.is_empty() && !pairs.is_empty() {
+            return Err(proc_exit::sysexits::CONFIG_ERR
+                .with_message("At least one delta encoder is required to generate the patches"));
+        }
+
+        let cache_dir = out_dir.join(PATCH_CACHE_DIRECTORY);
+        std::fs::create_dir_all(&cache_dir).map_err(|_| {
+            proc_exit::sysexits::IO_ERR.with_message(format!(
+                "Failed to create the patch cache directory {}",
+                cache_dir.display(),
+            ))
+        })?;
+
+        let source_hash = Sha3_256::digest(source);
+        let target_hashes = targets.par_iter().map(Sha3_256::digest).collect::<Vec<_>>();
+
+        // `Exit` cannot be sent between threads, so the errors are only turned into it once the patches are generated
+        let patches = pairs
+            .par_iter()
+            .map(|&(parent, i)| {
+                let build = |i: usize| targets.get(i).zip(target_hashes.get(i));
+                let ((parent_data, parent_hash), (target, target_hash)) = parent
+                    .map_or(Some((source, &source_hash)), |parent| {
+                        build(parent).map(|(data, hash)| (data.as_slice(), hash))
+                    })
+                    .zip(build(i))
+                    .ok_or_else(|| "Invalid pair of builds to patch".to_owned())?;
+
+                let key = self.cache_key(parent_hash, target_hash);
+                let (path, patch) = self
+                    .encode_cached(&cache_dir, &key, parent_data, target)
+                    .map_err(|error| error.to_string())?;
+
+                Ok(((parent, i), path, patch))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|message| proc_exit::sysexits::IO_ERR.with_message(message))?;
+
+        // The patches of the builds that changed since the previous run are no longer needed
+        let used = patches
+            .iter()
+            .map(|(_, path, _)| path.as_path())
+            .collect::<BTreeSet<_>>();
+        if let Ok(entries) = std::fs::read_dir(&cache_dir) {
+            for entry in entries.filter_map(Result::ok) {
+                if !used.contains(entry.path().as_path()) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        Ok(patches
+            .into_iter()
+            .map(|(pair, _, patch)| (pair, patch))
+            .collect())
+    }
+
+    /// Generates the patch from the source to the target, or reads it from the cache directory
+    ///
+    /// A cached patch starts with the time spent generating it, in seconds as a 64-bit little-endian float,
+    /// so that the reports of the layouts do not depend on the cache.
+    /// Returns the path of the patch in the cache.
+    fn encode_cached(
+        &self,
+        cache_dir: &Path,
+        key: &str,
+        source: &[u8],
+        target: &[u8],
+    ) -> Result<(PathBuf, Patch), Exit> {
+        for &delta in &self.delta_encoders {
+            let path = cache_dir.join(format!("{key}.{}", delta.name()));
+            let Ok(cached) = std::fs::read(&path) else {
+                continue;
+            };
+            let Some((seconds, compressed)) = cached.split_first_chunk() else {
+                continue;
+            };
+            let Ok(duration) = Duration::try_from_secs_f64(f64::from_le_bytes(*seconds)) else {
+                continue;
+            };
+            let patch = Patch {
+                delta,
+                compressed: compressed.to_vec(),
+                duration,
+            };
+
+            return Ok((path, patch));
+        }
+
+        let patch = Patch::encode(
+            &self.delta_encoders,
+            self.compression,
+            self.compression_level,
+            source,
+            target,
+        )
+        .map_err(|error| proc_exit::sysexits::IO_ERR.with_message(error.to_string()))?;
+        let path = cache_dir.join(format!("{key}.{}", patch.delta.name()));
+        // The patch is renamed once written, so that an interrupted build never leaves a truncated patch in the cache
+        let temporary_path = cache_dir.join(format!("{key}.{}.tmp", patch.delta.name()));
+        let cached = [
+            patch.duration.as_secs_f64().to_le_bytes().as_slice(),
+            &patch.compressed,
+        ]
+        .concat();
+        std::fs::write(&temporary_path, cached)
+            .and_then(|()| std::fs::rename(&temporary_path, &path))
+            .map_err(|_| {
+                proc_exit::sysexits::IO_ERR.with_message(format!(
+                    "Failed to write the cached patch {}",
+                    path.display()
+                ))
+            })?;
+
+        Ok((path, patch))
+    }
+
+    /// Generates the Rust file of a runner that reads the builds from files beside it,
+    /// so it only embeds the hashes of the builds
+    fn generate_external_sources(mut self, dest_path: &Path) -> Result<(), Exit> {
+        let source_build = self.remove_source();
+        let build_tokens = |build: &BuildDescription| {
+            let data = std::fs::read(&build.path).map_err(|_| {
+                proc_exit::sysexits::IO_ERR
+                    .with_message(format!("Failed to read build {}", build.path.display()))
+            })?;
+            let hash = Sha3_256::digest(data);
+            let hash = hash.as_slice();
+            let features = &build.features;
+            let cpus = &build.cpus;
+
+            Ok(quote! {
+                Build {
+                    data: Data::External(&[#(#hash),*]),
+                    features: &[#(#features),*],
+                    cpus: &[#(#cpus),*],
+                    source: None,
+                }
+            })
+        };
+
+        let source = source_build.as_ref().map(build_tokens).transpose()?.ok_or_else(|| {
+            proc_exit::sysexits::DATA_ERR.with_message(
+                "The JSON file loaded from the environment variable MULTIVERS_BUILDS_DESCRIPTION_PATH must contain builds",
+            )
+        })?;
+        let patches = self
+            .builds
+            .iter()
+            .map(build_tokens)
+            .collect::<Result<Vec<_>, Exit>>()?;
+        let n_builds = patches.len();
+        let rules = self.rules_tokens();
+
+        let tokens = quote! {
+            // The builds are read as is from the files beside the runner
+            const COMPRESSION: Compression = Compression::None;
+            static SOURCE: Build<'static> = #source;
+            static PATCHES: [Build<'static>; #n_builds] = [
+                #(#patches),*
+            ];
+            #rules
+        };
+
+        std::fs::write(dest_path, tokens.to_string()).map_err(|_| {
+            proc_exit::sysexits::IO_ERR.with_message(format!(
+                "Failed to write generated Rust file to {}",
+                dest_path.display(),
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    /// Returns the definition of the rules that select a build tuned for a CPU
+    fn rules_tokens(&self) -> impl quote::ToTokens + use<> {
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let vendor = match &rule.vendor {
+                    Some(vendor) => quote! { Some(#vendor) },
+                    None => quote! { None },
+                };
+                let family = match rule.family {
+                    Some(family) => quote! { Some(#family) },
+                    None => quote! { None },
+                };
+                let models = &rule.models;
+                let prefer = &rule.prefer;
+                let over = &rule.over;
+
+                quote! {
+                    Rule {
+                        vendor: #vendor,
+                        family: #family,
+                        models: &[#(#models),*],
+                        prefer: #prefer,
+                        over: #over,
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let n_rules = rules.len();
+
+        quote! {
+            static RULES: [Rule<'static>; #n_rules] = [
+                #(#rules),*
+            ];
+        }
+    }
+
     pub fn generate_sources(mut self, dest_path: &Path) -> Result<(), Exit> {
         let source_build = self.remove_source();
+        let (compression, compression_level) = (self.compression, self.compression_level);
 
         if source_build.is_none() {
             println!(
@@ -101,37 +414,69 @@ impl BuildsDescription {
             })
             .transpose()?
             .unwrap_or_default();
-        let source_features = source_build.map(|s| s.features).unwrap_or_default();
-        let source_features_string = source_features.join(", ");
+        let (source_features, source_cpus) = source_build
+            .map(|s| (s.features, s.cpus))
+            .unwrap_or_default();
 
         let out_dir_env = std::env::var_os("OUT_DIR").ok_or_else(|| {
             proc_exit::sysexits::SOFTWARE_ERR.with_message("Missing OUT_DIR environment variable")
         })?;
         let out_dir = Path::new(&out_dir_env);
 
-        let cargo_arch = std::env::var("CARGO_CFG_TARGET_ARCH").map_err(|_| {
-            proc_exit::sysexits::CONFIG_ERR
-                .with_message("Failed to get target's architecture from cargo")
+        let targets = self
+            .builds
+            .iter()
+            .map(|build| {
+                std::fs::read(&build.path).map_err(|_| {
+                    proc_exit::sysexits::IO_ERR
+                        .with_message(format!("Failed to read build {}", build.path.display()))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pairs = self.delta_layout.pairs(targets.len());
+        let mut patches = self.encode_all(out_dir, &source, &targets, &pairs)?;
+        let parents = self.delta_layout.parents(targets.len(), &patches);
+
+        let report_path = out_dir.join("delta_report.json");
+        let report = DeltaReport::new(self.delta_layout, &patches, &parents);
+        let report = serde_json::to_vec(&report).map_err(|_| {
+            proc_exit::sysexits::SOFTWARE_ERR.with_message("Failed to encode the delta report")
+        })?;
+        std::fs::write(&report_path, report).map_err(|_| {
+            proc_exit::sysexits::IO_ERR.with_message(format!(
+                "Failed to write the delta report {}",
+                report_path.display(),
+            ))
         })?;
-        let arch = cargo_arch.trim_end_matches("_64");
-        let is_feature_detected = format_ident!("is_{arch}_feature_detected");
 
+        let rules = self.rules_tokens();
+        let mut deltas = BTreeSet::new();
         let patches = self
             .builds
             .into_iter()
+            .zip(parents)
             .enumerate()
-            .map(|(i, build)| {
-                let target = std::fs::read(&build.path).map_err(|_| {
-                    proc_exit::sysexits::IO_ERR
-                        .with_message(format!("Failed to read build {}", build.path.display()))
+            .map(|(i, (build, parent))| {
+                let patch = patches.remove(&(parent, i)).ok_or_else(|| {
+                    proc_exit::sysexits::SOFTWARE_ERR
+                        .with_message(format!("Missing patch of build {}", build.path.display()))
                 })?;
-                let patch = gdelta_lz4(&source, &target)?;
+                deltas.insert(patch.delta);
+                let delta_tokens = match patch.delta {
+                    Delta::Gdelta => quote! { Delta::Gdelta },
+                    Delta::Bsdiff => quote! { Delta::Bsdiff },
+                };
+                let parent_tokens = match parent {
+                    Some(parent) => quote! { &PATCHES[#parent] },
+                    None => quote! { &SOURCE },
+                };
                 let features = build.features;
-                let features_string = features.join(", ");
+                let cpus = build.cpus;
 
                 let patch_filename = format!("patch_{i}.bin");
                 let patch_path = out_dir.join(&patch_filename);
-                std::fs::write(&patch_path, &patch).map_err(|_| {
+                std::fs::write(&patch_path, &patch.compressed).map_err(|_| {
                     proc_exit::sysexits::IO_ERR.with_message(format!(
                         "Failed to write patch file {}",
                         patch_path.display(),
@@ -140,42 +485,75 @@ impl BuildsDescription {
 
                 Ok(quote! {
                     Build {
-                        compressed: include_bytes!(concat!(env!("OUT_DIR"), "/", #patch_filename)),
-                        all_features_supported: || true #(&& #is_feature_detected!(#features))*,
-                        #[cfg(any(test, feature = "debug"))]
-                        features: #features_string,
-                        source: Some(&SOURCE),
+                        data: Data::Compressed(include_bytes!(concat!(env!("OUT_DIR"), "/", #patch_filename))),
+                        features: &[#(#features),*],
+                        cpus: &[#(#cpus),*],
+                        source: Some((#parent_tokens, #delta_tokens)),
                     }
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
 
-        let source_compressed = compress(&source[..])?;
-
+        // The uncompressed source is page-aligned, so that the runner can copy it from its executable
         let source_filename = "source.bin";
         let source_file = out_dir.join(source_filename);
-        std::fs::write(&source_file, &source_compressed).map_err(|_| {
+        let (source_data, source_compressed, source_static) = if self.uncompressed_source {
+            let len = source.len();
+            let source_static = quote! {
+                static SOURCE_DATA: PageAligned<[u8; #len]> =
+                    PageAligned(*include_bytes!(concat!(env!("OUT_DIR"), "/", #source_filename)));
+            };
+
+            (
+                source,
+                quote! { Data::Stored(&SOURCE_DATA.0) },
+                source_static,
+            )
+        } else {
+            let compressed = quote! {
+                Data::Compressed(include_bytes!(concat!(env!("OUT_DIR"), "/", #source_filename)))
+            };
+
+            let source = compression
+                .compress(compression_level, &source)
+                .map_err(|_| {
+                    proc_exit::sysexits::IO_ERR.with_message("Failed to compress the source build")
+                })?;
+
+            (source, compressed, quote! {})
+        };
+        std::fs::write(&source_file, &source_data).map_err(|_| {
             proc_exit::sysexits::IO_ERR.with_message(format!(
-                "Failed to write compressed source file {}",
+                "Failed to write source file {}",
                 source_file.display(),
             ))
         })?;
 
+        // Only the decoders of the patches are compiled in the runner
+        for delta in deltas {
+            println!("cargo:rustc-cfg=multivers_delta=\"{}\"", delta.name());
+        }
+
         let n_builds = patches.len();
+        let compression = match compression {
+            Compression::Lz4 => quote! { Compression::Lz4 },
+            Compression::Zstd => quote! { Compression::Zstd },
+            Compression::Xz => quote! { Compression::Xz },
+            Compression::None => quote! { Compression::None },
+        };
         let tokens = quote! {
-            #[allow(unused)]
-            use std::arch::#is_feature_detected;
-
-            const SOURCE: Build<'_> = Build {
-                compressed: include_bytes!(concat!(env!("OUT_DIR"), "/", #source_filename)),
-                all_features_supported: || true #(&& #is_feature_detected!(#source_features))*,
-                #[cfg(any(test, feature = "debug"))]
-                features: #source_features_string,
+            const COMPRESSION: Compression = #compression;
+            #source_static
+            static SOURCE: Build<'static> = Build {
+                data: #source_compressed,
+                features: &[#(#source_features),*],
+                cpus: &[#(#source_cpus),*],
                 source: None,
             };
-            const PATCHES: [Build<'_>; #n_builds] = [
+            static PATCHES: [Build<'static>; #n_builds] = [
                 #(#patches),*
             ];
+            #rules
         };
 
         std::fs::write(dest_path, tokens.to_string()).map_err(|_| {
@@ -189,34 +567,62 @@ impl BuildsDescription {
     }
 }
 
-fn compress(mut reader: impl Read) -> Result<Vec<u8>, Exit> {
-    let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
-    std::io::copy(&mut reader, &mut encoder)
-        .map_err(|_| proc_exit::sysexits::IO_ERR.with_message("Failed to compress data"))?;
-    encoder
-        .finish()
-        .map_err(|_| proc_exit::sysexits::IO_ERR.with_message("Failed to compress data"))
-}
-
-fn gdelta_lz4(source: &[u8], target: &[u8]) -> Result<Vec<u8>, Exit> {
-    let patch = gdelta::encode(target, source)
-        .map_err(|_| proc_exit::sysexits::IO_ERR.with_message("Failed to generate a patch"))?;
-
-    compress(&patch[..])
-}
-
 fn main() -> Result<(), Exit> {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(multivers_delta, values(\"gdelta\", \"bsdiff\"))");
 
     let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| {
         proc_exit::sysexits::SOFTWARE_ERR.with_message("Missing OUT_DIR environment variable")
     })?;
     let dest_path = Path::new(&out_dir).join("builds.rs");
 
-    let builds = BuildsDescription::from_env()
+    let has_detection = std_detect::generate(&Path::new(&out_dir).join("std_detect.rs"))?;
+
+    if std::env::var_os("CARGO_FEATURE_STUB").is_some() {
+        for delta in [Delta::Gdelta, Delta::Bsdiff] {
+            println!("cargo:rustc-cfg=multivers_delta=\"{}\"", delta.name());
+        }
+
+        return Ok(());
+    }
+
+    let mut builds = BuildsDescription::from_env()
         .transpose()?
         .unwrap_or_default();
 
+    if !has_detection && builds.builds.iter().any(|build| !build.features.is_empty()) {
+        let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+
+        return Err(proc_exit::sysexits::CONFIG_ERR.with_message(format!(
+            "CPU features cannot be detected at runtime on the `{arch}` architecture, \
+            so the runner cannot select a build requiring CPU features \
+            (use the nightly toolchain and the `nightly` feature of the runner if it can detect them)"
+        )));
+    }
+
+    if builds.external_variants.is_some() {
+        if std::env::var_os("CARGO_FEATURE_EXTERNAL").is_none() {
+            return Err(proc_exit::sysexits::CONFIG_ERR.with_message(
+                "The builds are stored beside the runner, so the `external` feature of the runner must be enabled",
+            ));
+        }
+
+        return builds.generate_external_sources(&dest_path);
+    }
+
+    if let Some(feature) = builds.compression.runner_feature()
+        && std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_none()
+    {
+        if !builds.builds.is_empty() {
+            return Err(proc_exit::sysexits::CONFIG_ERR.with_message(format!(
+                "The builds are compressed with `{feature}`, so the `{feature}` feature of the runner must be enabled"
+            )));
+        }
+
+        // Nothing is compressed, so the runner does not need the decoder
+        builds.compression = Compression::None;
+    }
+
     builds.generate_sources(&dest_path)?;
 
     Ok(())
@@ -0,0 +1,743 @@
+// The CPU features the runner can detect on each architecture.
+//
+// This file is shared by the build script of the runner, which generates the detection with the `std::arch` macros,
+// by the backends of the runner that read the `CPUID` bits and the names the Linux kernel gives to the features,
+// and by `cargo multivers pack`, which checks that the runner can detect the features of the binaries it packs.
+// It is included with `include!` by the runner and by `cargo-multivers`, so it cannot have inner attributes or doc comments.
+// The architectures are named as in `CARGO_CFG_TARGET_ARCH` (and `std::env::consts::ARCH`).
+use Stability::{Stable, Unstable};
+
+/// Returns true if the runner can detect a CPU feature on a target
+///
+/// The features only detected by unstable `std::arch` macros are only detectable
+/// if the runner is built with its `nightly` feature (and a nightly toolchain).
+pub fn is_detectable(arch: &str, os: &str, feature: &str, nightly: bool) -> bool {
+    let std = std_detection(arch).is_some_and(|(_, features)| {
+        features.iter().any(|(name, stability)| {
+            *name == feature && (nightly || matches!(stability, Stable(_)))
+        })
+    });
+    let cpuid = matches!(arch, "x86" | "x86_64")
+        && (CPUID_FEATURES.iter().any(|f| f.name == feature)
+            || feature
+                .strip_prefix("avx10.")
+                .is_some_and(|version| version.parse::<u32>().is_ok()));
+    // `/proc/cpuinfo`, or the auxiliary vector on PowerPC, which does not list the features in `/proc/cpuinfo`
+    let kernel = os == "linux"
+        && (!cpuinfo_features_key(arch).is_empty() || arch == "powerpc64")
+        && kernel_names(arch, feature).is_some();
+
+    std || cpuid || kernel
+}
+
+/// The stability of a feature in a `std::arch::is_{arch}_feature_detected!` macro
+#[derive(Clone, Copy)]
+pub enum Stability {
+    /// Stable since the given minor version of Rust (e.g., `27` for Rust 1.27)
+    Stable(u32),
+    /// Only available on nightly, with the given library feature
+    ///
+    /// Each library feature must also be listed in `multivers-runner/src/lib.rs`.
+    Unstable(&'static str),
+}
+
+/// Returns the name of the `std::arch` macro that detects CPU features on an architecture (as in `CARGO_CFG_TARGET_ARCH`)
+/// and the features it can detect
+pub fn std_detection(arch: &str) -> Option<(&'static str, &'static [(&'static str, Stability)])> {
+    let detection = match arch {
+        "x86" | "x86_64" => ("is_x86_feature_detected", STD_X86_FEATURES),
+        "aarch64" | "arm64ec" => ("is_aarch64_feature_detected", STD_AARCH64_FEATURES),
+        "arm" => ("is_arm_feature_detected", STD_ARM_FEATURES),
+        "riscv32" | "riscv64" => ("is_riscv_feature_detected", STD_RISCV_FEATURES),
+        "powerpc" => ("is_powerpc_feature_detected", STD_POWERPC_FEATURES),
+        "powerpc64" => ("is_powerpc64_feature_detected", STD_POWERPC_FEATURES),
+        "mips" => ("is_mips_feature_detected", STD_MIPS_FEATURES),
+        "mips64" => ("is_mips64_feature_detected", STD_MIPS_FEATURES),
+        "loongarch64" => ("is_loongarch_feature_detected", STD_LOONGARCH_FEATURES),
+        "s390x" => ("is_s390x_feature_detected", STD_S390X_FEATURES),
+        _ => return None,
+    };
+
+    Some(detection)
+}
+
+/// CPU features that `std::arch::is_x86_feature_detected!` can detect
+pub const STD_X86_FEATURES: &[(&str, Stability)] = &[
+    ("aes", Stable(27)),
+    ("pclmulqdq", Stable(27)),
+    ("rdrand", Stable(27)),
+    ("rdseed", Stable(27)),
+    ("tsc", Stable(27)),
+    ("mmx", Stable(27)),
+    ("sse", Stable(27)),
+    ("sse2", Stable(27)),
+    ("sse3", Stable(27)),
+    ("ssse3", Stable(27)),
+    ("sse4.1", Stable(27)),
+    ("sse4.2", Stable(27)),
+    ("sse4a", Stable(27)),
+    ("sha", Stable(27)),
+    ("avx", Stable(27)),
+    ("avx2", Stable(27)),
+    ("sha512", Stable(89)),
+    ("sm3", Stable(89)),
+    ("sm4", Stable(89)),
+    ("avx512f", Stable(27)),
+    ("avx512cd", Stable(27)),
+    ("avx512bw", Stable(27)),
+    ("avx512dq", Stable(27)),
+    ("avx512vl", Stable(27)),
+    ("avx512ifma", Stable(27)),
+    ("avx512vbmi", Stable(27)),
+    ("avx512vpopcntdq", Stable(27)),
+    ("avx512vbmi2", Stable(27)),
+    ("gfni", Stable(27)),
+    ("vaes", Stable(27)),
+    ("vpclmulqdq", Stable(27)),
+    ("avx512vnni", Stable(27)),
+    ("avx512bitalg", Stable(27)),
+    ("avx512bf16", Stable(27)),
+    ("avx512vp2intersect", Stable(27)),
+    ("avx512fp16", Stable(27)),
+    ("avxifma", Stable(89)),
+    ("avxneconvert", Stable(89)),
+    ("avxvnni", Stable(89)),
+    ("avxvnniint16", Stable(89)),
+    ("avxvnniint8", Stable(89)),
+    ("amx-tile", Unstable("x86_amx_intrinsics")),
+    ("amx-int8", Unstable("x86_amx_intrinsics")),
+    ("amx-bf16", Unstable("x86_amx_intrinsics")),
+    ("amx-fp16", Unstable("x86_amx_intrinsics")),
+    ("amx-complex", Unstable("x86_amx_intrinsics")),
+    ("amx-avx512", Unstable("x86_amx_intrinsics")),
+    ("amx-fp8", Unstable("x86_amx_intrinsics")),
+    ("amx-movrs", Unstable("x86_amx_intrinsics")),
+    ("amx-tf32", Unstable("x86_amx_intrinsics")),
+    ("apxf", Unstable("apx_target_feature")),
+    ("avx10.1", Unstable("avx10_target_feature")),
+    ("avx10.2", Unstable("avx10_target_feature")),
+    ("f16c", Stable(27)),
+    ("fma", Stable(27)),
+    ("bmi1", Stable(27)),
+    ("bmi2", Stable(27)),
+    ("lzcnt", Stable(27)),
+    ("tbm", Stable(27)),
+    ("popcnt", Stable(27)),
+    ("fxsr", Stable(27)),
+    ("xsave", Stable(27)),
+    ("xsaveopt", Stable(27)),
+    ("xsaves", Stable(27)),
+    ("xsavec", Stable(27)),
+    ("cmpxchg16b", Stable(27)),
+    ("kl", Stable(89)),
+    ("widekl", Stable(89)),
+    ("adx", Stable(33)),
+    ("rtm", Stable(27)),
+    ("movbe", Stable(67)),
+    ("movrs", Unstable("movrs_target_feature")),
+    ("ermsb", Stable(27)),
+    ("xop", Unstable("xop_target_feature")),
+];
+
+/// CPU features that `std::arch::is_aarch64_feature_detected!` can detect
+pub const STD_AARCH64_FEATURES: &[(&str, Stability)] = &[
+    ("neon", Stable(60)),
+    ("pmull", Stable(60)),
+    ("fp", Stable(60)),
+    ("aes", Stable(60)),
+    ("bf16", Stable(60)),
+    ("bti", Stable(60)),
+    ("crc", Stable(60)),
+    ("cssc", Unstable("stdarch_aarch64_feature_detection")),
+    ("dit", Stable(60)),
+    ("dpb", Stable(60)),
+    ("dpb2", Stable(60)),
+    ("dotprod", Stable(60)),
+    ("ecv", Unstable("stdarch_aarch64_feature_detection")),
+    ("f32mm", Stable(60)),
+    ("f64mm", Stable(60)),
+    ("faminmax", Unstable("stdarch_aarch64_feature_detection")),
+    ("fcma", Stable(60)),
+    ("fhm", Stable(60)),
+    ("flagm", Stable(60)),
+    ("flagm2", Unstable("stdarch_aarch64_feature_detection")),
+    ("fp16", Stable(60)),
+    ("fp8", Unstable("stdarch_aarch64_feature_detection")),
+    ("fp8dot2", Unstable("stdarch_aarch64_feature_detection")),
+    ("fp8dot4", Unstable("stdarch_aarch64_feature_detection")),
+    ("fp8fma", Unstable("stdarch_aarch64_feature_detection")),
+    ("fpmr", Unstable("stdarch_aarch64_feature_detection")),
+    ("frintts", Stable(60)),
+    ("hbc", Unstable("stdarch_aarch64_feature_detection")),
+    ("i8mm", Stable(60)),
+    ("jsconv", Stable(60)),
+    ("lse", Stable(60)),
+    ("lse128", Unstable("stdarch_aarch64_feature_detection")),
+    ("lse2", Stable(60)),
+    ("lut", Unstable("stdarch_aarch64_feature_detection")),
+    ("mops", Unstable("stdarch_aarch64_feature_detection")),
+    ("mte", Stable(60)),
+    ("paca", Stable(60)),
+    ("pacg", Stable(60)),
+    ("pauth-lr", Unstable("stdarch_aarch64_feature_detection")),
+    ("rand", Stable(60)),
+    ("rcpc", Stable(60)),
+    ("rcpc2", Stable(60)),
+    ("rcpc3", Unstable("stdarch_aarch64_feature_detection")),
+    ("rdm", Stable(60)),
+    ("sb", Stable(60)),
+    ("sha2", Stable(60)),
+    ("sha3", Stable(60)),
+    ("sm4", Stable(60)),
+    ("sme", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme2", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme2p1", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-b16b16", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-f16f16", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-f64f64", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-f8f16", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-f8f32", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-fa64", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-i16i64", Unstable("stdarch_aarch64_feature_detection")),
+    ("sme-lutv2", Unstable("stdarch_aarch64_feature_detection")),
+    ("ssbs", Stable(60)),
+    (
+        "ssve-fp8dot2",
+        Unstable("stdarch_aarch64_feature_detection"),
+    ),
+    (
+        "ssve-fp8dot4",
+        Unstable("stdarch_aarch64_feature_detection"),
+    ),
+    ("ssve-fp8fma", Unstable("stdarch_aarch64_feature_detection")),
+    ("sve", Stable(60)),
+    ("sve2", Stable(60)),
+    ("sve2p1", Unstable("stdarch_aarch64_feature_detection")),
+    ("sve2-aes", Stable(60)),
+    ("sve-b16b16", Unstable("stdarch_aarch64_feature_detection")),
+    ("sve2-bitperm", Stable(60)),
+    ("sve2-sha3", Stable(60)),
+    ("sve2-sm4", Stable(60)),
+    ("tme", Stable(60)),
+    ("wfxt", Unstable("stdarch_aarch64_feature_detection")),
+];
+
+/// CPU features that `std::arch::is_arm_feature_detected!` can detect
+pub const STD_ARM_FEATURES: &[(&str, Stability)] = &[
+    ("neon", Unstable("stdarch_arm_feature_detection")),
+    ("pmull", Unstable("stdarch_arm_feature_detection")),
+    ("crc", Unstable("stdarch_arm_feature_detection")),
+    ("aes", Unstable("stdarch_arm_feature_detection")),
+    ("sha2", Unstable("stdarch_arm_feature_detection")),
+    ("i8mm", Unstable("stdarch_arm_feature_detection")),
+    ("dotprod", Unstable("stdarch_arm_feature_detection")),
+];
+
+/// CPU features that `std::arch::is_riscv_feature_detected!` can detect
+pub const STD_RISCV_FEATURES: &[(&str, Stability)] = &[
+    ("rv32i", Unstable("stdarch_riscv_feature_detection")),
+    ("rv32e", Unstable("stdarch_riscv_feature_detection")),
+    ("rv64i", Unstable("stdarch_riscv_feature_detection")),
+    ("rv128i", Unstable("stdarch_riscv_feature_detection")),
+    (
+        "unaligned-scalar-mem",
+        Unstable("stdarch_riscv_feature_detection"),
+    ),
+    (
+        "unaligned-vector-mem",
+        Unstable("stdarch_riscv_feature_detection"),
+    ),
+    ("zicsr", Stable(94)),
+    ("zicntr", Stable(94)),
+    ("zihpm", Stable(94)),
+    ("zifencei", Stable(94)),
+    ("zihintntl", Stable(94)),
+    ("zihintpause", Stable(94)),
+    ("zimop", Stable(94)),
+    ("zicbom", Stable(94)),
+    ("zicboz", Stable(94)),
+    ("zicond", Stable(94)),
+    ("m", Stable(78)),
+    ("a", Stable(78)),
+    ("zalrsc", Stable(94)),
+    ("zaamo", Stable(94)),
+    ("zawrs", Stable(94)),
+    ("zabha", Stable(94)),
+    ("zacas", Stable(94)),
+    ("zam", Unstable("stdarch_riscv_feature_detection")),
+    ("ztso", Stable(94)),
+    ("f", Unstable("stdarch_riscv_feature_detection")),
+    ("d", Unstable("stdarch_riscv_feature_detection")),
+    ("q", Unstable("stdarch_riscv_feature_detection")),
+    ("zfh", Unstable("stdarch_riscv_feature_detection")),
+    ("zfhmin", Unstable("stdarch_riscv_feature_detection")),
+    ("zfa", Unstable("stdarch_riscv_feature_detection")),
+    ("zfbfmin", Unstable("stdarch_riscv_feature_detection")),
+    ("zfinx", Unstable("stdarch_riscv_feature_detection")),
+    ("zdinx", Unstable("stdarch_riscv_feature_detection")),
+    ("zhinx", Unstable("stdarch_riscv_feature_detection")),
+    ("zhinxmin", Unstable("stdarch_riscv_feature_detection")),
+    ("c", Stable(78)),
+    ("zca", Stable(94)),
+    ("zcf", Unstable("stdarch_riscv_feature_detection")),
+    ("zcd", Unstable("stdarch_riscv_feature_detection")),
+    ("zcb", Stable(94)),
+    ("zcmop", Stable(94)),
+    ("b", Stable(94)),
+    ("zba", Stable(78)),
+    ("zbb", Stable(78)),
+    ("zbc", Stable(78)),
+    ("zbs", Stable(78)),
+    ("zbkb", Stable(78)),
+    ("zbkc", Stable(78)),
+    ("zbkx", Stable(78)),
+    ("zknd", Stable(78)),
+    ("zkne", Stable(78)),
+    ("zknh", Stable(78)),
+    ("zksed", Stable(78)),
+    ("zksh", Stable(78)),
+    ("zkr", Stable(78)),
+    ("zkn", Stable(78)),
+    ("zks", Stable(78)),
+    ("zk", Stable(78)),
+    ("zkt", Stable(78)),
+    ("v", Unstable("stdarch_riscv_feature_detection")),
+    ("zve32x", Unstable("stdarch_riscv_feature_detection")),
+    ("zve32f", Unstable("stdarch_riscv_feature_detection")),
+    ("zve64x", Unstable("stdarch_riscv_feature_detection")),
+    ("zve64f", Unstable("stdarch_riscv_feature_detection")),
+    ("zve64d", Unstable("stdarch_riscv_feature_detection")),
+    ("zvfh", Unstable("stdarch_riscv_feature_detection")),
+    ("zvfhmin", Unstable("stdarch_riscv_feature_detection")),
+    ("zvfbfmin", Unstable("stdarch_riscv_feature_detection")),
+    ("zvfbfwma", Unstable("stdarch_riscv_feature_detection")),
+    ("zvbb", Unstable("stdarch_riscv_feature_detection")),
+    ("zvbc", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkb", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkg", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkned", Unstable("stdarch_riscv_feature_detection")),
+    ("zvknha", Unstable("stdarch_riscv_feature_detection")),
+    ("zvknhb", Unstable("stdarch_riscv_feature_detection")),
+    ("zvksed", Unstable("stdarch_riscv_feature_detection")),
+    ("zvksh", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkn", Unstable("stdarch_riscv_feature_detection")),
+    ("zvknc", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkng", Unstable("stdarch_riscv_feature_detection")),
+    ("zvks", Unstable("stdarch_riscv_feature_detection")),
+    ("zvksc", Unstable("stdarch_riscv_feature_detection")),
+    ("zvksg", Unstable("stdarch_riscv_feature_detection")),
+    ("zvkt", Unstable("stdarch_riscv_feature_detection")),
+    ("j", Unstable("stdarch_riscv_feature_detection")),
+    ("p", Unstable("stdarch_riscv_feature_detection")),
+];
+
+/// CPU features that `std::arch::is_powerpc_feature_detected!` and `std::arch::is_powerpc64_feature_detected!` can detect
+pub const STD_POWERPC_FEATURES: &[(&str, Stability)] = &[
+    ("altivec", Unstable("stdarch_powerpc_feature_detection")),
+    ("vsx", Unstable("stdarch_powerpc_feature_detection")),
+    ("power8", Unstable("stdarch_powerpc_feature_detection")),
+    (
+        "power8-altivec",
+        Unstable("stdarch_powerpc_feature_detection"),
+    ),
+    (
+        "power8-vector",
+        Unstable("stdarch_powerpc_feature_detection"),
+    ),
+    (
+        "power8-crypto",
+        Unstable("stdarch_powerpc_feature_detection"),
+    ),
+    ("power9", Unstable("stdarch_powerpc_feature_detection")),
+    (
+        "power9-altivec",
+        Unstable("stdarch_powerpc_feature_detection"),
+    ),
+    (
+        "power9-vector",
+        Unstable("stdarch_powerpc_feature_detection"),
+    ),
+];
+
+/// CPU features that `std::arch::is_mips_feature_detected!` and `std::arch::is_mips64_feature_detected!` can detect
+pub const STD_MIPS_FEATURES: &[(&str, Stability)] =
+    &[("msa", Unstable("stdarch_mips_feature_detection"))];
+
+/// CPU features that `std::arch::is_loongarch_feature_detected!` can detect
+pub const STD_LOONGARCH_FEATURES: &[(&str, Stability)] = &[
+    ("32s", Unstable("stdarch_loongarch_feature_detection")),
+    ("f", Stable(89)),
+    ("d", Stable(89)),
+    ("frecipe", Stable(89)),
+    ("div32", Stable(97)),
+    ("lsx", Stable(89)),
+    ("lasx", Stable(89)),
+    ("lam-bh", Stable(97)),
+    ("lamcas", Stable(97)),
+    ("ld-seq-sa", Stable(97)),
+    ("scq", Stable(97)),
+    ("lbt", Stable(89)),
+    ("lvz", Stable(89)),
+    ("ual", Unstable("stdarch_loongarch_feature_detection")),
+];
+
+/// CPU features that `std::arch::is_s390x_feature_detected!` can detect
+pub const STD_S390X_FEATURES: &[(&str, Stability)] = &[
+    ("concurrent-functions", Unstable("s390x_target_feature")),
+    ("deflate-conversion", Unstable("s390x_target_feature")),
+    ("enhanced-sort", Unstable("s390x_target_feature")),
+    ("guarded-storage", Unstable("s390x_target_feature")),
+    ("high-word", Unstable("s390x_target_feature")),
+    (
+        "message-security-assist-extension3",
+        Unstable("s390x_target_feature"),
+    ),
+    (
+        "message-security-assist-extension4",
+        Unstable("s390x_target_feature"),
+    ),
+    (
+        "message-security-assist-extension5",
+        Unstable("s390x_target_feature"),
+    ),
+    (
+        "message-security-assist-extension8",
+        Unstable("s390x_target_feature"),
+    ),
+    (
+        "message-security-assist-extension9",
+        Unstable("s390x_target_feature"),
+    ),
+    (
+        "message-security-assist-extension12",
+        Unstable("s390x_target_feature"),
+    ),
+    ("miscellaneous-extensions-2", Stable(93)),
+    ("miscellaneous-extensions-3", Stable(93)),
+    ("miscellaneous-extensions-4", Stable(93)),
+    ("nnp-assist", Stable(93)),
+    ("transactional-execution", Unstable("s390x_target_feature")),
+    ("vector", Stable(93)),
+    ("vector-enhancements-1", Stable(93)),
+    ("vector-enhancements-2", Stable(93)),
+    ("vector-enhancements-3", Stable(93)),
+    ("vector-packed-decimal", Stable(93)),
+    ("vector-packed-decimal-enhancement", Stable(93)),
+    ("vector-packed-decimal-enhancement-2", Stable(93)),
+    ("vector-packed-decimal-enhancement-3", Stable(93)),
+];
+
+/// A register returned by `CPUID`
+#[derive(Clone, Copy)]
+pub enum CpuidRegister {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// The register state that the OS must save and restore (as reported by `XCR0`) for a feature to be usable
+#[derive(Clone, Copy)]
+pub enum OsState {
+    /// No additional state is required
+    None,
+    /// SSE and AVX (YMM) states
+    Avx,
+    /// SSE, AVX, and AVX-512 (opmask, ZMM_Hi256, and Hi16_ZMM) states
+    Avx512,
+    /// AMX (XTILECFG and XTILEDATA) states
+    Amx,
+    /// APX (extended GPRs) state
+    Apx,
+}
+
+impl OsState {
+    /// Returns the bits of `XCR0` that must be set
+    pub const fn xcr0_mask(self) -> u64 {
+        match self {
+            Self::None => 0,
+            Self::Avx => 0x6,
+            Self::Avx512 => 0xe6,
+            Self::Amx => 0x6_0000,
+            Self::Apx => 0x8_0000,
+        }
+    }
+}
+
+/// A feature reported by a bit of a `CPUID` leaf
+pub struct CpuidFeature {
+    pub name: &'static str,
+    pub leaf: u32,
+    pub sub_leaf: u32,
+    pub register: CpuidRegister,
+    pub bit: u32,
+    pub os_state: OsState,
+}
+
+const fn cpuid_feature(
+    name: &'static str,
+    leaf: u32,
+    sub_leaf: u32,
+    register: CpuidRegister,
+    bit: u32,
+    os_state: OsState,
+) -> CpuidFeature {
+    CpuidFeature {
+        name,
+        leaf,
+        sub_leaf,
+        register,
+        bit,
+        os_state,
+    }
+}
+
+/// The features named as in `rustc --print target-features`, with the `CPUID` bit that reports them
+/// (see the Intel® 64 and IA-32 Architectures Software Developer's Manual and the AMD64 Architecture Programmer's Manual)
+#[rustfmt::skip]
+pub const CPUID_FEATURES: &[CpuidFeature] = {
+    use OsState::{Amx, Apx, Avx, Avx512, None};
+    use CpuidRegister::{Eax, Ebx, Ecx, Edx};
+
+    &[
+        cpuid_feature("x87", 0x1, 0, Edx, 0, None),
+        cpuid_feature("tsc", 0x1, 0, Edx, 4, None),
+        cpuid_feature("mmx", 0x1, 0, Edx, 23, None),
+        cpuid_feature("fxsr", 0x1, 0, Edx, 24, None),
+        cpuid_feature("sse", 0x1, 0, Edx, 25, None),
+        cpuid_feature("sse2", 0x1, 0, Edx, 26, None),
+        cpuid_feature("sse3", 0x1, 0, Ecx, 0, None),
+        cpuid_feature("pclmulqdq", 0x1, 0, Ecx, 1, None),
+        cpuid_feature("ssse3", 0x1, 0, Ecx, 9, None),
+        cpuid_feature("fma", 0x1, 0, Ecx, 12, Avx),
+        cpuid_feature("cmpxchg16b", 0x1, 0, Ecx, 13, None),
+        cpuid_feature("sse4.1", 0x1, 0, Ecx, 19, None),
+        cpuid_feature("sse4.2", 0x1, 0, Ecx, 20, None),
+        cpuid_feature("movbe", 0x1, 0, Ecx, 22, None),
+        cpuid_feature("popcnt", 0x1, 0, Ecx, 23, None),
+        cpuid_feature("aes", 0x1, 0, Ecx, 25, None),
+        cpuid_feature("xsave", 0x1, 0, Ecx, 26, None),
+        cpuid_feature("avx", 0x1, 0, Ecx, 28, Avx),
+        cpuid_feature("f16c", 0x1, 0, Ecx, 29, Avx),
+        cpuid_feature("rdrand", 0x1, 0, Ecx, 30, None),
+        cpuid_feature("bmi1", 0x7, 0, Ebx, 3, None),
+        cpuid_feature("avx2", 0x7, 0, Ebx, 5, Avx),
+        cpuid_feature("bmi2", 0x7, 0, Ebx, 8, None),
+        cpuid_feature("ermsb", 0x7, 0, Ebx, 9, None),
+        cpuid_feature("rtm", 0x7, 0, Ebx, 11, None),
+        cpuid_feature("avx512f", 0x7, 0, Ebx, 16, Avx512),
+        cpuid_feature("avx512dq", 0x7, 0, Ebx, 17, Avx512),
+        cpuid_feature("rdseed", 0x7, 0, Ebx, 18, None),
+        cpuid_feature("adx", 0x7, 0, Ebx, 19, None),
+        cpuid_feature("avx512ifma", 0x7, 0, Ebx, 21, Avx512),
+        cpuid_feature("avx512cd", 0x7, 0, Ebx, 28, Avx512),
+        cpuid_feature("sha", 0x7, 0, Ebx, 29, None),
+        cpuid_feature("avx512bw", 0x7, 0, Ebx, 30, Avx512),
+        cpuid_feature("avx512vl", 0x7, 0, Ebx, 31, Avx512),
+        cpuid_feature("avx512vbmi", 0x7, 0, Ecx, 1, Avx512),
+        cpuid_feature("avx512vbmi2", 0x7, 0, Ecx, 6, Avx512),
+        cpuid_feature("gfni", 0x7, 0, Ecx, 8, None),
+        cpuid_feature("vaes", 0x7, 0, Ecx, 9, Avx),
+        cpuid_feature("vpclmulqdq", 0x7, 0, Ecx, 10, Avx),
+        cpuid_feature("avx512vnni", 0x7, 0, Ecx, 11, Avx512),
+        cpuid_feature("avx512bitalg", 0x7, 0, Ecx, 12, Avx512),
+        cpuid_feature("avx512vpopcntdq", 0x7, 0, Ecx, 14, Avx512),
+        cpuid_feature("kl", 0x7, 0, Ecx, 23, None),
+        cpuid_feature("avx512vp2intersect", 0x7, 0, Edx, 8, Avx512),
+        cpuid_feature("amx-bf16", 0x7, 0, Edx, 22, Amx),
+        cpuid_feature("avx512fp16", 0x7, 0, Edx, 23, Avx512),
+        cpuid_feature("amx-tile", 0x7, 0, Edx, 24, Amx),
+        cpuid_feature("amx-int8", 0x7, 0, Edx, 25, Amx),
+        cpuid_feature("sha512", 0x7, 1, Eax, 0, Avx),
+        cpuid_feature("sm3", 0x7, 1, Eax, 1, Avx),
+        cpuid_feature("sm4", 0x7, 1, Eax, 2, Avx),
+        cpuid_feature("avxvnni", 0x7, 1, Eax, 4, Avx),
+        cpuid_feature("avx512bf16", 0x7, 1, Eax, 5, Avx512),
+        cpuid_feature("amx-fp16", 0x7, 1, Eax, 21, Amx),
+        cpuid_feature("avxifma", 0x7, 1, Eax, 23, Avx),
+        cpuid_feature("movrs", 0x7, 1, Eax, 31, None),
+        cpuid_feature("avxvnniint8", 0x7, 1, Edx, 4, Avx),
+        cpuid_feature("avxneconvert", 0x7, 1, Edx, 5, Avx),
+        cpuid_feature("amx-complex", 0x7, 1, Edx, 8, Amx),
+        cpuid_feature("avxvnniint16", 0x7, 1, Edx, 10, Avx),
+        cpuid_feature("apxf", 0x7, 1, Edx, 21, Apx),
+        cpuid_feature("xsaveopt", 0xd, 1, Eax, 0, None),
+        cpuid_feature("xsavec", 0xd, 1, Eax, 1, None),
+        cpuid_feature("xsaves", 0xd, 1, Eax, 3, None),
+        cpuid_feature("widekl", 0x19, 0, Ebx, 2, None),
+        cpuid_feature("amx-fp8", 0x1e, 1, Eax, 4, Amx),
+        cpuid_feature("amx-tf32", 0x1e, 1, Eax, 6, Amx),
+        cpuid_feature("amx-avx512", 0x1e, 1, Eax, 7, Amx),
+        cpuid_feature("amx-movrs", 0x1e, 1, Eax, 8, Amx),
+        cpuid_feature("lahfsahf", 0x8000_0001, 0, Ecx, 0, None),
+        cpuid_feature("lzcnt", 0x8000_0001, 0, Ecx, 5, None),
+        cpuid_feature("sse4a", 0x8000_0001, 0, Ecx, 6, None),
+        cpuid_feature("prfchw", 0x8000_0001, 0, Ecx, 8, None),
+        cpuid_feature("xop", 0x8000_0001, 0, Ecx, 11, Avx),
+        cpuid_feature("fma4", 0x8000_0001, 0, Ecx, 16, Avx),
+        cpuid_feature("tbm", 0x8000_0001, 0, Ecx, 21, None),
+    ]
+};
+
+/// Returns the key of the line of `/proc/cpuinfo` that lists the CPU features, or an empty string if there is none
+pub fn cpuinfo_features_key(arch: &str) -> &'static str {
+    match arch {
+        "x86" | "x86_64" => "flags",
+        "aarch64" => "Features",
+        "riscv32" | "riscv64" => "isa",
+        _ => "",
+    }
+}
+
+/// Returns the names the Linux kernel uses for a feature named as in `rustc --print target-features`,
+/// or `None` if it does not report this feature
+pub fn kernel_names<'a>(arch: &str, feature: &'a str) -> Option<Vec<&'a str>> {
+    match arch {
+        "x86" | "x86_64" => x86_kernel_names(feature),
+        "aarch64" => aarch64_kernel_names(feature),
+        // Extensions have the same name, other features (e.g., `unaligned-scalar-mem`) cannot be detected
+        "riscv32" | "riscv64" => is_feature_name(feature).then(|| vec![feature]),
+        "powerpc" | "powerpc64" => powerpc_kernel_names(feature),
+        _ => None,
+    }
+}
+
+/// Returns the names the Linux kernel uses on x86 for a feature
+fn x86_kernel_names(feature: &str) -> Option<Vec<&str>> {
+    let names = match feature {
+        "sse3" => vec!["pni"],
+        "cmpxchg16b" => vec!["cx16"],
+        "lahfsahf" => vec!["lahf_lm"],
+        "lzcnt" => vec!["abm"],
+        "prfchw" => vec!["3dnowprefetch"],
+        "sha" => vec!["sha_ni"],
+        "ermsb" => vec!["erms"],
+        "x87" => vec!["fpu"],
+        "avxvnni" => vec!["avx_vnni"],
+        "avxvnniint8" => vec!["avx_vnni_int8"],
+        "avxvnniint16" => vec!["avx_vnni_int16"],
+        "avxneconvert" => vec!["avx_ne_convert"],
+        "avxifma" => vec!["avx_ifma"],
+        "kl" => vec!["aeskle"],
+        "widekl" => vec!["aeskle", "widekl"],
+        "sse4.1" => vec!["sse4_1"],
+        "sse4.2" => vec!["sse4_2"],
+        "avx512bitalg" => vec!["avx512_bitalg"],
+        "avx512bf16" => vec!["avx512_bf16"],
+        "avx512fp16" => vec!["avx512_fp16"],
+        "avx512vbmi2" => vec!["avx512_vbmi2"],
+        "avx512vnni" => vec!["avx512_vnni"],
+        "avx512vp2intersect" => vec!["avx512_vp2intersect"],
+        "avx512vpopcntdq" => vec!["avx512_vpopcntdq"],
+        "amx-bf16" => vec!["amx_bf16"],
+        "amx-complex" => vec!["amx_complex"],
+        "amx-fp16" => vec!["amx_fp16"],
+        "amx-int8" => vec!["amx_int8"],
+        "amx-tile" => vec!["amx_tile"],
+        // Most features have the same name
+        _ if is_feature_name(feature) => vec![feature],
+        _ => return None,
+    };
+
+    Some(names)
+}
+
+/// Returns the names the Linux kernel uses on AArch64 for a feature
+fn aarch64_kernel_names(feature: &str) -> Option<Vec<&str>> {
+    let names: &[&str] = match feature {
+        "aes" => &["aes", "pmull"],
+        "asimd" => &["asimd"],
+        "bf16" => &["bf16"],
+        "bti" => &["bti"],
+        "crc" => &["crc32"],
+        "cssc" => &["cssc"],
+        "dit" => &["dit"],
+        "dotprod" => &["asimddp"],
+        "dpb" => &["dcpop"],
+        "dpb2" => &["dcpodp"],
+        "ecv" => &["ecv"],
+        "f32mm" => &["svef32mm"],
+        "f64mm" => &["svef64mm"],
+        "faminmax" => &["faminmax"],
+        "fcma" => &["fcma"],
+        "fhm" => &["asimdfhm"],
+        "flagm" => &["flagm"],
+        "flagm2" => &["flagm2"],
+        "fp" => &["fp"],
+        "fp16" => &["fphp", "asimdhp"],
+        "fp8" => &["f8cvt"],
+        "fp8dot2" => &["f8dp2"],
+        "fp8dot4" => &["f8dp4"],
+        "fp8fma" => &["f8fma"],
+        "frintts" => &["frint"],
+        "hbc" => &["hbc"],
+        "i8mm" => &["i8mm"],
+        "jsconv" => &["jscvt"],
+        "lse" => &["atomics"],
+        "lse2" => &["uscat"],
+        "lse128" => &["lse128"],
+        "lut" => &["lut"],
+        "mops" => &["mops"],
+        "mte" => &["mte"],
+        "neon" => &["fp", "asimd"],
+        "paca" => &["paca"],
+        "pacg" => &["pacg"],
+        "pmull" => &["pmull"],
+        "rand" => &["rng"],
+        "rcpc" => &["lrcpc"],
+        "rcpc2" => &["ilrcpc"],
+        "rcpc3" => &["lrcpc3"],
+        "rdm" => &["asimdrdm"],
+        "sb" => &["sb"],
+        "sha2" => &["sha1", "sha2"],
+        "sha3" => &["sha512", "sha3"],
+        "sm4" => &["sm3", "sm4"],
+        "sme" => &["sme"],
+        "sme2" => &["sme2"],
+        "sme2p1" => &["sme2p1"],
+        "sme-b16b16" => &["smeb16b16"],
+        "sme-f16f16" => &["smef16f16"],
+        "sme-f64f64" => &["smef64f64"],
+        "sme-fa64" => &["smefa64"],
+        "sme-i16i64" => &["smei16i64"],
+        "ssbs" => &["ssbs"],
+        "sve" => &["sve"],
+        "sve-b16b16" => &["sveb16b16"],
+        "sve2" => &["sve2"],
+        "sve2-aes" => &["sveaes", "svepmull"],
+        "sve2-bitperm" => &["svebitperm"],
+        "sve2-sha3" => &["svesha3"],
+        "sve2-sm4" => &["svesm4"],
+        "sve2p1" => &["sve2p1"],
+        "wfxt" => &["wfxt"],
+        _ => return None,
+    };
+
+    Some(names.to_vec())
+}
+
+/// Returns the names the Linux kernel uses on PowerPC for a feature
+fn powerpc_kernel_names(feature: &str) -> Option<Vec<&str>> {
+    let names: &[&str] = match feature {
+        "altivec" => &["altivec"],
+        "vsx" => &["vsx"],
+        "partword-atomics" | "quadword-atomics" => &["arch_2_07"],
+        "power8-altivec" => &["altivec", "arch_2_07"],
+        "power8-vector" => &["vsx", "arch_2_07"],
+        "power8-crypto" => &["altivec", "vcrypto"],
+        "power9-altivec" => &["altivec", "arch_3_00"],
+        "power9-vector" => &["vsx", "arch_3_00"],
+        "power10-vector" => &["vsx", "arch_3_1"],
+        _ => return None,
+    };
+
+    Some(names.to_vec())
+}
+
+/// Returns true if the feature looks like a name the kernel could list (e.g., not `unknown feature` or `avx10.1`)
+fn is_feature_name(feature: &str) -> bool {
+    !feature.is_empty()
+        && feature
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
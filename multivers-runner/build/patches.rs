@@ -0,0 +1,294 @@
+// Generation of the compressed patches of the builds.
+//
+// This file is shared by the build script of the runner and by `cargo multivers --runner-stub`,
+// which includes it from the sources of the runner embedded in `cargo-multivers`,
+// so that both encode the builds the same way.
+// It is included with `include!` by `cargo-multivers`, so it cannot have inner attributes or doc comments.
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// The compression of the builds embedded in the runner
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// LZ4, fast to decompress
+    #[default]
+    Lz4,
+    /// Zstandard, smaller than LZ4 but slower to decompress
+    Zstd,
+    /// XZ, the smallest but the slowest to decompress
+    Xz,
+    /// No compression
+    None,
+}
+
+impl Compression {
+    /// Returns the name of the compression, as given on the command line
+    #[allow(dead_code, reason = "only used by cargo-multivers")]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+            Self::None => "none",
+        }
+    }
+
+    /// Returns the feature of the runner that enables the decoder of the compression
+    pub const fn runner_feature(self) -> Option<&'static str> {
+        match self {
+            Self::Lz4 => Some("lz4"),
+            Self::Zstd => Some("zstd"),
+            Self::Xz => Some("xz"),
+            Self::None => None,
+        }
+    }
+
+    /// Returns the levels of the compression, or `None` if it has no levels
+    #[allow(dead_code, reason = "only used by cargo-multivers")]
+    pub const fn levels(self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Self::Zstd => Some(1..=22),
+            Self::Xz => Some(0..=9),
+            Self::Lz4 | Self::None => None,
+        }
+    }
+
+    /// Compresses data with the given level, or with the default level of the compression
+    /// (19 for Zstandard, 6 for XZ, LZ4 has no levels)
+    pub fn compress(self, level: Option<i32>, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish().map_err(io::Error::other)
+            }
+            Self::Zstd => zstd::encode_all(data, level.unwrap_or(19)),
+            Self::Xz => {
+                let preset = u32::try_from(level.unwrap_or(6)).map_err(io::Error::other)?;
+                let options = lzma_rust2::XzOptions::with_preset(preset);
+                let mut encoder = lzma_rust2::XzWriter::new(Vec::new(), options)?;
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::None => Ok(data.to_vec()),
+        }
+    }
+}
+
+/// An algorithm that generates a patch from the source to a build
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delta {
+    Gdelta,
+    Bsdiff,
+}
+
+impl Delta {
+    /// Returns the name of the algorithm, as set in `multivers_delta` for the runner
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Gdelta => "gdelta",
+            Self::Bsdiff => "bsdiff",
+        }
+    }
+
+    /// Generates a patch from the source to the target
+    fn encode(self, source: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+        let error = || io::Error::other(format!("Failed to generate a patch with {}", self.name()));
+
+        match self {
+            Self::Gdelta => gdelta::encode(target, source).map_err(|_| error()),
+            Self::Bsdiff => {
+                let mut patch = Vec::new();
+                bsdiff::diff(source, target, &mut patch).map_err(|_| error())?;
+
+                Ok(patch)
+            }
+        }
+    }
+}
+
+/// How the patches of the builds are organized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaLayout {
+    /// Every build is patched from the source
+    #[default]
+    Star,
+    /// Every build is patched from its nearest build
+    Tree,
+}
+
+impl DeltaLayout {
+    /// Returns the pairs of a parent (the source if `None`) and a build whose patch must be generated
+    ///
+    /// The patches from the source are always generated, and, with the `tree` layout, the patches between each pair of builds.
+    pub fn pairs(self, n_builds: usize) -> Vec<(Option<usize>, usize)> {
+        let mut pairs = (0..n_builds).map(|i| (None, i)).collect::<Vec<_>>();
+        if self == Self::Tree {
+            pairs.extend((0..n_builds).flat_map(|i| {
+                (0..n_builds)
+                    .filter(move |&j| j != i)
+                    .map(move |j| (Some(i), j))
+            }));
+        }
+
+        pairs
+    }
+
+    /// Returns the parent of each build (the source if `None`), given the patches generated for the [`DeltaLayout::pairs`]
+    pub fn parents(self, n_builds: usize, patches: &Patches) -> Vec<Option<usize>> {
+        match self {
+            Self::Star => vec![None; n_builds],
+            Self::Tree => spanning_tree(n_builds, |parent, build| {
+                patches
+                    .get(&(parent, build))
+                    .map_or(usize::MAX, |patch| patch.compressed.len())
+            }),
+        }
+    }
+}
+
+/// A compressed patch that generates a build
+pub struct Patch {
+    pub delta: Delta,
+    pub compressed: Vec<u8>,
+    /// Time spent generating and compressing the patch with all the encoders
+    pub duration: Duration,
+}
+
+impl Patch {
+    /// Generates a patch from the source to the target with each encoder, and keeps the smallest one once compressed
+    pub fn encode(
+        encoders: &[Delta],
+        compression: Compression,
+        compression_level: Option<i32>,
+        source: &[u8],
+        target: &[u8],
+    ) -> io::Result<Self> {
+        let start = Instant::now();
+        let (delta, compressed) = encoders
+            .iter()
+            .map(|&delta| {
+                let patch = delta.encode(source, target)?;
+
+                Ok((delta, compression.compress(compression_level, &patch)?))
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .min_by_key(|(_, patch)| patch.len())
+            .ok_or_else(|| {
+                io::Error::other("At least one delta encoder is required to generate the patches")
+            })?;
+
+        Ok(Self {
+            delta,
+            compressed,
+            duration: start.elapsed(),
+        })
+    }
+}
+
+/// The patches generated for each pair of a parent (the source if `None`) and a build
+pub type Patches = BTreeMap<(Option<usize>, usize), Patch>;
+
+/// Total size and generation time of the patches of a layout
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LayoutReport {
+    /// Size of the compressed patches, in bytes
+    pub size: u64,
+    /// Time spent generating and compressing the patches, in seconds
+    pub seconds: f64,
+}
+
+impl<'a> FromIterator<&'a Patch> for LayoutReport {
+    fn from_iter<I: IntoIterator<Item = &'a Patch>>(patches: I) -> Self {
+        let (size, duration) =
+            patches
+                .into_iter()
+                .fold((0, Duration::ZERO), |(size, duration), patch| {
+                    (
+                        size + patch.compressed.len() as u64,
+                        duration + patch.duration,
+                    )
+                });
+
+        Self {
+            size,
+            seconds: duration.as_secs_f64(),
+        }
+    }
+}
+
+/// The patches generated with the `star` layout and, if it was selected, with the `tree` layout
+/// (which also generates the patches of the `star` layout)
+///
+/// The build script of the runner writes it to `delta_report.json`, for `cargo multivers` to report it.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DeltaReport {
+    pub star: LayoutReport,
+    pub tree: Option<LayoutReport>,
+}
+
+impl DeltaReport {
+    /// Reports the patches generated for the [`DeltaLayout::pairs`] of a layout, given the parent of each build
+    ///
+    /// The time of the `tree` layout is the time spent generating all the patches, since they are all needed to select the parents.
+    pub fn new(layout: DeltaLayout, patches: &Patches, parents: &[Option<usize>]) -> Self {
+        let star = patches
+            .iter()
+            .filter(|((parent, _), _)| parent.is_none())
+            .map(|(_, patch)| patch)
+            .collect::<LayoutReport>();
+        let tree = (layout == DeltaLayout::Tree).then(|| LayoutReport {
+            seconds: patches
+                .values()
+                .map(|patch| patch.duration.as_secs_f64())
+                .sum(),
+            ..parents
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &parent)| patches.get(&(parent, i)))
+                .collect()
+        });
+
+        Self { star, tree }
+    }
+}
+
+/// Returns the parent of each build in a spanning tree rooted at the source (`None`), where `size` returns
+/// the size of the patch from a parent to a build
+///
+/// The tree is built greedily from the source, by adding the build with the smallest patch from a build
+/// that is already in the tree (Prim's algorithm).
+pub fn spanning_tree(
+    n_builds: usize,
+    size: impl Fn(Option<usize>, usize) -> usize,
+) -> Vec<Option<usize>> {
+    // The best parent of each build that is not in the tree yet, and the size of its patch
+    let mut remaining = (0..n_builds)
+        .map(|build| (build, (None, size(None, build))))
+        .collect::<BTreeMap<_, _>>();
+    let mut parents = vec![None; n_builds];
+
+    while let Some((&added, &(parent, _))) = remaining.iter().min_by_key(|(_, (_, size))| *size) {
+        remaining.remove(&added);
+        if let Some(added_parent) = parents.get_mut(added) {
+            *added_parent = parent;
+        }
+
+        for (&build, best) in &mut remaining {
+            let size = size(Some(added), build);
+            if size < best.1 {
+                *best = (Some(added), size);
+            }
+        }
+    }
+
+    parents
+}
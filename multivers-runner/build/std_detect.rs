@@ -0,0 +1,148 @@
+//! Generation of the detection of CPU features with the `std::arch::is_{arch}_feature_detected!` macros.
+//!
+//! The macros only accept the features they know, and some features (or whole macros) are only available on nightly,
+//! so the generated code only covers the features known to be usable with the current toolchain.
+//! The unstable ones are only used with the `nightly` feature of the runner, since their library features
+//! can be renamed or stabilized by any nightly toolchain.
+//! The other features are left to the other detection backends of the runner.
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+use quote::{format_ident, quote};
+
+use proc_exit::Exit;
+
+use crate::detection::Stability::{self, Stable, Unstable};
+use crate::detection::std_detection;
+
+/// The version of the Rust toolchain used to build the runner
+struct Toolchain {
+    minor: u32,
+    /// Whether the unstable features can be used: the toolchain is nightly and the `nightly` feature of the runner is enabled
+    unstable: bool,
+}
+
+impl Toolchain {
+    /// Gets the version of `rustc`, or assumes the minimum supported version of stable Rust if it fails
+    fn detect() -> Self {
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let version = Command::new(rustc)
+            .arg("-vV")
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .and_then(|output| {
+                output
+                    .lines()
+                    .find_map(|line| line.strip_prefix("release: ").map(str::to_owned))
+            })
+            .or_else(|| std::env::var("CARGO_PKG_RUST_VERSION").ok())
+            .unwrap_or_default();
+
+        let minor = version
+            .split(['.', '-'])
+            .nth(1)
+            .and_then(|minor| minor.parse().ok())
+            .unwrap_or_default();
+        let nightly = version.contains("-nightly") || version.contains("-dev");
+        let unstable = std::env::var_os("CARGO_FEATURE_NIGHTLY").is_some();
+        if unstable && !nightly {
+            println!(
+                "cargo:warning=The `nightly` feature of the runner is ignored, since the toolchain is not nightly"
+            );
+        }
+
+        Self {
+            minor,
+            unstable: unstable && nightly,
+        }
+    }
+
+    /// Returns true if a feature with the given stability can be used with this toolchain
+    fn supports(&self, stability: Stability) -> bool {
+        match stability {
+            Stable(since) => since <= self.minor,
+            Unstable(_) => self.unstable,
+        }
+    }
+}
+
+/// Returns true if the runner has a detection backend that does not rely on `std::arch` for the target
+/// (see `multivers-runner/src/detect.rs`)
+fn has_other_detection(arch: &str, os: &str) -> bool {
+    match arch {
+        "x86" | "x86_64" => true,
+        "aarch64" | "riscv32" | "riscv64" | "powerpc64" => os == "linux",
+        _ => false,
+    }
+}
+
+/// Generates a Rust file with a function that detects CPU features using the `std::arch::is_{arch}_feature_detected!` macros.
+///
+/// It returns false if the runner cannot detect any CPU feature on the target.
+pub fn generate(dest_path: &Path) -> Result<bool, Exit> {
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").map_err(|_| {
+        proc_exit::sysexits::CONFIG_ERR
+            .with_message("Failed to get target's architecture from cargo")
+    })?;
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rustc-check-cfg=cfg(multivers_runner_gate, values(any()))");
+
+    let toolchain = Toolchain::detect();
+    let detection = std_detection(&arch)
+        .map(|(name, features)| {
+            let features = features
+                .iter()
+                .filter(|(_, stability)| toolchain.supports(*stability))
+                .collect::<Vec<_>>();
+
+            (name, features)
+        })
+        .filter(|(_, features)| !features.is_empty());
+
+    let tokens = if let Some((name, features)) = &detection {
+        // Enables the library features required by the unstable features (see `multivers-runner/src/lib.rs`)
+        let gates = features
+            .iter()
+            .filter_map(|(_, stability)| match stability {
+                Unstable(gate) => Some(*gate),
+                Stable(_) => None,
+            })
+            .collect::<BTreeSet<_>>();
+        for gate in gates {
+            println!("cargo:rustc-cfg=multivers_runner_gate=\"{gate}\"");
+        }
+
+        let is_feature_detected = format_ident!("{name}");
+        let features = features.iter().map(|(feature, _)| feature);
+
+        quote! {
+            fn std_is_feature_detected(feature: &str) -> Option<bool> {
+                let detected = match feature {
+                    #(#features => std::arch::#is_feature_detected!(#features),)*
+                    _ => return None,
+                };
+
+                Some(detected)
+            }
+        }
+    } else {
+        quote! {
+            fn std_is_feature_detected(_feature: &str) -> Option<bool> {
+                None
+            }
+        }
+    };
+
+    std::fs::write(dest_path, tokens.to_string()).map_err(|_| {
+        proc_exit::sysexits::IO_ERR.with_message(format!(
+            "Failed to write generated Rust file to {}",
+            dest_path.display(),
+        ))
+    })?;
+
+    Ok(detection.is_some() || has_other_detection(&arch, &os))
+}
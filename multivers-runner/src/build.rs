@@ -1,92 +1,398 @@
+use std::borrow::Cow;
 use std::convert::Infallible;
 use std::ffi::c_char;
-use std::io::{Read, Write};
+use std::io::{self, BufWriter, Read, Write};
+use std::time::Instant;
 
-include!(concat!(env!("OUT_DIR"), "/builds.rs"));
+use self::compression::Compression;
+use self::delta::Delta;
+use crate::detect::{CpuModel, DefaultDetector, Detector};
+use crate::rule::Rule;
+use crate::timings;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "stub")] {
+        /// Returns the builds appended to the runner stub by `cargo multivers --runner-stub`
+        fn embedded() -> &'static Embedded {
+            payload::payload()
+        }
+    } else {
+        include!(concat!(env!("OUT_DIR"), "/builds.rs"));
+
+        /// Returns the builds embedded in the runner by its build script
+        fn embedded() -> &'static Embedded {
+            static EMBEDDED: Embedded = Embedded {
+                compression: COMPRESSION,
+                source: &SOURCE,
+                patches: &PATCHES,
+                rules: &RULES,
+            };
+
+            &EMBEDDED
+        }
+    }
+}
+
+/// Size of the buffer used to write a patched build
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Bytes aligned on a page boundary
+///
+/// The uncompressed source is stored in it, so that it starts at a page-aligned offset of the runner executable
+/// and the kernel can copy it page by page.
+#[cfg_attr(
+    any(
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "loongarch64"
+    ),
+    repr(C, align(65536))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "loongarch64"
+    )),
+    repr(C, align(4096))
+)]
+#[allow(
+    dead_code,
+    reason = "only used by the generated code when the source is stored uncompressed"
+)]
+struct PageAligned<Bytes>(Bytes);
+
+/// The builds of the runner, with their compression and the selection rules
+///
+/// They are embedded when the runner is compiled, or appended to the runner stub with the `stub` feature.
+struct Embedded {
+    compression: Compression,
+    source: &'static Build<'static>,
+    patches: &'static [Build<'static>],
+    rules: &'static [Rule<'static>],
+}
+
+/// How the bytes of a build are stored
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(PartialEq, Eq, Debug))]
+#[allow(
+    dead_code,
+    reason = "only the variants used by the generated code are constructed"
+)]
+enum Data<'a> {
+    /// The build compressed with the compression of the runner
+    Compressed(&'a [u8]),
+    /// The build stored uncompressed (only for the source, with `--uncompressed-source`)
+    Stored(&'a [u8]),
+    /// The SHA3-256 hash of the build stored in a file beside the runner (with `--external-variants`)
+    #[cfg(feature = "external")]
+    External(&'a [u8; 32]),
+}
+
+impl<'a> Data<'a> {
+    /// Returns a reader of the uncompressed build
+    ///
+    /// A build stored beside the runner is hashed while it is read, and the reader fails at its end if its hash differs.
+    fn reader(self) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Self::Compressed(compressed) => embedded().compression.decoder(compressed),
+            Self::Stored(data) => Ok(Box::new(data)),
+            #[cfg(feature = "external")]
+            Self::External(hash) => Ok(Box::new(external::open(hash)?)),
+        }
+    }
+
+    /// Returns the phase of `MULTIVERS_TIMINGS` that reads the build when it is a source
+    const fn read_phase(self) -> &'static str {
+        match self {
+            Self::Compressed(_) => "source_decompress",
+            Self::Stored(_) => "source_copy",
+            #[cfg(feature = "external")]
+            Self::External(_) => "variant_read",
+        }
+    }
+}
 
 /// Stores a build and the CPU features it requires
-#[cfg_attr(test, derive(Eq, Debug, Clone))]
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(Eq, Debug))]
 pub struct Build<'a> {
-    compressed: &'a [u8],
+    /// The bytes of the build, or the hash of the file beside the runner that stores them
+    data: Data<'a>,
 
-    /// A function pointer that, when called, returns true if the running CPU supports all build's features
-    all_features_supported: fn() -> bool,
+    /// The list of CPU features required by the build (e.g., `["avx", "cmpxchg16b", "fxsr", "pclmulqdq", "popcnt", "sse"]`)
+    features: &'a [&'a str],
 
-    #[cfg(any(test, feature = "debug"))]
-    /// A comma-separated list of CPU features (e.g., `"avx, cmpxchg16b, fxsr, pclmulqdq, popcnt, sse, sse2, sse3, sse4.1, sse4.2, ssse3, xsave, xsaveopt"`)
-    features: &'a str,
+    /// The CPUs whose set of CPU features produced the build (e.g., `["x86-64-v3", "haswell"]`)
+    cpus: &'a [&'a str],
 
-    /// The source of this build (`None` if it is not a patch, but a source and it only needs to be uncompressed)
-    source: Option<&'a Self>,
+    /// The build from which this build is patched, and the algorithm of its patch
+    /// (`None` if it is not a patch, but a source and it only needs to be uncompressed)
+    ///
+    /// It is the source of the runner, or another patched build with the `tree` layout.
+    source: Option<(&'a Self, Delta)>,
 }
 
 #[cfg(test)]
 impl PartialEq for Build<'_> {
     fn eq(&self, other: &Self) -> bool {
-        #[inline]
-        fn make_eq_key(build: &Build<'_>) -> impl Eq {
-            (
-                build.compressed,
-                #[cfg(any(test, feature = "debug"))]
-                build.features,
-                build.source,
-            )
-        }
-
-        make_eq_key(self) == make_eq_key(other)
+        (self.data, self.features, self.source) == (other.data, other.features, other.source)
     }
 }
 
 impl Default for Build<'_> {
+    /// Returns the source of the runner, which is executed when no build is supported
     fn default() -> Self {
-        SOURCE
+        *embedded().source
+    }
+}
+
+impl Build<'static> {
+    /// Returns an iterator over the builds embedded in the runner.
+    ///
+    /// The builds are ordered by preference: the ones requiring the most CPU features come first,
+    /// and the source, which is used as a fallback, comes last.
+    pub fn builds() -> impl Iterator<Item = &'static Self> {
+        let embedded = embedded();
+
+        embedded
+            .patches
+            .iter()
+            .chain(std::iter::once(embedded.source))
+    }
+
+    /// Returns the build from which the other builds are patched, and that is used when none of them is supported
+    pub fn source() -> &'static Self {
+        embedded().source
+    }
+
+    /// Returns the selection rules embedded in the runner
+    pub fn rules() -> &'static [Rule<'static>] {
+        embedded().rules
+    }
+
+    /// Finds a version that matches the CPU features of the host, following the selection rules
+    /// and preferring the version tuned for the CPU model of the host (see [`Build::find_with_rules`])
+    ///
+    /// With the `all-cpus` feature, on Linux, the CPU features must be supported by all the CPUs of the host
+    /// (see [`AllCpus`](crate::detect::AllCpus)).
+    ///
+    /// The source is checked like the other builds, so `None` is returned if it is not supported either,
+    /// but the runner still executes it as its fallback (see [`Build::default`]).
+    pub fn find() -> Option<Self> {
+        let builds = Self::builds().copied();
+        let model = CpuModel::host();
+
+        cfg_if::cfg_if! {
+            if #[cfg(all(feature = "all-cpus", target_os = "linux"))] {
+                let features = Self::builds().flat_map(|build| build.features.iter().copied());
+                let detector = crate::detect::AllCpus::new(features);
+
+                Self::find_with_rules(builds, &detector, Self::rules(), model.as_ref())
+            } else {
+                Self::find_with_rules(builds, DefaultDetector, Self::rules(), model.as_ref())
+            }
+        }
     }
 }
 
-impl Build<'_> {
+impl<'a> Build<'a> {
     /// Extracts the build into a writer
-    pub fn extract_into(&self, mut output: impl Write) -> std::io::Result<()> {
-        if let Some(source) = self.source {
-            let mut decoder = lz4_flex::frame::FrameDecoder::new(source.compressed);
+    ///
+    /// A patched build is written while its patch is decompressed and applied,
+    /// so only the decompressed source is stored in memory (none if the source is stored uncompressed).
+    /// If the source is itself a patched build, it is extracted first (and so on up to the source of the runner).
+    /// A build stored beside the runner is written while it is read, and the error of a differing hash is returned
+    /// once it is written, so the output must then be discarded.
+    pub fn extract_into(&self, mut output: impl Write) -> io::Result<()> {
+        if let Some((source, delta)) = self.source {
+            let source = source.decompress()?;
 
-            let mut source = Vec::with_capacity(source.compressed.len());
-            decoder.read_to_end(&mut source)?;
+            timings::measure("patch_apply", || {
+                let patch = self.data.reader()?;
+                // Patches have many small instructions, the writes are buffered to reduce the number of syscalls
+                let mut output = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut output);
 
-            let mut patch = Vec::with_capacity(self.compressed.len());
-            let mut decoder = lz4_flex::frame::FrameDecoder::new(self.compressed);
-            decoder.read_to_end(&mut patch)?;
+                delta.apply(patch, &source, &mut output)?;
+                output.flush()
+            })?;
+        } else if let Data::Stored(data) = self.data {
+            timings::measure("source_copy", || output.write_all(data))?;
+        } else {
+            // The source is decompressed (or read from beside the runner) while it is written
+            timings::measure(self.data.read_phase(), || {
+                let mut reader = self.data.reader()?;
+
+                io::copy(&mut reader, &mut output)
+            })?;
+        }
 
-            let result = gdelta::decode(&patch, &source).map_err(|_| std::io::Error::other(""))?;
-            output.write_all(&result)?;
+        Ok(())
+    }
+
+    /// Returns the uncompressed build, after applying the patches from the source of the runner if it is a patched build
+    fn decompress(&self) -> io::Result<Cow<'a, [u8]>> {
+        if let Some((source, delta)) = self.source {
+            let source = source.decompress()?;
+
+            timings::measure("parent_patch_apply", || {
+                let patch = self.data.reader()?;
+
+                let mut build = Vec::with_capacity(source.len());
+                delta.apply(patch, &source, &mut build)?;
+
+                Ok(Cow::Owned(build))
+            })
+        } else if let Data::Stored(data) = self.data {
+            Ok(Cow::Borrowed(data))
         } else {
-            let mut decoder = lz4_flex::frame::FrameDecoder::new(self.compressed);
+            timings::measure(self.data.read_phase(), || {
+                let mut reader = self.data.reader()?;
+
+                let mut source = Vec::new();
+                if let Data::Compressed(compressed) = self.data {
+                    source.reserve(compressed.len());
+                }
+                reader.read_to_end(&mut source)?;
 
-            std::io::copy(&mut decoder, &mut output)?;
+                Ok(Cow::Owned(source))
+            })
         }
+    }
+
+    /// Extracts the build with the given function, then appends a launch report if `MULTIVERS_REPORT` is set,
+    /// and writes the timings of the runner if `MULTIVERS_TIMINGS` is set
+    fn extract_and_report(&self, extract: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        let start = Instant::now();
+        extract()?;
+        crate::report::append(self, start.elapsed());
+        timings::finish();
 
         Ok(())
     }
 
     /// Finds a version that matches the CPU features of the host
     pub fn find_from(builds: impl IntoIterator<Item = Self>) -> Option<Self> {
+        Self::find_from_by(builds, DefaultDetector)
+    }
+
+    /// Finds a version whose CPU features are all supported according to the given detector
+    pub fn find_from_by(
+        builds: impl IntoIterator<Item = Self>,
+        detector: impl Detector,
+    ) -> Option<Self> {
         builds.into_iter().find(|build| {
             #[cfg(feature = "debug")]
-            log::debug!("Checking build requiring CPU features: {}", build.features);
+            log::debug!(
+                "Checking build requiring CPU features: {}",
+                build.features.join(", ")
+            );
 
-            (build.all_features_supported)()
+            build.is_supported_by(&detector)
         })
     }
 
-    /// Finds a version that matches the CPU features of the host
-    pub fn find() -> Option<Self> {
-        Self::find_from(PATCHES)
+    /// Finds a version whose CPU features are all supported according to the given detector,
+    /// skipping the ones avoided by a rule that matches the CPU model, when the preferred build is supported
+    ///
+    /// If all the supported builds are avoided, the first one is returned.
+    /// If several builds require the same CPU features as the selected one (i.e., they are tuned for different CPUs),
+    /// the one tuned for the CPU model is preferred.
+    pub fn find_with_rules(
+        builds: impl IntoIterator<Item = Self>,
+        detector: impl Detector,
+        rules: &[Rule<'_>],
+        model: Option<&CpuModel>,
+    ) -> Option<Self> {
+        let supported = builds
+            .into_iter()
+            .filter(|build| build.is_supported_by(&detector))
+            .collect::<Vec<_>>();
+        let rules = model.map_or_else(Vec::new, |model| {
+            rules
+                .iter()
+                .filter(|rule| rule.matches(model))
+                .collect::<Vec<_>>()
+        });
+
+        let is_avoided = |build: &Self| {
+            rules.iter().any(|rule| {
+                let avoided =
+                    rule.avoids(build) && supported.iter().any(|build| rule.prefers(build));
+
+                #[cfg(feature = "debug")]
+                if avoided {
+                    log::debug!(
+                        "Skipping build of `{}` in favor of the one of `{}`",
+                        rule.over(),
+                        rule.prefer()
+                    );
+                }
+
+                avoided
+            })
+        };
+
+        let candidates = supported
+            .iter()
+            .filter(|build| !is_avoided(build))
+            .collect::<Vec<_>>();
+        let build = candidates.first().copied().or_else(|| supported.first())?;
+
+        let name = model.and_then(CpuModel::name);
+        let tuned = name.and_then(|name| {
+            candidates
+                .iter()
+                .find(|candidate| {
+                    candidate.features == build.features && candidate.cpus.contains(&name)
+                })
+                .copied()
+        });
+
+        #[cfg(feature = "debug")]
+        if let (Some(name), Some(_)) = (name, tuned) {
+            log::debug!("Selecting build tuned for `{name}`");
+        }
+
+        Some(*tuned.unwrap_or(build))
+    }
+
+    /// Returns true if the CPU of the host supports all the CPU features required by the build
+    ///
+    /// Like [`Build::find`], with the `all-cpus` feature, on Linux, the CPU features must be supported by all the CPUs of the host.
+    pub fn is_supported(&self) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(all(feature = "all-cpus", target_os = "linux"))] {
+                self.is_supported_by(crate::detect::AllCpus::new(self.features.iter().copied()))
+            } else {
+                self.is_supported_by(DefaultDetector)
+            }
+        }
+    }
+
+    /// Returns true if the given detector reports that all the CPU features required by the build are supported
+    ///
+    /// A feature the detector cannot detect is considered not supported.
+    pub fn is_supported_by(&self, detector: impl Detector) -> bool {
+        self.features
+            .iter()
+            .all(|feature| detector.detect(feature).unwrap_or(false))
+    }
+
+    /// Returns true if the build is the source, i.e., it is not stored as a patch
+    pub fn is_source(&self) -> bool {
+        self.source.is_none()
     }
 
     /// List of CPU features required by the build
-    #[cfg(feature = "debug")]
-    pub fn features(&self) -> &str {
+    pub fn features(&self) -> &[&str] {
         self.features
     }
+
+    /// List of CPUs whose set of CPU features produced the build
+    pub fn cpus(&self) -> &[&str] {
+        self.cpus
+    }
 }
 
 /// A type that can be executed like a standard program.
@@ -109,6 +415,13 @@ pub trait Executable {
     ) -> Result<Infallible, proc_exit::Exit>;
 }
 
+mod compression;
+mod delta;
+#[cfg(feature = "external")]
+mod external;
+#[cfg(feature = "stub")]
+mod payload;
+
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
         mod linux;
@@ -119,10 +432,22 @@ cfg_if::cfg_if! {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
     use std::io::Write;
 
+    use super::Data;
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    use super::delta::Delta;
     use crate::Build;
 
+    #[test]
+    #[cfg(not(feature = "stub"))]
+    fn builds_end_with_source() {
+        let builds = Build::builds().collect::<Vec<_>>();
+        assert_eq!(builds.last().copied(), Some(Build::source()));
+        assert!(Build::source().is_source());
+    }
+
     #[test]
     fn find_none() {
         assert_eq!(Build::find_from(None), None);
@@ -131,34 +456,215 @@ mod tests {
     #[test]
     fn find_no_features() {
         let build = Build {
-            compressed: b"test",
-            all_features_supported: || true,
-            features: "",
+            data: Data::Compressed(b"test"),
+            features: &[],
+            cpus: &[],
             source: None,
         };
-        assert_eq!(
-            Build::find_from(std::iter::once(build.clone())),
-            Some(build)
-        );
+        assert_eq!(Build::find_from(std::iter::once(build)), Some(build));
     }
 
     #[test]
     fn find_feature_not_found() {
         let build = Build {
-            compressed: b"test",
-            all_features_supported: || false,
-            features: "unknown feature",
+            data: Data::Compressed(b"test"),
+            features: &["unknown feature"],
+            cpus: &[],
+            source: None,
+        };
+        assert_eq!(Build::find_from(std::iter::once(build)), None);
+    }
+
+    #[test]
+    fn find_with_rules() {
+        use crate::Rule;
+        use crate::detect::{CpuModel, DefaultDetector};
+
+        let v4 = Build {
+            data: Data::Compressed(b"v4"),
+            features: &[],
+            cpus: &["x86-64-v4"],
+            source: None,
+        };
+        let v3 = Build {
+            data: Data::Compressed(b"v3"),
+            features: &[],
+            cpus: &["haswell", "x86-64-v3"],
+            source: None,
+        };
+        let rule = Rule {
+            vendor: Some("AuthenticAMD"),
+            family: Some(0x19),
+            models: &[],
+            prefer: "x86-64-v3",
+            over: "x86-64-v4",
+        };
+        let model = |vendor: &str| CpuModel {
+            vendor: vendor.into(),
+            family: 0x19,
+            model: 0x61,
+            stepping: 0,
+        };
+
+        let find = |builds: &[Build<'static>], model: Option<&CpuModel>| {
+            Build::find_with_rules(builds.iter().copied(), DefaultDetector, &[rule], model)
+        };
+
+        assert_eq!(find(&[v4, v3], None), Some(v4));
+        assert_eq!(find(&[v4, v3], Some(&model("GenuineIntel"))), Some(v4));
+        assert_eq!(find(&[v4, v3], Some(&model("AuthenticAMD"))), Some(v3));
+        // The preferred build is not available, so the avoided one is still selected
+        assert_eq!(find(&[v4], Some(&model("AuthenticAMD"))), Some(v4));
+    }
+
+    #[test]
+    fn find_tuned() {
+        use crate::detect::{CpuModel, DefaultDetector};
+
+        let skylake = Build {
+            data: Data::Compressed(b"skylake"),
+            features: &[],
+            cpus: &["skylake"],
+            source: None,
+        };
+        let znver1 = Build {
+            data: Data::Compressed(b"znver1"),
+            features: &[],
+            cpus: &["znver1"],
+            source: None,
+        };
+        let zen = CpuModel {
+            vendor: "AuthenticAMD".into(),
+            family: 0x17,
+            model: 0x1,
+            stepping: 0,
+        };
+
+        let find = |model: Option<&CpuModel>| {
+            Build::find_with_rules([skylake, znver1], DefaultDetector, &[], model)
+        };
+
+        assert_eq!(find(None), Some(skylake));
+        assert_eq!(find(Some(&zen)), Some(znver1));
+    }
+
+    #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn extract_into_patch() {
+        let source_data = b"source data that will be patched".repeat(100);
+        let mut expected_data = source_data.clone();
+        expected_data.extend_from_slice(b"with additional data");
+
+        let compress = |data: &[u8]| {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let compressed_source = compress(&source_data);
+        let compressed_patch = compress(&gdelta::encode(&expected_data, &source_data).unwrap());
+
+        let source = Build {
+            data: Data::Compressed(&compressed_source),
+            features: &[],
+            cpus: &[],
+            source: None,
+        };
+        let build = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Gdelta)),
+        };
+        let mut decompressed_data = vec![];
+        build.extract_into(&mut decompressed_data).unwrap();
+        assert_eq!(decompressed_data, expected_data);
+    }
+
+    #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn extract_into_chain() {
+        let source_data = b"source data that will be patched twice".repeat(100);
+        let mut parent_data = source_data.clone();
+        parent_data.extend_from_slice(b"with additional data");
+        let mut expected_data = parent_data.clone();
+        expected_data.splice(0..0, b"with more data".iter().copied());
+
+        let compress = |data: &[u8]| {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let compressed_source = compress(&source_data);
+        let compressed_parent = compress(&gdelta::encode(&parent_data, &source_data).unwrap());
+        let mut patch = Vec::new();
+        bsdiff::diff(&parent_data, &expected_data, &mut patch).unwrap();
+        let compressed_patch = compress(&patch);
+
+        let source = Build {
+            data: Data::Compressed(&compressed_source),
+            features: &[],
+            cpus: &[],
+            source: None,
+        };
+        let parent = Build {
+            data: Data::Compressed(&compressed_parent),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Gdelta)),
+        };
+        let build = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&parent, Delta::Bsdiff)),
+        };
+        let mut decompressed_data = vec![];
+        build.extract_into(&mut decompressed_data).unwrap();
+        assert_eq!(decompressed_data, expected_data);
+    }
+
+    #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn extract_into_stored_source() {
+        let source_data = b"source data that is stored uncompressed".repeat(100);
+        let mut expected_data = source_data.clone();
+        expected_data.extend_from_slice(b"with additional data");
+
+        let mut patch = Vec::new();
+        bsdiff::diff(&source_data, &expected_data, &mut patch).unwrap();
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(&patch).unwrap();
+        let compressed_patch = encoder.finish().unwrap();
+
+        let source = Build {
+            data: Data::Stored(&source_data),
+            features: &[],
+            cpus: &[],
             source: None,
         };
-        assert_eq!(Build::find_from(std::iter::once(build.clone())), None);
+        let build = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Bsdiff)),
+        };
+
+        let mut data = vec![];
+        source.extract_into(&mut data).unwrap();
+        assert_eq!(data, source_data);
+
+        let mut data = vec![];
+        build.extract_into(&mut data).unwrap();
+        assert_eq!(data, expected_data);
     }
 
     #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
     fn extract_into_fail_not_compressed() {
         let build = Build {
-            compressed: b"invalid compressed data",
-            all_features_supported: || true,
-            features: "",
+            data: Data::Compressed(b"invalid compressed data"),
+            features: &[],
+            cpus: &[],
             source: None,
         };
         let mut v = vec![];
@@ -166,6 +672,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
     fn extract_into() {
         let expected_data = b"data that will be compressed";
         let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
@@ -173,9 +680,9 @@ mod tests {
         let compressed = encoder.finish().unwrap();
 
         let build = Build {
-            compressed: &compressed,
-            all_features_supported: || true,
-            features: "",
+            data: Data::Compressed(&compressed),
+            features: &[],
+            cpus: &[],
             source: None,
         };
         let mut decompressed_data = vec![];
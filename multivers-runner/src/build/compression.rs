@@ -0,0 +1,112 @@
+use std::io::{self, Read};
+
+/// The compression of the builds embedded in the runner
+///
+/// The generated code sets it in `COMPRESSION`, and only the decoder of this compression ends up in the runner
+/// (the decoders are only compiled with the features of the same name).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(
+    dead_code,
+    reason = "only the compression set by the generated code is constructed"
+)]
+#[cfg_attr(
+    feature = "stub",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub(crate) enum Compression {
+    /// LZ4 frame format
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// XZ
+    #[cfg(feature = "xz")]
+    Xz,
+    /// Builds stored as is
+    None,
+}
+
+impl Compression {
+    /// Returns a reader that decompresses the given data
+    pub(super) fn decoder(self, compressed: &[u8]) -> io::Result<Box<dyn Read + '_>> {
+        let decoder: Box<dyn Read> = match self {
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(compressed)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(
+                ruzstd::decoding::StreamingDecoder::new(compressed)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            ),
+            #[cfg(feature = "xz")]
+            Self::Xz => Box::new(lzma_rust2::XzReader::new(compressed, false)),
+            Self::None => Box::new(compressed),
+        };
+
+        Ok(decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    #[cfg(any(feature = "lz4", feature = "xz"))]
+    use std::io::Write;
+
+    use super::Compression;
+
+    fn decompress(compression: Compression, compressed: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        compression
+            .decoder(compressed)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4() {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(b"lz4 data").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress(Compression::Lz4, &compressed), b"lz4 data");
+    }
+
+    #[test]
+    fn none() {
+        assert_eq!(decompress(Compression::None, b"raw data"), b"raw data");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let compressed = ruzstd::encoding::compress_to_vec(
+            &b"zstd data".repeat(10)[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+
+        assert_eq!(
+            decompress(Compression::Zstd, &compressed),
+            b"zstd data".repeat(10)
+        );
+        assert!(Compression::Zstd.decoder(b"invalid").is_err());
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz() {
+        let mut writer =
+            lzma_rust2::XzWriter::new(Vec::new(), lzma_rust2::XzOptions::with_preset(6)).unwrap();
+        writer.write_all(&b"xz data".repeat(10)).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        assert_eq!(
+            decompress(Compression::Xz, &compressed),
+            b"xz data".repeat(10)
+        );
+    }
+}
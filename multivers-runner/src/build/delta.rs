@@ -0,0 +1,171 @@
+use std::io::{self, Read, Write};
+
+#[cfg(any(test, multivers_delta = "bsdiff"))]
+mod bsdiff;
+#[cfg(any(test, multivers_delta = "gdelta"))]
+mod gdelta;
+#[cfg(any(test, target_os = "linux"))]
+mod in_place;
+
+/// Size of the chunks of the source to which the bytes of the patch are added
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The algorithm that generated the patch of a build
+///
+/// The build script sets `multivers_delta` to each algorithm used by the patches,
+/// so the runner only includes the decoders it needs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "stub",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub(crate) enum Delta {
+    /// `gdelta::encode`
+    #[cfg(any(test, multivers_delta = "gdelta"))]
+    Gdelta,
+    /// `bsdiff::diff`
+    #[cfg(any(test, multivers_delta = "bsdiff"))]
+    Bsdiff,
+}
+
+/// An instruction of a patch, which writes the next bytes of the patched build
+#[derive(Clone, Copy, Debug)]
+#[allow(
+    dead_code,
+    reason = "only the instructions of the algorithms used by the patches are constructed"
+)]
+enum Instruction {
+    /// Copies bytes of the source
+    Copy { offset: usize, length: usize },
+    /// Adds the next bytes of the patch to bytes of the source
+    Add { offset: usize, length: usize },
+    /// Inserts the next bytes of the patch
+    Insert { length: usize },
+}
+
+/// Reads the instructions of a patch, and the bytes of the patch they add or insert
+enum Instructions<R> {
+    #[cfg(any(test, multivers_delta = "gdelta"))]
+    Gdelta(gdelta::Instructions<R>),
+    #[cfg(any(test, multivers_delta = "bsdiff"))]
+    Bsdiff(bsdiff::Instructions<R>),
+    /// Never constructed, when the runner includes no decoder
+    #[allow(dead_code, reason = "only used so that the type parameter is used")]
+    #[cfg(not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")))]
+    None(std::convert::Infallible, std::marker::PhantomData<R>),
+}
+
+#[cfg_attr(
+    not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")),
+    allow(unused_variables)
+)]
+impl<R: Read> Instructions<R> {
+    /// Returns the next instruction, or `None` at the end of the patch
+    ///
+    /// The bytes added or inserted by the instruction must be read from [`Instructions::data`],
+    /// or skipped with [`Instructions::skip`], before the next instruction.
+    fn next(&mut self) -> io::Result<Option<Instruction>> {
+        match self {
+            #[cfg(any(test, multivers_delta = "gdelta"))]
+            Self::Gdelta(instructions) => instructions.next(),
+            #[cfg(any(test, multivers_delta = "bsdiff"))]
+            Self::Bsdiff(instructions) => instructions.next(),
+            #[cfg(not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")))]
+            Self::None(never, _) => match *never {},
+        }
+    }
+
+    /// Returns the reader of the bytes added or inserted by the current instruction
+    fn data(&mut self) -> &mut R {
+        match self {
+            #[cfg(any(test, multivers_delta = "gdelta"))]
+            Self::Gdelta(instructions) => instructions.data(),
+            #[cfg(any(test, multivers_delta = "bsdiff"))]
+            Self::Bsdiff(instructions) => instructions.data(),
+            #[cfg(not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")))]
+            Self::None(never, _) => match *never {},
+        }
+    }
+
+    /// Reads the bytes added or inserted by the current instruction
+    fn read_data(&mut self, data: &mut [u8]) -> io::Result<()> {
+        self.data()
+            .read_exact(data)
+            .map_err(|_| invalid("truncated data"))
+    }
+}
+
+impl Delta {
+    /// Returns a reader of the instructions of a patch
+    #[cfg_attr(
+        not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")),
+        allow(unused_variables)
+    )]
+    fn instructions<R: Read>(self, patch: R) -> io::Result<Instructions<R>> {
+        match self {
+            #[cfg(any(test, multivers_delta = "gdelta"))]
+            Self::Gdelta => gdelta::Instructions::new(patch).map(Instructions::Gdelta),
+            #[cfg(any(test, multivers_delta = "bsdiff"))]
+            Self::Bsdiff => bsdiff::Instructions::new(patch).map(Instructions::Bsdiff),
+        }
+    }
+
+    /// Applies a patch to the source, writing the result while the patch is read
+    pub(super) fn apply(
+        self,
+        patch: impl Read,
+        source: &[u8],
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        let mut instructions = self.instructions(patch)?;
+        let mut buffer = Vec::new();
+
+        while let Some(instruction) = instructions.next()? {
+            match instruction {
+                Instruction::Copy { offset, length } => {
+                    output.write_all(source_range(source, offset, length)?)?;
+                }
+                Instruction::Add { offset, length } => {
+                    for chunk in source_range(source, offset, length)?.chunks(CHUNK_SIZE) {
+                        buffer.resize(chunk.len(), 0);
+                        instructions.read_data(&mut buffer)?;
+                        add(&mut buffer, chunk);
+                        output.write_all(&buffer)?;
+                    }
+                }
+                Instruction::Insert { length } => {
+                    let length = length as u64;
+                    let inserted = io::copy(&mut instructions.data().take(length), &mut output)?;
+                    if inserted != length {
+                        return Err(invalid("truncated data"));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns bytes of the source, or an error if they are outside of it
+fn source_range(source: &[u8], offset: usize, length: usize) -> io::Result<&[u8]> {
+    offset
+        .checked_add(length)
+        .and_then(|end| source.get(offset..end))
+        .ok_or_else(|| invalid("copy outside of the source"))
+}
+
+/// Adds bytes of the source to bytes of the patch
+fn add(bytes: &mut [u8], source: &[u8]) {
+    for (byte, source_byte) in bytes.iter_mut().zip(source) {
+        *byte = byte.wrapping_add(*source_byte);
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid patch: {message}"),
+    )
+}
@@ -0,0 +1,175 @@
+use std::io::{self, Read};
+
+use super::{Instruction, invalid};
+
+/// Reads the instructions of a patch generated by `bsdiff::diff`
+///
+/// A patch is a sequence of blocks, each starting with three 64-bit little-endian integers:
+/// the number of bytes of the patch to add to the source, the number of bytes of the patch to insert after them,
+/// and the (sign-magnitude) offset by which the position in the source moves after the added bytes.
+/// This is the format of bsdiff 0.2.1, whose version is pinned in the manifest so that the encoder cannot change it.
+pub(super) struct Instructions<R> {
+    patch: R,
+    /// Position in the source of the next added bytes
+    position: usize,
+    /// Number of bytes to insert after the added bytes of the current block
+    insert_length: Option<usize>,
+}
+
+impl<R: Read> Instructions<R> {
+    #[allow(
+        clippy::unnecessary_wraps,
+        reason = "same signature as the other formats"
+    )]
+    pub(super) fn new(patch: R) -> io::Result<Self> {
+        Ok(Self {
+            patch,
+            position: 0,
+            insert_length: None,
+        })
+    }
+
+    pub(super) fn next(&mut self) -> io::Result<Option<Instruction>> {
+        if let Some(length) = self.insert_length.take() {
+            return Ok(Some(Instruction::Insert { length }));
+        }
+        let Some((add_length, insert_length, seek)) = read_header(&mut self.patch)? else {
+            return Ok(None);
+        };
+
+        let offset = self.position;
+        let length =
+            usize::try_from(add_length).map_err(|_| invalid("addition outside of the source"))?;
+        self.insert_length =
+            Some(usize::try_from(insert_length).map_err(|_| invalid("insertion too long"))?);
+        self.position = offset
+            .checked_add(length)
+            .zip(isize::try_from(seek).ok())
+            .and_then(|(position, seek)| position.checked_add_signed(seek))
+            .ok_or_else(|| invalid("seek outside of the source"))?;
+
+        Ok(Some(Instruction::Add { offset, length }))
+    }
+
+    pub(super) fn data(&mut self) -> &mut R {
+        &mut self.patch
+    }
+
+    /// Skips the bytes added or inserted by an instruction, which come before the next block
+    #[cfg_attr(
+        not(target_os = "linux"),
+        allow(dead_code, reason = "builds are only patched in place on Linux")
+    )]
+    pub(super) fn skip(&mut self, length: usize) -> io::Result<()> {
+        let length = length as u64;
+        let skipped = io::copy(&mut (&mut self.patch).take(length), &mut io::sink())?;
+        if skipped != length {
+            return Err(invalid("truncated data"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the header of a block, or returns `None` at the end of the patch
+fn read_header(patch: &mut impl Read) -> io::Result<Option<(u64, u64, i64)>> {
+    let mut add_length = [0; 8];
+    let (first, rest) = add_length
+        .split_first_mut()
+        .ok_or_else(|| invalid("empty header"))?;
+    loop {
+        match patch.read(std::slice::from_mut(first)) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    let mut insert_length = [0; 8];
+    let mut seek = [0; 8];
+    for bytes in [rest, &mut insert_length, &mut seek] {
+        patch
+            .read_exact(bytes)
+            .map_err(|_| invalid("truncated header"))?;
+    }
+
+    // The offset is stored as a sign bit and a 63-bit magnitude
+    let seek = u64::from_le_bytes(seek);
+    let magnitude = i64::try_from(seek & !(1 << 63)).map_err(|_| invalid("invalid seek"))?;
+    let seek = if seek & (1 << 63) == 0 {
+        magnitude
+    } else {
+        -magnitude
+    };
+
+    Ok(Some((
+        u64::from_le_bytes(add_length),
+        u64::from_le_bytes(insert_length),
+        seek,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Delta;
+
+    fn apply(patch: &[u8], source: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Delta::Bsdiff.apply(patch, source, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn apply_in_place(patch: &[u8], source: &[u8]) -> std::io::Result<Vec<u8>> {
+        let plan = Delta::Bsdiff.plan(patch)?;
+        let mut build = vec![0; plan.len(source.len())];
+        build[plan.source_offset()..][..source.len()].copy_from_slice(source);
+        Delta::Bsdiff.apply_in_place(patch, &plan, &mut build, source.len())?;
+        build.truncate(plan.length());
+
+        Ok(build)
+    }
+
+    #[test]
+    fn apply_same_as_bsdiff() {
+        let source = (0..100_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut target = source.clone();
+        target.splice(1000..1000, b"inserted data".iter().copied());
+        target.truncate(300_000);
+        target.extend_from_slice(&source[..5000]);
+        for byte in target.iter_mut().step_by(1000) {
+            *byte = byte.wrapping_add(1);
+        }
+
+        let mut patch = Vec::new();
+        bsdiff::diff(&source, &target, &mut patch).unwrap();
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        assert_eq!(apply_in_place(&patch, &source).unwrap(), target);
+
+        let mut patched = Vec::new();
+        bsdiff::patch(&source, &mut patch.as_slice(), &mut patched).unwrap();
+        assert_eq!(patched, target);
+    }
+
+    #[test]
+    fn apply_invalid() {
+        let source = b"a source that is long enough to be copied".repeat(100);
+        let mut target = source.clone();
+        target.extend_from_slice(b"with inserted data");
+        let mut patch = Vec::new();
+        bsdiff::diff(&source, &target, &mut patch).unwrap();
+
+        apply(&patch, &source[..100]).unwrap_err();
+        apply(&patch[..patch.len() - 1], &source).unwrap_err();
+        apply(&patch[..10], &source).unwrap_err();
+        assert_eq!(apply(&[], &source).unwrap(), b"");
+
+        apply_in_place(&patch, &source[..100]).unwrap_err();
+        apply_in_place(&patch[..patch.len() - 1], &source).unwrap_err();
+        apply_in_place(&patch[..10], &source).unwrap_err();
+        assert_eq!(apply_in_place(&[], &source).unwrap(), b"");
+    }
+}
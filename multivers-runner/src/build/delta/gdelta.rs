@@ -0,0 +1,160 @@
+use std::io::{self, Read};
+
+use super::{Instruction, invalid};
+
+/// Reads the instructions of a patch generated by `gdelta::encode`
+///
+/// Unlike `gdelta::decode`, neither the whole patch nor the whole result are stored in memory.
+/// A patch starts with the length of its instructions, followed by the instructions,
+/// and then by the literal data they insert (in the same order as the instructions).
+/// This is the format of gdelta 0.2.1, whose version is pinned in the manifest so that the encoder cannot change it.
+pub(super) struct Instructions<R> {
+    /// The patch, after its instructions
+    patch: R,
+    /// The instructions, which are read before the literal data
+    instructions: Vec<u8>,
+    /// Number of bytes of the instructions already decoded
+    decoded: usize,
+}
+
+impl<R: Read> Instructions<R> {
+    pub(super) fn new(mut patch: R) -> io::Result<Self> {
+        let instructions_len = read_varint(&mut patch)?;
+        let mut instructions = Vec::new();
+        (&mut patch)
+            .take(instructions_len)
+            .read_to_end(&mut instructions)?;
+        if instructions.len() as u64 != instructions_len {
+            return Err(invalid("truncated instructions"));
+        }
+
+        Ok(Self {
+            patch,
+            instructions,
+            decoded: 0,
+        })
+    }
+
+    pub(super) fn next(&mut self) -> io::Result<Option<Instruction>> {
+        let mut instructions = self.instructions.get(self.decoded..).unwrap_or_default();
+        if instructions.is_empty() {
+            return Ok(None);
+        }
+        let remaining = instructions.len();
+
+        // [copy:1][more:1][length:6], then the rest of the length (if more), then the offset (if copy)
+        let head = read_u8(&mut instructions)?;
+        let mut length = u64::from(head & 0x3f);
+        if head & 0x40 != 0 {
+            length |= read_varint(&mut instructions)? << 6;
+        }
+        let length = usize::try_from(length).map_err(|_| invalid("instruction too long"))?;
+
+        let instruction = if head & 0x80 != 0 {
+            let offset = usize::try_from(read_varint(&mut instructions)?)
+                .map_err(|_| invalid("copy outside of the source"))?;
+
+            Instruction::Copy { offset, length }
+        } else {
+            Instruction::Insert { length }
+        };
+        self.decoded += remaining - instructions.len();
+
+        Ok(Some(instruction))
+    }
+
+    pub(super) fn data(&mut self) -> &mut R {
+        &mut self.patch
+    }
+
+    /// Skips the literal data of an instruction, which is not read since it comes after all the instructions
+    #[allow(
+        clippy::unused_self,
+        clippy::unnecessary_wraps,
+        reason = "same signature as the other formats"
+    )]
+    #[cfg_attr(
+        not(target_os = "linux"),
+        allow(dead_code, reason = "builds are only patched in place on Linux")
+    )]
+    pub(super) fn skip(&mut self, _length: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    let [byte] = byte;
+
+    Ok(byte)
+}
+
+/// Reads an integer stored in 7-bit groups, with the high bit set when more bytes follow
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..u64::BITS).step_by(7) {
+        let byte = read_u8(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid("integer too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Delta;
+
+    fn apply(patch: &[u8], source: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Delta::Gdelta.apply(patch, source, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn apply_in_place(patch: &[u8], source: &[u8]) -> std::io::Result<Vec<u8>> {
+        let plan = Delta::Gdelta.plan(patch)?;
+        let mut build = vec![0; plan.len(source.len())];
+        build[plan.source_offset()..][..source.len()].copy_from_slice(source);
+        Delta::Gdelta.apply_in_place(patch, &plan, &mut build, source.len())?;
+        build.truncate(plan.length());
+
+        Ok(build)
+    }
+
+    #[test]
+    fn apply_same_as_gdelta() {
+        let source = (0..100_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut target = source.clone();
+        target.splice(1000..1000, b"inserted data".iter().copied());
+        target.truncate(300_000);
+        target.extend_from_slice(&source[..5000]);
+
+        let patch = gdelta::encode(&target, &source).unwrap();
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        assert_eq!(apply_in_place(&patch, &source).unwrap(), target);
+        assert_eq!(gdelta::decode(&patch, &source).unwrap(), target);
+    }
+
+    #[test]
+    fn apply_invalid() {
+        let source = b"a source that is long enough to be copied".repeat(100);
+        let mut target = source.clone();
+        target.extend_from_slice(b"with inserted data");
+        let patch = gdelta::encode(&target, &source).unwrap();
+
+        apply(&patch, &source[..100]).unwrap_err();
+        apply(&patch[..patch.len() - 1], &source).unwrap_err();
+        apply(&[], &source).unwrap_err();
+        apply(&[0xff; 16], &source).unwrap_err();
+
+        apply_in_place(&patch, &source[..100]).unwrap_err();
+        apply_in_place(&patch[..patch.len() - 1], &source).unwrap_err();
+        apply_in_place(&[], &source).unwrap_err();
+    }
+}
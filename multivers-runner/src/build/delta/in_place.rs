@@ -0,0 +1,272 @@
+use std::io::{self, Read};
+use std::ops::Range;
+
+use super::{CHUNK_SIZE, Delta, Instruction, Instructions, add, invalid, source_range};
+
+/// How a build is patched in place (see [`Delta::apply_in_place`])
+pub(in crate::build) struct Plan {
+    /// Length of the patched build
+    length: usize,
+    /// Offset of the source in the build, chosen so that the build overwrites few bytes of the source before they are read
+    source_offset: usize,
+    /// End of the bytes of the source read by the patch
+    source_end: usize,
+    /// Sorted and disjoint ranges of the bytes of the source that the build overwrites before they are read
+    saved: Vec<Range<usize>>,
+}
+
+impl Plan {
+    /// Length of the patched build
+    pub(in crate::build) const fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Offset at which the source must be written in the build before it is patched in place
+    pub(in crate::build) const fn source_offset(&self) -> usize {
+        self.source_offset
+    }
+
+    /// Returns the number of bytes needed to patch in place a source of the given length
+    pub(in crate::build) fn len(&self, source_length: usize) -> usize {
+        self.length
+            .max(self.source_offset.saturating_add(source_length))
+    }
+}
+
+impl Instruction {
+    /// Returns the number of bytes written by the instruction
+    const fn length(self) -> usize {
+        match self {
+            Self::Copy { length, .. } | Self::Add { length, .. } | Self::Insert { length } => {
+                length
+            }
+        }
+    }
+}
+
+#[cfg_attr(
+    not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")),
+    allow(unused_variables)
+)]
+impl<R: Read> Instructions<R> {
+    /// Skips the bytes added or inserted by the current instruction
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        match self {
+            #[cfg(any(test, multivers_delta = "gdelta"))]
+            Self::Gdelta(instructions) => instructions.skip(length),
+            #[cfg(any(test, multivers_delta = "bsdiff"))]
+            Self::Bsdiff(instructions) => instructions.skip(length),
+            #[cfg(not(any(test, multivers_delta = "gdelta", multivers_delta = "bsdiff")))]
+            Self::None(never, _) => match *never {},
+        }
+    }
+}
+
+impl Delta {
+    /// Reads the instructions of a patch to find how to apply it in place
+    ///
+    /// The build is written from its start, over the source, which is placed at the offset
+    /// where the build overwrites the bytes of the source after they are read, if the builds only differ locally.
+    /// The other bytes of the source that the build overwrites before they are read are listed to be saved first.
+    pub(in crate::build) fn plan(self, patch: impl Read) -> io::Result<Plan> {
+        let mut instructions = self.instructions(patch)?;
+        let mut position = 0_usize;
+        let mut source_end = 0;
+        // The reads of the source before the position in the build where they are written
+        let mut backward_reads = Vec::new();
+
+        while let Some(instruction) = instructions.next()? {
+            match instruction {
+                Instruction::Copy { offset, length } | Instruction::Add { offset, length } => {
+                    let end = offset
+                        .checked_add(length)
+                        .ok_or_else(|| invalid("copy outside of the source"))?;
+                    source_end = source_end.max(end);
+                    if offset < position {
+                        backward_reads.push((position, offset..end));
+                    }
+                }
+                Instruction::Insert { .. } => {}
+            }
+            if let Instruction::Add { length, .. } | Instruction::Insert { length } = instruction {
+                instructions.skip(length)?;
+            }
+
+            position = position
+                .checked_add(instruction.length())
+                .ok_or_else(|| invalid("build too long"))?;
+        }
+        let length = position;
+
+        // The bytes of the source before this offset in the build are overwritten before they are read
+        let source_offset = length.saturating_sub(source_end);
+        let mut overwritten = backward_reads
+            .into_iter()
+            .filter_map(|(position, read)| {
+                let end = read.end.min(position.saturating_sub(source_offset));
+                (read.start < end).then_some(read.start..end)
+            })
+            .collect::<Vec<_>>();
+        overwritten.sort_unstable_by_key(|range| range.start);
+
+        let mut saved = Vec::<Range<usize>>::with_capacity(overwritten.len());
+        for range in overwritten {
+            match saved.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => saved.push(range),
+            }
+        }
+
+        Ok(Plan {
+            length,
+            source_offset,
+            source_end,
+            saved,
+        })
+    }
+
+    /// Applies a patch to a source stored at [`Plan::source_offset`] in the build, writing the build over it
+    ///
+    /// The build must hold at least [`Plan::len`] bytes.
+    /// Only the bytes of the source listed by the plan are stored in memory while the patch is applied.
+    pub(in crate::build) fn apply_in_place(
+        self,
+        patch: impl Read,
+        plan: &Plan,
+        build: &mut [u8],
+        source_length: usize,
+    ) -> io::Result<()> {
+        if plan.source_end > source_length {
+            return Err(invalid("copy outside of the source"));
+        }
+        if build.len() < plan.len(source_length) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "build too short to be patched in place",
+            ));
+        }
+
+        let source = Source::save(build, plan)?;
+        let mut instructions = self.instructions(patch)?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut position = 0_usize;
+
+        while let Some(instruction) = instructions.next()? {
+            let length = instruction.length();
+            let end = position
+                .checked_add(length)
+                .filter(|&end| end <= plan.length)
+                .ok_or_else(|| invalid("build too long"))?;
+
+            match instruction {
+                Instruction::Copy { offset, .. } => {
+                    source.copy(build, offset, length, position)?;
+                }
+                Instruction::Add { offset, .. } => {
+                    source.copy(build, offset, length, position)?;
+
+                    for chunk in build
+                        .get_mut(position..end)
+                        .unwrap_or_default()
+                        .chunks_mut(CHUNK_SIZE)
+                    {
+                        let buffer = buffer.get_mut(..chunk.len()).unwrap_or_default();
+                        instructions.read_data(buffer)?;
+                        add(chunk, buffer);
+                    }
+                }
+                Instruction::Insert { .. } => {
+                    instructions.read_data(build.get_mut(position..end).unwrap_or_default())?;
+                }
+            }
+
+            position = end;
+        }
+
+        Ok(())
+    }
+}
+
+/// The source of a build patched in place
+struct Source<'a> {
+    plan: &'a Plan,
+    /// The bytes of the ranges of [`Plan::saved`], one after the other
+    saved: Vec<u8>,
+    /// The offset in `saved` of each range of [`Plan::saved`]
+    saved_offsets: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    /// Saves the bytes of the source that the build overwrites before they are read
+    fn save(build: &[u8], plan: &'a Plan) -> io::Result<Self> {
+        let mut saved = Vec::with_capacity(plan.saved.iter().map(ExactSizeIterator::len).sum());
+        let mut saved_offsets = Vec::with_capacity(plan.saved.len());
+        for range in &plan.saved {
+            saved_offsets.push(saved.len());
+            saved.extend_from_slice(source_range(
+                build,
+                plan.source_offset.saturating_add(range.start),
+                range.len(),
+            )?);
+        }
+
+        Ok(Self {
+            plan,
+            saved,
+            saved_offsets,
+        })
+    }
+
+    /// Copies bytes of the source at the given position in the build,
+    /// taking the ones that the build already overwrote from the saved bytes
+    fn copy(
+        &self,
+        build: &mut [u8],
+        offset: usize,
+        length: usize,
+        position: usize,
+    ) -> io::Result<()> {
+        let end = offset.saturating_add(length);
+        // The bytes of the source before `split` are already overwritten by the build
+        let split = position
+            .saturating_sub(self.plan.source_offset)
+            .clamp(offset, end);
+
+        // The bytes still in place come first, since they can be overwritten by the saved ones
+        let in_place = self.plan.source_offset + split..self.plan.source_offset + end;
+        let destination = position + (split - offset);
+        if in_place.end > build.len() || destination + in_place.len() > build.len() {
+            return Err(invalid("copy outside of the source"));
+        }
+        build.copy_within(in_place, destination);
+
+        if split > offset {
+            let saved = self.saved(offset..split)?;
+            build
+                .get_mut(position..destination)
+                .ok_or_else(|| invalid("copy outside of the source"))?
+                .copy_from_slice(saved);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the saved bytes of a range of the source
+    fn saved(&self, range: Range<usize>) -> io::Result<&[u8]> {
+        // The saved range that contains the given one, which was saved when the plan was made
+        let index = self
+            .plan
+            .saved
+            .partition_point(|saved| saved.end <= range.start);
+        self.plan
+            .saved
+            .get(index)
+            .zip(self.saved_offsets.get(index))
+            .filter(|(saved, _)| saved.start <= range.start && range.end <= saved.end)
+            .and_then(|(saved, offset)| {
+                let start = offset + (range.start - saved.start);
+                self.saved.get(start..start + range.len())
+            })
+            .ok_or_else(|| invalid("copy of bytes of the source that are not saved"))
+    }
+}
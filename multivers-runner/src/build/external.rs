@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use sha3::{Digest, Sha3_256};
+
+/// Bytes that start an archive of variants (`cargo multivers --external-variants archive`)
+///
+/// Each variant follows, preceded by its SHA3-256 hash and its length as a 64-bit little-endian integer.
+const ARCHIVE_MAGIC: &[u8; 8] = b"MVVARNT1";
+
+/// Size of the hash of a variant
+const HASH_SIZE: usize = 32;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid variants: {message}"),
+    )
+}
+
+/// Returns the hash as a hexadecimal string, which is the name of the file of the variant in a directory
+fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Returns the path of the variants of the runner: a directory or an archive named like the runner with a `.variants` extension
+///
+/// The executable of the runner is resolved (e.g., a symbolic link to the runner is followed).
+fn variants_path() -> io::Result<PathBuf> {
+    let mut path = std::env::current_exe()?.into_os_string();
+    path.push(".variants");
+
+    Ok(path.into())
+}
+
+/// Adds the hash of a variant to an error that occurred while reading it
+fn context(hash: &[u8], error: io::Error) -> io::Error {
+    io::Error::new(
+        error.kind(),
+        format!(
+            "Failed to read the variant {} beside the runner: {error}",
+            to_hex(hash)
+        ),
+    )
+}
+
+/// Opens the variant with the given SHA3-256 hash beside the runner
+///
+/// The variant is hashed while it is read, so it is never stored entirely in memory,
+/// and the reader fails at its end if its hash differs (the bytes read until then must be discarded).
+pub(super) fn open(hash: &[u8; HASH_SIZE]) -> io::Result<impl Read> {
+    let variant = || -> io::Result<Box<dyn Read>> {
+        let path = variants_path()?;
+        if path.is_dir() {
+            Ok(Box::new(File::open(path.join(to_hex(hash)))?))
+        } else {
+            let mut archive = BufReader::new(File::open(&path)?);
+            let length = find_in_archive(&mut archive, hash)?;
+
+            Ok(Box::new(archive.take(length)))
+        }
+    };
+
+    Ok(Verified {
+        variant: variant().map_err(|error| context(hash, error))?,
+        hasher: Some(Sha3_256::new()),
+        hash: *hash,
+    })
+}
+
+/// A variant that is hashed while it is read
+struct Verified<R> {
+    variant: R,
+    /// The hash of the bytes read until then, or `None` once the end of the variant is reached and its hash checked
+    hasher: Option<Sha3_256>,
+    hash: [u8; HASH_SIZE],
+}
+
+impl<R: Read> Read for Verified<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(hasher) = &mut self.hasher else {
+            return Ok(0);
+        };
+
+        let read = self
+            .variant
+            .read(buf)
+            .map_err(|error| context(&self.hash, error))?;
+        if read > 0 {
+            hasher.update(buf.get(..read).unwrap_or_default());
+        } else if !buf.is_empty()
+            && self
+                .hasher
+                .take()
+                .is_some_and(|hasher| hasher.finalize().as_slice() != self.hash)
+        {
+            return Err(context(
+                &self.hash,
+                invalid("the hash of the variant differs"),
+            ));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Finds the variant with the given hash in an archive, and returns its length
+///
+/// The archive is then positioned at the start of the variant.
+fn find_in_archive(archive: &mut (impl Read + Seek), hash: &[u8]) -> io::Result<u64> {
+    let mut magic = [0; ARCHIVE_MAGIC.len()];
+    archive.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(invalid("not an archive of variants"));
+    }
+
+    loop {
+        let mut header = [0; HASH_SIZE + 8];
+        archive.read_exact(&mut header).map_err(|error| {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                io::Error::new(io::ErrorKind::NotFound, "not in the archive")
+            } else {
+                error
+            }
+        })?;
+        let (entry_hash, length) = header.split_at(HASH_SIZE);
+        let length = length
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| invalid("truncated header"))?;
+
+        if entry_hash == hash {
+            return Ok(length);
+        }
+
+        let length =
+            i64::try_from(length).map_err(|_| invalid("variant larger than the archive"))?;
+        archive.seek(SeekFrom::Current(length))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use sha3::{Digest, Sha3_256};
+
+    use super::{ARCHIVE_MAGIC, Verified, find_in_archive, to_hex};
+
+    #[test]
+    fn read_archive() {
+        let variants = [b"first variant".as_slice(), b"second variant"];
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        for variant in variants {
+            archive.extend_from_slice(&Sha3_256::digest(variant));
+            archive.extend_from_slice(&(variant.len() as u64).to_le_bytes());
+            archive.extend_from_slice(variant);
+        }
+
+        for variant in variants {
+            let hash = Sha3_256::digest(variant);
+            let mut archive = Cursor::new(&archive);
+            let length = find_in_archive(&mut archive, &hash).unwrap();
+            let mut data = Vec::new();
+            archive.take(length).read_to_end(&mut data).unwrap();
+            assert_eq!(data, variant);
+        }
+        let missing = Sha3_256::digest(b"missing variant");
+        assert!(find_in_archive(&mut Cursor::new(&archive), &missing).is_err());
+        assert!(find_in_archive(&mut Cursor::new(b"invalid archive"), &missing).is_err());
+    }
+
+    #[test]
+    fn verify_hash() {
+        let verified = |variant: &'static [u8], hashed: &[u8]| Verified {
+            variant,
+            hasher: Some(Sha3_256::new()),
+            hash: Sha3_256::digest(hashed).into(),
+        };
+
+        let mut data = Vec::new();
+        verified(b"variant", b"variant")
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"variant");
+
+        let error = verified(b"tampered", b"variant")
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("the hash of the variant differs")
+        );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex(&[0x00, 0x1f, 0xa0]), "001fa0");
+    }
+}
@@ -27,16 +27,18 @@ impl Executable for Build<'_> {
         #[cfg(unix)]
         builder.permissions(std::fs::Permissions::from_mode(0o700));
 
-        let mut file = builder.tempfile().map_err(|_| {
-            proc_exit::Code::FAILURE.with_message("Failed to create a temporary file")
-        })?;
-
-        self.extract_into(&mut file).map_err(|_| {
-            proc_exit::Code::FAILURE.with_message(format!(
-                "Failed to write the build to the temporary file `{}`",
-                file.path().display()
-            ))
-        })?;
+        let mut file =
+            crate::timings::measure("create_file", || builder.tempfile()).map_err(|_| {
+                proc_exit::Code::FAILURE.with_message("Failed to create a temporary file")
+            })?;
+
+        self.extract_and_report(|| self.extract_into(&mut file))
+            .map_err(|error| {
+                proc_exit::Code::FAILURE.with_message(format!(
+                    "Failed to write the build to the temporary file `{}`: {error}",
+                    file.path().display()
+                ))
+            })?;
 
         let path = file.into_temp_path();
 
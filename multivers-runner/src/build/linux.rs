@@ -1,15 +1,16 @@
 use std::convert::Infallible;
 use std::ffi::{CStr, c_char};
 use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
 use std::os::fd::{FromRawFd, IntoRawFd};
 
 use libc::fexecve;
 
-use rustix::fd::IntoRawFd as _;
-use rustix::fd::OwnedFd;
-use rustix::fs::{MemfdFlags, memfd_create};
+use rustix::fs::{MemfdFlags, copy_file_range, fstat, makedev, memfd_create, sendfile};
+use rustix::mm::{MapFlags, ProtFlags, mmap, munmap};
 
-use super::{Build, Executable};
+use super::{Build, Data, Executable};
+use crate::timings;
 
 impl Executable for Build<'_> {
     unsafe fn exec(
@@ -23,15 +24,28 @@ impl Executable for Build<'_> {
         } else {
             c""
         };
-        let mut file = memfd_create(memfd_name, MemfdFlags::CLOEXEC)
-            .map(OwnedFd::into_raw_fd)
-            .map(|fd| unsafe { File::from_raw_fd(fd) })
-            .map_err(|_| {
-                proc_exit::Code::FAILURE.with_message("Failed to create an anonymous memory file")
-            })?;
-        self.extract_into(&mut file).map_err(|_| {
-            proc_exit::Code::FAILURE
-                .with_message("Failed to write the build to an anonymous memory file")
+        let file = crate::timings::measure("create_file", || {
+            memfd_create(memfd_name, MemfdFlags::CLOEXEC)
+        })
+        .map(rustix::fd::IntoRawFd::into_raw_fd)
+        .map(|fd| unsafe { File::from_raw_fd(fd) })
+        .map_err(|_| {
+            proc_exit::Code::FAILURE.with_message("Failed to create an anonymous memory file")
+        })?;
+        self.extract_and_report(|| {
+            if let Data::Stored(data) = self.data
+                && self.is_source()
+                && crate::timings::measure("source_copy", || copy_from_executable(data, &file))?
+            {
+                return Ok(());
+            }
+
+            self.extract_into_file(&file)
+        })
+        .map_err(|error| {
+            proc_exit::Code::FAILURE.with_message(format!(
+                "Failed to write the build to an anonymous memory file: {error}"
+            ))
         })?;
 
         let fd = file.into_raw_fd();
@@ -59,3 +73,377 @@ impl Executable for Build<'_> {
         Err(proc_exit::Code::FAILURE.with_message(format!("Failed to execute the build: {error}")))
     }
 }
+
+impl Build<'_> {
+    /// Extracts the build into a file, which must be empty
+    ///
+    /// Unlike [`Build::extract_into`], a patched build is written over its decompressed source in the file,
+    /// so the runner needs about as much memory as the largest of the build and its sources,
+    /// instead of storing the decompressed source in memory while the build is written.
+    /// It is still patched from the source stored uncompressed in the runner, which is not copied.
+    fn extract_into_file(&self, file: &File) -> io::Result<()> {
+        match self.source {
+            Some((source, _)) if !matches!(source.data, Data::Stored(_)) => {
+                let length = self.extract_at(file, 0, "patch_apply")?;
+                file.set_len(length as u64)
+            }
+            _ => self.extract_into(file),
+        }
+    }
+
+    /// Writes the build at the given offset of the file, and returns its length
+    ///
+    /// A patched build is written over its source, which is written first (and so on up to the source of the runner),
+    /// at the offset found by [`Delta::plan`](super::delta::Delta::plan).
+    /// The bytes after the build in the file are left as is.
+    fn extract_at(&self, file: &File, offset: usize, phase: &'static str) -> io::Result<usize> {
+        let Some((source, delta)) = self.source else {
+            return timings::measure(self.data.read_phase(), || {
+                let mut output = file;
+                output.seek(SeekFrom::Start(offset as u64))?;
+                let length = io::copy(&mut self.data.reader()?, &mut output)?;
+
+                usize::try_from(length).map_err(|_| io::ErrorKind::FileTooLarge.into())
+            });
+        };
+
+        let plan = timings::measure("patch_plan", || delta.plan(self.data.reader()?))?;
+        let source_offset = offset
+            .checked_add(plan.source_offset())
+            .ok_or(io::ErrorKind::FileTooLarge)?;
+        let source_length = source.extract_at(file, source_offset, "parent_patch_apply")?;
+
+        timings::measure(phase, || {
+            let end = offset
+                .checked_add(plan.len(source_length))
+                .ok_or(io::ErrorKind::FileTooLarge)?;
+            if file.metadata()?.len() < end as u64 {
+                file.set_len(end as u64)?;
+            }
+
+            let mut mapping = Mapping::new(file, end)?;
+            let build = mapping.as_mut_slice().get_mut(offset..).unwrap_or_default();
+            delta.apply_in_place(self.data.reader()?, &plan, build, source_length)
+        })?;
+
+        Ok(plan.length())
+    }
+}
+
+/// The start of a file mapped in memory, which is unmapped when dropped
+struct Mapping {
+    address: *mut std::ffi::c_void,
+    length: usize,
+}
+
+impl Mapping {
+    /// Maps the given number of bytes from the start of the file, to read and write them
+    fn new(file: &File, length: usize) -> io::Result<Self> {
+        let address = if length == 0 {
+            std::ptr::NonNull::dangling().as_ptr()
+        } else {
+            unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    length,
+                    ProtFlags::READ | ProtFlags::WRITE,
+                    MapFlags::SHARED,
+                    file,
+                    0,
+                )?
+            }
+        };
+
+        Ok(Self { address, length })
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.address.cast(), self.length) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.length > 0 {
+            let _ = unsafe { munmap(self.address, self.length) };
+        }
+    }
+}
+
+/// Copies bytes of the runner executable that are mapped in memory (e.g., a static) to a file,
+/// without reading them in user space
+///
+/// Returns `Ok(false)` if nothing was copied, because the bytes are not mapped from the executable
+/// or because the kernel cannot copy them.
+fn copy_from_executable(data: &[u8], output: &File) -> io::Result<bool> {
+    // `/proc/self/exe` is the executed file, even if it was replaced or deleted since then
+    let Ok(executable) = File::open("/proc/self/exe") else {
+        return Ok(false);
+    };
+    let Some(mut offset) = fstat(&executable)
+        .ok()
+        .and_then(|stat| file_offset(data, stat.st_dev, stat.st_ino))
+    else {
+        return Ok(false);
+    };
+
+    let mut remaining = data.len();
+    let mut use_sendfile = false;
+    while remaining > 0 {
+        let copied = if use_sendfile {
+            sendfile(output, &executable, Some(&mut offset), remaining)
+        } else {
+            copy_file_range(&executable, Some(&mut offset), output, None, remaining)
+        };
+
+        match copied {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(copied) => remaining = remaining.saturating_sub(copied),
+            // `copy_file_range` fails between files of different file systems (since Linux 5.19),
+            // `sendfile` does not, but it can still fail (e.g., before Linux 2.6.33)
+            Err(_) if remaining == data.len() && !use_sendfile => use_sendfile = true,
+            Err(_) if remaining == data.len() => return Ok(false),
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns the offset of bytes mapped in memory in the file with the given device and inode numbers,
+/// or `None` if they are not mapped from this file
+fn file_offset(data: &[u8], device: u64, inode: u64) -> Option<u64> {
+    let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+
+    find_offset(&maps, data, device, inode)
+}
+
+/// Finds the offset of bytes mapped in memory in the file with the given device and inode numbers,
+/// from the content of `/proc/self/maps`
+fn find_offset(maps: &str, data: &[u8], device: u64, inode: u64) -> Option<u64> {
+    let start_address = u64::try_from(data.as_ptr().addr()).ok()?;
+    let end_address = start_address.checked_add(u64::try_from(data.len()).ok()?)?;
+    let hex = |value: &str| u64::from_str_radix(value, 16).ok();
+
+    // Each line is `start-end perms offset major:minor inode path`
+    maps.lines().find_map(|line| {
+        let mut fields = line.split_ascii_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let offset = fields.nth(1)?;
+        let (major, minor) = fields.next()?.split_once(':')?;
+        let mapped_inode = fields.next()?.parse::<u64>().ok()?;
+
+        let (start, end, offset) = (hex(start)?, hex(end)?, hex(offset)?);
+        let major = u32::from_str_radix(major, 16).ok()?;
+        let minor = u32::from_str_radix(minor, 16).ok()?;
+
+        let is_mapped = start <= start_address
+            && end_address <= end
+            && mapped_inode == inode
+            && makedev(major, minor) == device;
+        if !is_mapped {
+            return None;
+        }
+
+        offset.checked_add(start_address - start)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::io::Read;
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    use std::io::Write;
+
+    use super::*;
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    use crate::build::delta::Delta;
+
+    /// Counts the bytes allocated by each thread, to check the memory used by the runner
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+        static PEAK: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| {
+                allocated.set(allocated.get() + layout.size());
+                PEAK.with(|peak| peak.set(peak.get().max(allocated.get())));
+            });
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = ALLOCATED
+                .try_with(|allocated| allocated.set(allocated.get().saturating_sub(layout.size())));
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Returns the peak number of bytes allocated by the current thread while the function runs
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn peak_allocated(f: impl FnOnce()) -> usize {
+        let start = ALLOCATED.with(Cell::get);
+        PEAK.with(|peak| peak.set(start));
+        f();
+
+        PEAK.with(Cell::get) - start
+    }
+
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_memfd(mut file: &File) -> Vec<u8> {
+        let mut data = vec![];
+        file.rewind().unwrap();
+        file.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Bytes stored in the executable of the tests
+    static DATA: [u8; 8192] = [0x5a; 8192];
+
+    #[test]
+    fn find_offset_in_maps() {
+        let data = &DATA[..];
+        let address = data.as_ptr().addr();
+        let maps = format!(
+            "00400000-00401000 r--p 00000000 fd:01 42 /usr/bin/other\n\
+            {:x}-{:x} r--p 00002000 fd:01 1234 /usr/bin/runner\n",
+            address - 0x100,
+            address + data.len()
+        );
+        let device = makedev(0xfd, 0x01);
+
+        assert_eq!(find_offset(&maps, data, device, 1234), Some(0x2100));
+        assert_eq!(find_offset(&maps, data, device, 42), None);
+        assert_eq!(find_offset(&maps, data, makedev(0xfd, 0x02), 1234), None);
+        let other = [0; 1];
+        assert_eq!(find_offset(&maps, &other, device, 1234), None);
+    }
+
+    #[test]
+    fn copy_static_from_executable() {
+        let output = File::from(memfd_create(c"test", MemfdFlags::CLOEXEC).unwrap());
+
+        assert!(copy_from_executable(&DATA, &output).unwrap());
+        assert_eq!(read_memfd(&output), DATA);
+    }
+
+    #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn extract_into_file_chain() {
+        let source_data = b"source data that will be patched twice".repeat(1000);
+        let mut parent_data = source_data.clone();
+        parent_data.splice(5000..5000, b"with inserted data".iter().copied());
+        parent_data.extend_from_slice(&source_data[..2000]);
+        let mut expected_data = parent_data[1000..].to_vec();
+        expected_data.splice(0..0, b"with more data".iter().copied());
+
+        let compressed_source = compress(&source_data);
+        let compressed_parent = compress(&gdelta::encode(&parent_data, &source_data).unwrap());
+        let mut patch = Vec::new();
+        bsdiff::diff(&parent_data, &expected_data, &mut patch).unwrap();
+        let compressed_patch = compress(&patch);
+
+        let source = Build {
+            data: Data::Compressed(&compressed_source),
+            features: &[],
+            cpus: &[],
+            source: None,
+        };
+        let parent = Build {
+            data: Data::Compressed(&compressed_parent),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Gdelta)),
+        };
+        let build = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&parent, Delta::Bsdiff)),
+        };
+
+        for (build, expected_data) in [(parent, &parent_data), (build, &expected_data)] {
+            let file = File::from(memfd_create(c"test", MemfdFlags::CLOEXEC).unwrap());
+            build.extract_into_file(&file).unwrap();
+            assert_eq!(&read_memfd(&file), expected_data);
+        }
+
+        let invalid = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Bsdiff)),
+        };
+        let file = File::from(memfd_create(c"test", MemfdFlags::CLOEXEC).unwrap());
+        invalid.extract_into_file(&file).unwrap_err();
+    }
+
+    #[test]
+    #[cfg(all(not(feature = "stub"), feature = "lz4"))]
+    fn extract_into_file_memory() {
+        // Pseudo-random bytes, so that the patch has many instructions
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let source_data = std::iter::repeat_with(|| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_le_bytes()
+        })
+        .take(512 * 1024)
+        .flatten()
+        .collect::<Vec<_>>();
+        // The build grows and shrinks in places, and copies the start of the source at its end
+        let mut expected_data = Vec::new();
+        for (i, chunk) in source_data.chunks(64 * 1024).enumerate() {
+            let skipped = if i % 3 == 0 { 100 } else { 0 };
+            expected_data.extend_from_slice(&chunk[skipped..]);
+            expected_data.extend_from_slice(&b"inserted data".repeat(i % 5 * 10));
+        }
+        expected_data.extend_from_slice(&source_data[..64 * 1024]);
+
+        let compressed_source = compress(&source_data);
+        let compressed_patch = compress(&gdelta::encode(&expected_data, &source_data).unwrap());
+        let source = Build {
+            data: Data::Compressed(&compressed_source),
+            features: &[],
+            cpus: &[],
+            source: None,
+        };
+        let build = Build {
+            data: Data::Compressed(&compressed_patch),
+            features: &[],
+            cpus: &[],
+            source: Some((&source, Delta::Gdelta)),
+        };
+
+        // The decoders have buffers of the size of their blocks, even if the build is written while it is decompressed
+        let decoder_peak = peak_allocated(|| source.extract_into(io::sink()).unwrap());
+
+        let file = File::from(memfd_create(c"test", MemfdFlags::CLOEXEC).unwrap());
+        let peak = peak_allocated(|| build.extract_into_file(&file).unwrap());
+        assert_eq!(read_memfd(&file), expected_data);
+        assert!(
+            peak < decoder_peak + source_data.len() / 8,
+            "{peak} bytes allocated to extract a build of {} bytes ({decoder_peak} bytes to decompress its source)",
+            expected_data.len()
+        );
+
+        let peak = peak_allocated(|| build.extract_into(io::sink()).unwrap());
+        assert!(peak >= decoder_peak + source_data.len());
+    }
+}
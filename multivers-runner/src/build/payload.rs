@@ -0,0 +1,287 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use super::compression::Compression;
+use super::delta::Delta;
+use super::{Build, Data, Embedded};
+use crate::rule::Rule;
+
+/// Bytes that end a runner stub to which `cargo multivers --runner-stub` appended a payload
+const MAGIC: &[u8; 8] = b"MVPAYLD1";
+
+/// Size of the trailer that ends the stub: the length of the payload, the length of the manifest, and [`MAGIC`]
+const TRAILER_SIZE: u64 = 24;
+
+/// A build stored in the payload
+#[derive(Deserialize)]
+struct BuildEntry {
+    /// Offset of the build in the payload
+    offset: usize,
+    length: usize,
+    stored: bool,
+    features: Vec<String>,
+    cpus: Vec<String>,
+    #[serde(default)]
+    patch: Option<PatchEntry>,
+}
+
+/// How a build is patched from its parent
+#[derive(Deserialize)]
+struct PatchEntry {
+    /// Index of the parent in the patched builds, or `None` if it is the source
+    parent: Option<usize>,
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct RuleEntry {
+    #[serde(default)]
+    vendor: Option<String>,
+    #[serde(default)]
+    family: Option<u32>,
+    #[serde(default)]
+    models: Vec<u32>,
+    prefer: String,
+    over: String,
+}
+
+/// Describes the builds of the payload and the selection rules
+#[derive(Deserialize)]
+struct Manifest {
+    compression: Compression,
+    source: BuildEntry,
+    patches: Vec<BuildEntry>,
+    rules: Vec<RuleEntry>,
+}
+
+/// Returns the payload appended to the executable of the runner, reading it the first time
+///
+/// The runner exits with an error if the payload cannot be read.
+pub(super) fn payload() -> &'static Embedded {
+    static PAYLOAD: OnceLock<Embedded> = OnceLock::new();
+
+    PAYLOAD.get_or_init(|| {
+        crate::timings::measure("payload_read", || {
+            let mut executable = File::open(std::env::current_exe()?)?;
+
+            read_from(&mut executable)
+        })
+        .unwrap_or_else(|error| {
+            proc_exit::exit(Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Failed to read the builds appended to the runner: {error}"
+            ))))
+        })
+    })
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid payload: {message}"),
+    )
+}
+
+/// Leaks strings, to get the `'static` slices stored in a [`Build`]
+fn leak_strings(strings: &[String]) -> &'static [&'static str] {
+    strings
+        .iter()
+        .map(|string| &*Box::leak(string.clone().into_boxed_str()))
+        .collect::<Vec<_>>()
+        .leak()
+}
+
+/// Reads the payload at the end of a file
+fn read_from(file: &mut (impl Read + Seek)) -> io::Result<Embedded> {
+    let trailer_start = file
+        .seek(SeekFrom::End(0))?
+        .checked_sub(TRAILER_SIZE)
+        .ok_or_else(|| invalid("the runner stub has no payload"))?;
+    file.seek(SeekFrom::Start(trailer_start))?;
+    let mut trailer = [0; TRAILER_SIZE as usize];
+    file.read_exact(&mut trailer)?;
+    let &[data_length, manifest_length, magic] = trailer.as_chunks::<8>().0 else {
+        return Err(invalid("truncated trailer"));
+    };
+    if &magic != MAGIC {
+        return Err(invalid("the runner stub has no payload"));
+    }
+
+    let lengths = [data_length, manifest_length].map(u64::from_le_bytes);
+    let start = lengths
+        .iter()
+        .try_fold(trailer_start, |start, &length| start.checked_sub(length))
+        .ok_or_else(|| invalid("lengths larger than the runner"))?;
+    let [data_length, manifest_length] = lengths
+        .map(usize::try_from)
+        .map(|length| length.map_err(|_| invalid("lengths larger than the memory")));
+
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = vec![0; data_length?];
+    file.read_exact(&mut data)?;
+    let mut manifest = vec![0; manifest_length?];
+    file.read_exact(&mut manifest)?;
+    let manifest = serde_json::from_slice(&manifest)
+        .map_err(|error| invalid(&format!("invalid manifest ({error})")))?;
+
+    leak_builds(manifest, data.leak())
+}
+
+/// Creates the builds described by the manifest from the data of the payload
+fn leak_builds(manifest: Manifest, data: &'static [u8]) -> io::Result<Embedded> {
+    let data = |entry: &BuildEntry| {
+        let bytes = entry
+            .offset
+            .checked_add(entry.length)
+            .and_then(|end| data.get(entry.offset..end))
+            .ok_or_else(|| invalid("build outside of the payload"))?;
+
+        Ok::<_, io::Error>(if entry.stored {
+            Data::Stored(bytes)
+        } else {
+            Data::Compressed(bytes)
+        })
+    };
+
+    let source: &'static Build<'static> = Box::leak(Box::new(Build {
+        data: data(&manifest.source)?,
+        features: leak_strings(&manifest.source.features),
+        cpus: leak_strings(&manifest.source.cpus),
+        source: None,
+    }));
+
+    // A patched build points to its parent, so the parents are leaked first
+    let mut patches = vec![None; manifest.patches.len()];
+    while patches.iter().any(Option::is_none) {
+        let mut progress = false;
+
+        for (i, entry) in manifest.patches.iter().enumerate() {
+            let patch = entry
+                .patch
+                .as_ref()
+                .ok_or_else(|| invalid("a build other than the source is not a patch"))?;
+            let parent = match patch.parent {
+                Some(parent) => patches.get(parent).copied().flatten(),
+                None => Some(source),
+            };
+            let (Some(leaked @ None), Some(parent)) = (patches.get_mut(i), parent) else {
+                continue;
+            };
+
+            *leaked = Some(&*Box::leak(Box::new(Build {
+                data: data(entry)?,
+                features: leak_strings(&entry.features),
+                cpus: leak_strings(&entry.cpus),
+                source: Some((parent, patch.delta)),
+            })));
+            progress = true;
+        }
+
+        if !progress {
+            return Err(invalid("a build is not patched from the source"));
+        }
+    }
+    let patches = patches
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>()
+        .leak();
+
+    let rules = manifest
+        .rules
+        .iter()
+        .map(|rule| Rule {
+            vendor: rule
+                .vendor
+                .as_ref()
+                .map(|vendor| &*Box::leak(vendor.clone().into_boxed_str())),
+            family: rule.family,
+            models: rule.models.clone().leak(),
+            prefer: Box::leak(rule.prefer.clone().into_boxed_str()),
+            over: Box::leak(rule.over.clone().into_boxed_str()),
+        })
+        .collect::<Vec<_>>()
+        .leak();
+
+    Ok(Embedded {
+        compression: manifest.compression,
+        source,
+        patches,
+        rules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{MAGIC, read_from};
+    use crate::build::Data;
+    use crate::build::compression::Compression;
+    use crate::build::delta::Delta;
+
+    /// Appends a payload to a fake runner stub, as `cargo multivers` does
+    fn stub_with_payload(data: &[u8], manifest: &str) -> Cursor<Vec<u8>> {
+        let mut stub = b"runner stub".to_vec();
+        stub.extend_from_slice(data);
+        stub.extend_from_slice(manifest.as_bytes());
+        stub.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        stub.extend_from_slice(&(manifest.len() as u64).to_le_bytes());
+        stub.extend_from_slice(MAGIC);
+
+        Cursor::new(stub)
+    }
+
+    #[test]
+    fn read_payload() {
+        let data = b"sourcepatchparent patch";
+        let manifest = r#"{
+            "compression": "none",
+            "source": {"offset": 0, "length": 6, "stored": true, "features": [], "cpus": ["x86-64"]},
+            "patches": [
+                {"offset": 6, "length": 5, "stored": false, "features": ["avx2", "fma"], "cpus": ["x86-64-v3"],
+                    "patch": {"parent": 1, "delta": "gdelta"}},
+                {"offset": 11, "length": 12, "stored": false, "features": ["avx"], "cpus": ["sandybridge"],
+                    "patch": {"parent": null, "delta": "bsdiff"}}
+            ],
+            "rules": [{"vendor": null, "family": null, "models": [], "prefer": "sandybridge", "over": "x86-64-v3"}]
+        }"#;
+        let embedded = read_from(&mut stub_with_payload(data, manifest)).unwrap();
+
+        assert_eq!(embedded.compression, Compression::None);
+        assert!(embedded.source.is_source());
+        assert_eq!(embedded.source.data, Data::Stored(b"source"));
+        assert_eq!(embedded.source.cpus(), ["x86-64"]);
+
+        let [patch, parent] = embedded.patches else {
+            panic!("expected two patched builds");
+        };
+        assert_eq!(patch.data, Data::Compressed(b"patch"));
+        assert_eq!(patch.features(), ["avx2", "fma"]);
+        assert_eq!(patch.source, Some((parent, Delta::Gdelta)));
+        assert_eq!(parent.data, Data::Compressed(b"parent patch"));
+        assert_eq!(parent.source, Some((embedded.source, Delta::Bsdiff)));
+
+        assert_eq!(
+            embedded.rules.first().map(|rule| (rule.prefer, rule.over)),
+            Some(("sandybridge", "x86-64-v3"))
+        );
+    }
+
+    #[test]
+    fn read_invalid_payload() {
+        assert!(read_from(&mut Cursor::new(b"runner stub".to_vec())).is_err());
+
+        let manifest = r#"{"compression": "none", "source": {"offset": 0, "length": 1, "stored": true, "features": [], "cpus": []},
+            "patches": [{"offset": 0, "length": 1, "stored": false, "features": [], "cpus": [], "patch": {"parent": 0, "delta": "gdelta"}}],
+            "rules": []}"#;
+        assert!(read_from(&mut stub_with_payload(b"a", manifest)).is_err());
+
+        let manifest = r#"{"compression": "none", "source": {"offset": 0, "length": 2, "stored": true, "features": [], "cpus": []},
+            "patches": [], "rules": []}"#;
+        assert!(read_from(&mut stub_with_payload(b"a", manifest)).is_err());
+    }
+}
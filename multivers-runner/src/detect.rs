@@ -0,0 +1,192 @@
+//! Detection of the CPU features supported by the host.
+//!
+//! The `std::arch::is_{arch}_feature_detected!` macros only know a subset of the CPU features `rustc` can
+//! compile for, so the runner combines several backends:
+//! - [`Std`]: the `std::arch` macros,
+//! - [`Cpuid`]: the `CPUID` and `XGETBV` instructions (x86 and x86-64 only),
+//! - [`Hwcap`]: the `AT_HWCAP` and `AT_HWCAP2` entries of the auxiliary vector (Linux on AArch64, RISC-V, and PowerPC only),
+//! - [`CpuInfo`]: `/proc/cpuinfo` (Linux only).
+//!
+//! [`DefaultDetector`] asks each of them in this order, and the first one that knows a feature decides whether it is supported.
+//! A feature that no backend knows is considered not supported, so a build requiring it is never executed.
+//!
+//! With the `all-cpus` feature, on Linux, [`AllCpus`] checks the features on all the CPUs of the host
+//! instead of only the one the runner starts on.
+//!
+//! [`CpuModel`] identifies the CPU of the host, to evaluate the selection rules (see [`Rule`](crate::Rule))
+//! and to select the build tuned for it.
+
+/// A backend that detects whether the CPU of the host supports a CPU feature.
+pub trait Detector {
+    /// Returns whether the CPU of the host supports the given feature (e.g., `"avx2"`),
+    /// or `None` if the backend cannot detect this feature.
+    ///
+    /// The feature is named as in `rustc --print target-features`.
+    fn detect(&self, feature: &str) -> Option<bool>;
+}
+
+impl<D: Detector + ?Sized> Detector for &D {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        (**self).detect(feature)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/std_detect.rs"));
+
+/// The features known by each backend, shared with the build script and `cargo-multivers` (see `build/detection.rs`)
+#[allow(
+    dead_code,
+    reason = "the build script and `cargo-multivers` use the other tables"
+)]
+mod tables {
+    include!("../build/detection.rs");
+}
+
+/// Detects CPU features with the `std::arch::is_{arch}_feature_detected!` macros
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Std;
+
+impl Detector for Std {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        std_is_feature_detected(feature)
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
+        mod cpuid;
+
+        pub use cpuid::Cpuid;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        target_os = "linux",
+        any(
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "powerpc64"
+        )
+    ))] {
+        mod hwcap;
+
+        pub use hwcap::Hwcap;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        mod cpuinfo;
+
+        pub use cpuinfo::CpuInfo;
+    }
+}
+
+mod model;
+
+pub use model::CpuModel;
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "all-cpus", target_os = "linux"))] {
+        mod all_cpus;
+
+        pub use all_cpus::AllCpus;
+    }
+}
+
+/// Detects CPU features with all the backends available on the host, in order of reliability
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultDetector;
+
+impl Detector for DefaultDetector {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        let detected = Std.detect(feature);
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let detected = detected.or_else(|| Cpuid.detect(feature));
+
+        #[cfg(all(
+            target_os = "linux",
+            any(
+                target_arch = "aarch64",
+                target_arch = "riscv64",
+                target_arch = "powerpc64"
+            )
+        ))]
+        let detected = detected.or_else(|| Hwcap.detect(feature));
+
+        #[cfg(target_os = "linux")]
+        let detected = detected.or_else(|| CpuInfo.detect(feature));
+
+        #[cfg(feature = "debug")]
+        if detected.is_none() {
+            log::debug!(
+                "CPU feature `{feature}` cannot be detected, it is considered not supported"
+            );
+        }
+
+        detected
+    }
+}
+
+/// Returns true if the CPU of the host supports the given feature, using [`DefaultDetector`]
+pub fn is_feature_detected(feature: &str) -> bool {
+    DefaultDetector.detect(feature).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DefaultDetector, Detector};
+
+    #[test]
+    fn unknown_feature() {
+        assert_eq!(DefaultDetector.detect("unknown feature"), None);
+        assert!(!super::is_feature_detected("unknown feature"));
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn cpuid_agrees_with_std() {
+        use super::{Cpuid, Std};
+
+        for feature in [
+            "sse2", "sse4.2", "avx", "avx2", "bmi2", "fma", "avx512f", "lzcnt",
+        ] {
+            assert_eq!(Cpuid.detect(feature), Std.detect(feature), "{feature}");
+        }
+    }
+
+    #[cfg(all(feature = "all-cpus", target_os = "linux"))]
+    #[test]
+    fn all_cpus_subset_of_default() {
+        use super::AllCpus;
+
+        let features = ["sse2", "avx2", "neon", "unknown feature"];
+        let all_cpus = AllCpus::new(features);
+        for feature in features {
+            let detected = all_cpus.detect(feature);
+            assert!(detected.is_some(), "{feature}");
+            if detected == Some(true) {
+                assert_eq!(DefaultDetector.detect(feature), Some(true), "{feature}");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cpuinfo_agrees_with_default() {
+        use super::CpuInfo;
+
+        let feature = if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+            "sse2"
+        } else if cfg!(target_arch = "aarch64") {
+            "neon"
+        } else {
+            return;
+        };
+        if let Some(detected) = CpuInfo.detect(feature) {
+            assert_eq!(Some(detected), DefaultDetector.detect(feature));
+        }
+    }
+}
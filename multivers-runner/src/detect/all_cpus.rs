@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+use super::{DefaultDetector, Detector, cpuinfo};
+
+/// Detects the CPU features supported by all the CPUs the process can run on
+///
+/// On heterogeneous systems (e.g., big.LITTLE), the CPUs may not support the same features, and a build
+/// selected on one CPU could crash after the process migrates to another one.
+/// A feature is only considered supported if:
+/// - [`DefaultDetector`] detects it on the current CPU,
+/// - `/proc/cpuinfo` lists it for every CPU (when it lists features),
+/// - on x86, `CPUID` reports it on every CPU of the affinity mask of the process (by pinning the process to each of them).
+///
+/// If the process cannot be pinned to one of these CPUs, its features are unknown, so none is considered supported.
+///
+/// On the other architectures, the process is not pinned to each CPU: their backends do not read the features of the current CPU,
+/// `AT_HWCAP` reports the features of all the CPUs (e.g., Linux only reports the features common to all the CPUs of a big.LITTLE
+/// system on aarch64), and `/proc/cpuinfo` is already checked for every CPU. Where `/proc/cpuinfo` does not list the features
+/// of each CPU, only the features reported for the whole system are checked.
+///
+/// The features are detected once, when [`AllCpus`] is created.
+#[derive(Clone, Debug, Default)]
+pub struct AllCpus<'a> {
+    features: BTreeMap<&'a str, bool>,
+}
+
+impl<'a> AllCpus<'a> {
+    /// Detects the given features on all the CPUs
+    pub fn new(features: impl IntoIterator<Item = &'a str>) -> Self {
+        let features = features
+            .into_iter()
+            .map(|feature| {
+                let detected = DefaultDetector.detect(feature).unwrap_or(false)
+                    && cpuinfo::detect_on_all_cpus(feature).unwrap_or(true);
+
+                (feature, detected)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let features = {
+            let mut features = features;
+            let pinned = on_each_cpu(|| {
+                for (feature, detected) in &mut features {
+                    *detected &= super::Cpuid.detect(feature).unwrap_or(true);
+                }
+            });
+            if !pinned {
+                #[cfg(feature = "debug")]
+                log::debug!(
+                    "The process cannot be pinned to each of its CPUs, no CPU feature is considered supported"
+                );
+
+                features.values_mut().for_each(|detected| *detected = false);
+            }
+            features
+        };
+
+        Self { features }
+    }
+}
+
+impl Detector for AllCpus<'_> {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        self.features.get(feature).copied()
+    }
+}
+
+/// Calls a function while the process is pinned to each CPU of its affinity mask, then restores the mask
+///
+/// Returns false if the affinity mask cannot be read, or if the process cannot be pinned to one of its CPUs.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn on_each_cpu(mut f: impl FnMut()) -> bool {
+    use libc::{CPU_ISSET, CPU_SET, CPU_SETSIZE, cpu_set_t, sched_getaffinity, sched_setaffinity};
+
+    let size = std::mem::size_of::<cpu_set_t>();
+
+    // SAFETY: `cpu_set_t` is a bit mask, all zeros is a valid (empty) set
+    let mut affinity: cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: `affinity` is valid for writes of `size` bytes
+    if unsafe { sched_getaffinity(0, size, &mut affinity) } != 0 {
+        return false;
+    }
+
+    let mut pinned_all = true;
+
+    for cpu in 0..usize::try_from(CPU_SETSIZE).unwrap_or_default() {
+        #[allow(unused_unsafe)]
+        // SAFETY: `cpu` is lower than `CPU_SETSIZE`
+        if !unsafe { CPU_ISSET(cpu, &affinity) } {
+            continue;
+        }
+
+        // SAFETY: see above
+        let mut pinned: cpu_set_t = unsafe { std::mem::zeroed() };
+        #[allow(unused_unsafe)]
+        // SAFETY: see above
+        unsafe {
+            CPU_SET(cpu, &mut pinned)
+        };
+
+        // SAFETY: `pinned` is valid for reads of `size` bytes
+        if unsafe { sched_setaffinity(0, size, &pinned) } == 0 {
+            f();
+        } else {
+            pinned_all = false;
+        }
+    }
+
+    // SAFETY: `affinity` is valid for reads of `size` bytes
+    unsafe { sched_setaffinity(0, size, &affinity) };
+
+    pinned_all
+}
@@ -0,0 +1,132 @@
+#[cfg(target_arch = "x86")]
+use std::arch::x86::{__cpuid_count, __get_cpuid_max, _xgetbv, CpuidResult};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__cpuid_count, __get_cpuid_max, _xgetbv, CpuidResult};
+
+use super::tables::{CPUID_FEATURES, CpuidRegister, OsState};
+use super::{CpuModel, Detector};
+
+/// Reads a register returned by `CPUID`
+const fn read(register: CpuidRegister, result: &CpuidResult) -> u32 {
+    match register {
+        CpuidRegister::Eax => result.eax,
+        CpuidRegister::Ebx => result.ebx,
+        CpuidRegister::Ecx => result.ecx,
+        CpuidRegister::Edx => result.edx,
+    }
+}
+
+/// Detects CPU features with the `CPUID` instruction, and checks with `XGETBV` that the OS supports
+/// the registers they use
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cpuid;
+
+impl Cpuid {
+    /// Returns the registers of a `CPUID` leaf, or `None` if the CPU does not have this leaf
+    fn cpuid(leaf: u32, sub_leaf: u32) -> Option<CpuidResult> {
+        let extended = leaf & 0x8000_0000;
+
+        #[allow(unused_unsafe)]
+        // SAFETY: all x86 and x86-64 CPUs supported by Rust have the CPUID instruction
+        let (max_leaf, _) = unsafe { __get_cpuid_max(extended) };
+        if leaf > max_leaf {
+            return None;
+        }
+
+        if sub_leaf > 0 && leaf != 0xd {
+            // For the other leaves with sub-leaves, EAX of sub-leaf 0 is the highest sub-leaf
+            #[allow(unused_unsafe)]
+            // SAFETY: see above
+            let max_sub_leaf = unsafe { __cpuid_count(leaf, 0) }.eax;
+            if sub_leaf > max_sub_leaf {
+                return None;
+            }
+        }
+
+        #[allow(unused_unsafe)]
+        // SAFETY: see above
+        let result = unsafe { __cpuid_count(leaf, sub_leaf) };
+
+        Some(result)
+    }
+
+    /// Returns true if the OS saves and restores the given register state on context switches
+    fn os_supports(os_state: OsState) -> bool {
+        let mask = os_state.xcr0_mask();
+        if mask == 0 {
+            return true;
+        }
+
+        // OSXSAVE: the OS enabled XSAVE, so XGETBV can be used to read XCR0
+        let osxsave = Self::cpuid(0x1, 0).is_some_and(|result| result.ecx & (1 << 27) != 0);
+        if !osxsave {
+            return false;
+        }
+
+        #[target_feature(enable = "xsave")]
+        unsafe fn xcr0() -> u64 {
+            unsafe { _xgetbv(0) }
+        }
+
+        // SAFETY: XGETBV is supported since OSXSAVE is set
+        let xcr0 = unsafe { xcr0() };
+
+        xcr0 & mask == mask
+    }
+
+    /// Returns the vendor, family, and model of the CPU
+    pub(super) fn model() -> Option<CpuModel> {
+        let vendor = Self::cpuid(0x0, 0)?;
+        let vendor = [vendor.ebx, vendor.edx, vendor.ecx]
+            .iter()
+            .flat_map(|register| register.to_le_bytes())
+            .collect::<Vec<_>>();
+        let vendor = String::from_utf8_lossy(&vendor).into_owned();
+
+        let signature = Self::cpuid(0x1, 0)?.eax;
+        let stepping = signature & 0xf;
+        let mut family = (signature >> 8) & 0xf;
+        let mut model = (signature >> 4) & 0xf;
+        if family == 0xf {
+            family += (signature >> 20) & 0xff;
+        }
+        if family == 0x6 || family >= 0xf {
+            model += ((signature >> 16) & 0xf) << 4;
+        }
+
+        Some(CpuModel {
+            vendor,
+            family,
+            model,
+            stepping,
+        })
+    }
+
+    /// Returns the AVX10 version supported by the CPU (0 if AVX10 is not supported)
+    fn avx10_version() -> u32 {
+        let avx10 = Self::cpuid(0x7, 1).is_some_and(|result| result.edx & (1 << 19) != 0);
+        if !avx10 || !Self::os_supports(OsState::Avx512) {
+            return 0;
+        }
+
+        Self::cpuid(0x24, 0).map_or(0, |result| result.ebx & 0xff)
+    }
+}
+
+impl Detector for Cpuid {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        if let Some(version) = feature.strip_prefix("avx10.") {
+            let version = version.parse().ok()?;
+
+            return Some(Self::avx10_version() >= version);
+        }
+
+        let feature = CPUID_FEATURES.iter().find(|f| f.name == feature)?;
+
+        let supported = Self::cpuid(feature.leaf, feature.sub_leaf)
+            .is_some_and(|result| read(feature.register, &result) & (1 << feature.bit) != 0)
+            && Self::os_supports(feature.os_state);
+
+        Some(supported)
+    }
+}
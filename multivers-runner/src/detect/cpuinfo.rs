@@ -0,0 +1,92 @@
+use std::env::consts::ARCH;
+use std::sync::OnceLock;
+
+use super::{Detector, tables};
+
+/// Returns the names the Linux kernel uses for a feature named as in `rustc --print target-features`
+pub(super) fn kernel_names(feature: &str) -> Option<Vec<&str>> {
+    tables::kernel_names(ARCH, feature)
+}
+
+/// Returns the features listed for each CPU in `/proc/cpuinfo`, or `None` if they cannot be read
+fn cpus() -> Option<&'static [Vec<String>]> {
+    static CPUS: OnceLock<Option<Vec<Vec<String>>>> = OnceLock::new();
+
+    CPUS.get_or_init(|| {
+        if tables::cpuinfo_features_key(ARCH).is_empty() {
+            return None;
+        }
+
+        let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+        let cpus = parse_cpus(&cpuinfo);
+
+        (!cpus.is_empty()).then_some(cpus)
+    })
+    .as_deref()
+}
+
+/// Parses the features listed for each CPU in `/proc/cpuinfo`
+fn parse_cpus(cpuinfo: &str) -> Vec<Vec<String>> {
+    let features_key = tables::cpuinfo_features_key(ARCH);
+
+    cpuinfo
+        .lines()
+        .filter_map(|line| {
+            line.split_once(':')
+                .filter(|(key, _)| key.trim() == features_key)
+        })
+        .map(|(_, value)| parse_features(value))
+        .collect()
+}
+
+/// Parses the value of the line that lists the features of a CPU
+fn parse_features(value: &str) -> Vec<String> {
+    let value = value.trim().to_ascii_lowercase();
+
+    if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+        // e.g., `rv64imafdc_zicsr_zifencei`: single-letter extensions, then multi-letter ones
+        let value = value.trim_start_matches("rv32").trim_start_matches("rv64");
+        let mut extensions = value.split('_');
+        let single_letter = extensions.next().unwrap_or_default();
+
+        single_letter
+            .chars()
+            .map(String::from)
+            .chain(extensions.map(String::from))
+            .collect()
+    } else {
+        value.split_whitespace().map(String::from).collect()
+    }
+}
+
+/// Returns true if the features of a CPU include all the given names
+fn has_all(features: &[String], names: &[&str]) -> bool {
+    names
+        .iter()
+        .all(|name| features.iter().any(|feature| feature == name))
+}
+
+/// Returns whether all the CPUs listed in `/proc/cpuinfo` support the given feature,
+/// or `None` if the feature or `/proc/cpuinfo` cannot be used
+#[cfg(feature = "all-cpus")]
+pub(super) fn detect_on_all_cpus(feature: &str) -> Option<bool> {
+    let names = kernel_names(feature)?;
+    let cpus = cpus()?;
+
+    Some(cpus.iter().all(|features| has_all(features, &names)))
+}
+
+/// Detects CPU features by parsing the first entry of `/proc/cpuinfo`
+///
+/// It is the least reliable backend: the kernel may not list the features it does not know.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuInfo;
+
+impl Detector for CpuInfo {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        let names = kernel_names(feature)?;
+        let features = cpus()?.first()?;
+
+        Some(has_all(features, &names))
+    }
+}
@@ -0,0 +1,158 @@
+use libc::{AT_HWCAP, AT_HWCAP2, c_ulong, getauxval};
+
+use super::Detector;
+use super::cpuinfo::kernel_names;
+
+/// An entry of the auxiliary vector that reports CPU features
+#[derive(Clone, Copy)]
+enum Entry {
+    Hwcap,
+    // RISC-V only uses `AT_HWCAP`
+    #[cfg_attr(target_arch = "riscv64", allow(dead_code))]
+    Hwcap2,
+}
+
+impl Entry {
+    fn read(self) -> c_ulong {
+        let kind = match self {
+            Self::Hwcap => AT_HWCAP,
+            Self::Hwcap2 => AT_HWCAP2,
+        };
+
+        // SAFETY: `getauxval` has no preconditions, it returns 0 for a missing entry
+        unsafe { getauxval(kind) }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "aarch64")] {
+        /// Returns the entry and bit that report a feature, named as in `/proc/cpuinfo`
+        /// (see `arch/arm64/include/uapi/asm/hwcap.h` in Linux)
+        fn hwcap_bit(name: &str) -> Option<(Entry, u32)> {
+            use Entry::{Hwcap, Hwcap2};
+
+            let bit = match name {
+                "fp" => (Hwcap, 0),
+                "asimd" => (Hwcap, 1),
+                "aes" => (Hwcap, 3),
+                "pmull" => (Hwcap, 4),
+                "sha1" => (Hwcap, 5),
+                "sha2" => (Hwcap, 6),
+                "crc32" => (Hwcap, 7),
+                "atomics" => (Hwcap, 8),
+                "fphp" => (Hwcap, 9),
+                "asimdhp" => (Hwcap, 10),
+                "asimdrdm" => (Hwcap, 12),
+                "jscvt" => (Hwcap, 13),
+                "fcma" => (Hwcap, 14),
+                "lrcpc" => (Hwcap, 15),
+                "dcpop" => (Hwcap, 16),
+                "sha3" => (Hwcap, 17),
+                "sm3" => (Hwcap, 18),
+                "sm4" => (Hwcap, 19),
+                "asimddp" => (Hwcap, 20),
+                "sha512" => (Hwcap, 21),
+                "sve" => (Hwcap, 22),
+                "asimdfhm" => (Hwcap, 23),
+                "dit" => (Hwcap, 24),
+                "uscat" => (Hwcap, 25),
+                "ilrcpc" => (Hwcap, 26),
+                "flagm" => (Hwcap, 27),
+                "ssbs" => (Hwcap, 28),
+                "sb" => (Hwcap, 29),
+                "paca" => (Hwcap, 30),
+                "pacg" => (Hwcap, 31),
+                "dcpodp" => (Hwcap2, 0),
+                "sve2" => (Hwcap2, 1),
+                "sveaes" => (Hwcap2, 2),
+                "svepmull" => (Hwcap2, 3),
+                "svebitperm" => (Hwcap2, 4),
+                "svesha3" => (Hwcap2, 5),
+                "svesm4" => (Hwcap2, 6),
+                "flagm2" => (Hwcap2, 7),
+                "frint" => (Hwcap2, 8),
+                "svei8mm" => (Hwcap2, 9),
+                "svef32mm" => (Hwcap2, 10),
+                "svef64mm" => (Hwcap2, 11),
+                "svebf16" => (Hwcap2, 12),
+                "i8mm" => (Hwcap2, 13),
+                "bf16" => (Hwcap2, 14),
+                "rng" => (Hwcap2, 16),
+                "bti" => (Hwcap2, 17),
+                "mte" => (Hwcap2, 18),
+                "ecv" => (Hwcap2, 19),
+                "sme" => (Hwcap2, 23),
+                "smei16i64" => (Hwcap2, 24),
+                "smef64f64" => (Hwcap2, 25),
+                "smefa64" => (Hwcap2, 30),
+                "wfxt" => (Hwcap2, 31),
+                "cssc" => (Hwcap2, 34),
+                "sve2p1" => (Hwcap2, 36),
+                "sme2" => (Hwcap2, 37),
+                "sme2p1" => (Hwcap2, 38),
+                "smeb16b16" => (Hwcap2, 41),
+                "smef16f16" => (Hwcap2, 42),
+                "mops" => (Hwcap2, 43),
+                "hbc" => (Hwcap2, 44),
+                "sveb16b16" => (Hwcap2, 45),
+                "lrcpc3" => (Hwcap2, 46),
+                "lse128" => (Hwcap2, 47),
+                "lut" => (Hwcap2, 49),
+                "faminmax" => (Hwcap2, 50),
+                "f8cvt" => (Hwcap2, 51),
+                "f8fma" => (Hwcap2, 52),
+                "f8dp4" => (Hwcap2, 53),
+                "f8dp2" => (Hwcap2, 54),
+                _ => return None,
+            };
+
+            Some(bit)
+        }
+    } else if #[cfg(target_arch = "riscv64")] {
+        /// Returns the entry and bit that report a feature, named as in `/proc/cpuinfo`
+        /// (only single-letter extensions are reported, see `arch/riscv/include/uapi/asm/hwcap.h` in Linux)
+        fn hwcap_bit(name: &str) -> Option<(Entry, u32)> {
+            match name.as_bytes() {
+                [letter @ b'a'..=b'z'] => Some((Entry::Hwcap, u32::from(letter - b'a'))),
+                _ => None,
+            }
+        }
+    } else if #[cfg(target_arch = "powerpc64")] {
+        /// Returns the entry and bit that report a feature, named as in `AT_PLATFORM`
+        /// (see `arch/powerpc/include/uapi/asm/cputable.h` in Linux)
+        fn hwcap_bit(name: &str) -> Option<(Entry, u32)> {
+            use Entry::{Hwcap, Hwcap2};
+
+            let bit = match name {
+                "altivec" => (Hwcap, 28),
+                "vsx" => (Hwcap, 7),
+                "arch_2_07" => (Hwcap2, 31),
+                "vcrypto" => (Hwcap2, 25),
+                "arch_3_00" => (Hwcap2, 23),
+                "arch_3_1" => (Hwcap2, 18),
+                _ => return None,
+            };
+
+            Some(bit)
+        }
+    }
+}
+
+/// Detects CPU features with the `AT_HWCAP` and `AT_HWCAP2` entries of the auxiliary vector,
+/// which the Linux kernel sets from the features it enabled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hwcap;
+
+impl Detector for Hwcap {
+    fn detect(&self, feature: &str) -> Option<bool> {
+        let bits = kernel_names(feature)?
+            .into_iter()
+            .map(hwcap_bit)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(
+            bits.into_iter()
+                .all(|(entry, bit)| entry.read() & (1 << bit) != 0),
+        )
+    }
+}
@@ -0,0 +1,148 @@
+/// The vendor, family, and model of a CPU, as reported by `CPUID`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuModel {
+    /// Vendor (e.g., `GenuineIntel` or `AuthenticAMD`)
+    pub vendor: String,
+
+    /// Family, including the extended family (e.g., `6` or `0x19`)
+    pub family: u32,
+
+    /// Model, including the extended model (e.g., `0x8c`)
+    pub model: u32,
+
+    /// Stepping (i.e., revision) of the model
+    pub stepping: u32,
+}
+
+impl CpuModel {
+    /// Returns the model of the CPU of the host, or `None` if it cannot be identified
+    /// (only x86 and x86-64 CPUs can be identified)
+    pub fn host() -> Option<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
+                super::Cpuid::model()
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Returns the name of the CPU as in `rustc --print target-cpus` (e.g., `skylake` or `znver3`),
+    /// or `None` if the model is unknown
+    ///
+    /// The names follow the ones LLVM chooses for `-Ctarget-cpu=native`.
+    pub fn name(&self) -> Option<&'static str> {
+        match self.vendor.as_str() {
+            "GenuineIntel" => self.intel_name(),
+            "AuthenticAMD" => self.amd_name(),
+            _ => None,
+        }
+    }
+
+    fn intel_name(&self) -> Option<&'static str> {
+        if self.family != 0x6 {
+            return None;
+        }
+
+        let name = match self.model {
+            0x0f | 0x16 => "core2",
+            0x17 | 0x1d => "penryn",
+            0x1a | 0x1e | 0x1f | 0x2e => "nehalem",
+            0x25 | 0x2c | 0x2f => "westmere",
+            0x2a | 0x2d => "sandybridge",
+            0x3a | 0x3e => "ivybridge",
+            0x3c | 0x3f | 0x45 | 0x46 => "haswell",
+            0x3d | 0x47 | 0x4f | 0x56 => "broadwell",
+            0x4e | 0x5e | 0x8e | 0x9e | 0xa5 | 0xa6 => "skylake",
+            0x55 => match self.stepping {
+                0..=4 => "skylake-avx512",
+                5..=9 => "cascadelake",
+                _ => "cooperlake",
+            },
+            0x66 => "cannonlake",
+            0x7d | 0x7e => "icelake-client",
+            0x6a | 0x6c => "icelake-server",
+            0x8c | 0x8d => "tigerlake",
+            0xa7 => "rocketlake",
+            0x97 | 0x9a => "alderlake",
+            0xb7 | 0xba | 0xbf => "raptorlake",
+            0xaa | 0xac => "meteorlake",
+            0xb5 | 0xc5 => "arrowlake",
+            0xc6 => "arrowlake-s",
+            0xbd => "lunarlake",
+            0x8f => "sapphirerapids",
+            0xcf => "emeraldrapids",
+            0xad | 0xae => "graniterapids",
+            0x1c | 0x26 | 0x27 | 0x35 | 0x36 => "bonnell",
+            0x37 | 0x4a | 0x4c | 0x4d | 0x5a | 0x5d => "silvermont",
+            0x5c | 0x5f => "goldmont",
+            0x7a => "goldmont-plus",
+            0x86 | 0x8a | 0x96 | 0x9c => "tremont",
+            0xaf => "sierraforest",
+            0xb6 => "grandridge",
+            0xdd => "clearwaterforest",
+            0x57 => "knl",
+            0x85 => "knm",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+
+    fn amd_name(&self) -> Option<&'static str> {
+        let name = match (self.family, self.model) {
+            (0x10, _) => "amdfam10",
+            (0x14, _) => "btver1",
+            (0x15, 0x00..=0x01) => "bdver1",
+            (0x15, 0x02..=0x2f) => "bdver2",
+            (0x15, 0x30..=0x5f) => "bdver3",
+            (0x15, _) => "bdver4",
+            (0x16, _) => "btver2",
+            (0x17, 0x00..=0x2f | 0x50..=0x5f) => "znver1",
+            (0x17, _) => "znver2",
+            (0x19, 0x10..=0x1f | 0x60..=0x7f | 0xa0..=0xaf) => "znver4",
+            (0x19, _) => "znver3",
+            (0x1a, _) => "znver5",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CpuModel;
+
+    #[test]
+    fn host() {
+        let model = CpuModel::host();
+        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+            let model = model.unwrap();
+            assert_eq!(model.vendor.len(), 12);
+            assert_ne!(model.family, 0);
+        } else {
+            assert_eq!(model, None);
+        }
+    }
+
+    #[test]
+    fn name() {
+        let model = |vendor: &str, family, model, stepping| CpuModel {
+            vendor: vendor.into(),
+            family,
+            model,
+            stepping,
+        };
+
+        assert_eq!(model("GenuineIntel", 6, 0x9e, 0).name(), Some("skylake"));
+        assert_eq!(
+            model("GenuineIntel", 6, 0x55, 7).name(),
+            Some("cascadelake")
+        );
+        assert_eq!(model("AuthenticAMD", 0x19, 0x21, 0).name(), Some("znver3"));
+        assert_eq!(model("AuthenticAMD", 0x19, 0x61, 0).name(), Some("znver4"));
+        assert_eq!(model("GenuineIntel", 0xf, 0x1, 0).name(), None);
+        assert_eq!(model("HygonGenuine", 0x18, 0x1, 0).name(), None);
+    }
+}
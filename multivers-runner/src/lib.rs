@@ -1,18 +1,81 @@
 #![doc = "README.md"]
 #![cfg_attr(not(test), no_main)]
 #![cfg_attr(test, allow(dead_code))]
+// Library features required on nightly by the CPU features detected with `std::arch`,
+// only enabled with the `nightly` feature (see `build/std_detect.rs`)
+#![cfg_attr(
+    multivers_runner_gate = "apx_target_feature",
+    feature(apx_target_feature)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "avx10_target_feature",
+    feature(avx10_target_feature)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "movrs_target_feature",
+    feature(movrs_target_feature)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "s390x_target_feature",
+    feature(s390x_target_feature)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_aarch64_feature_detection",
+    feature(stdarch_aarch64_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_arm_feature_detection",
+    feature(stdarch_arm_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_loongarch_feature_detection",
+    feature(stdarch_loongarch_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_mips_feature_detection",
+    feature(stdarch_mips_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_powerpc_feature_detection",
+    feature(stdarch_powerpc_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "stdarch_riscv_feature_detection",
+    feature(stdarch_riscv_feature_detection)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "x86_amx_intrinsics",
+    feature(x86_amx_intrinsics)
+)]
+#![cfg_attr(
+    multivers_runner_gate = "xop_target_feature",
+    feature(xop_target_feature)
+)]
 
 mod build;
+pub mod detect;
+mod report;
+mod rule;
+mod runner;
+mod timings;
 
+#[cfg(all(feature = "main", not(test)))]
 use std::ffi::c_char;
 
-use build::{Build, Executable};
+pub use build::{Build, Executable};
+pub use rule::Rule;
+pub use runner::Runner;
+
+pub use proc_exit;
 
 /// Function called at program startup.
 ///
 /// When [main] is executed, it uncompresses and executes the version that matches the CPU features
 /// of the host.
 ///
+/// It is only available with the `main` feature (enabled by default).
+/// Disable it to write a custom runner with [`Runner`].
+///
 /// # Example
 ///
 /// ```no_run
@@ -27,30 +90,7 @@ use build::{Build, Executable};
 /// - `argv` and `envp` must be null-terminated arrays of valid pointers to null-terminated strings.
 /// - Each element of `argv` and `envp` must be valid for reads of bytes up to and including the null terminator.
 #[unsafe(no_mangle)]
-#[cfg(not(test))]
+#[cfg(all(feature = "main", not(test)))]
 pub unsafe extern "C" fn main(argc: i32, argv: *const *const c_char, envp: *const *const c_char) {
-    let result = unsafe { run(argc, argv, envp) };
-
-    proc_exit::exit(result);
-}
-
-unsafe fn run(
-    argc: i32,
-    argv: *const *const c_char,
-    envp: *const *const c_char,
-) -> proc_exit::ExitResult {
-    #[cfg(feature = "debug")]
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug")).init();
-
-    let build = Build::find().unwrap_or_default();
-
-    #[cfg(feature = "debug")]
-    log::debug!(
-        "Executing build with the following CPU features: {}",
-        build.features()
-    );
-
-    unsafe { build.exec(argc, argv, envp) }?;
-
-    Ok(())
+    unsafe { Runner::new().main(argc, argv, envp) }
 }
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::{Duration, SystemTime};
+
+use crate::build::Build;
+use crate::detect::{CpuModel, Detector};
+
+/// Environment variable that contains the path of the file to which a report is appended at each launch
+pub(crate) const REPORT_ENV: &str = "MULTIVERS_REPORT";
+
+/// Appends a JSON line describing the launch to the file whose path is in `MULTIVERS_REPORT`, if it is set
+///
+/// Errors are ignored, so that the report never prevents the build from being executed.
+pub(crate) fn append(build: &Build<'_>, decode_time: Duration) {
+    let Some(path) = std::env::var_os(REPORT_ENV).filter(|path| !path.is_empty()) else {
+        return;
+    };
+
+    let line = to_json_line(
+        build,
+        decode_time,
+        &host_features(),
+        CpuModel::host().as_ref(),
+    );
+
+    // A single write of the whole line, so that concurrent launches do not interleave their lines
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(_error) = result {
+        #[cfg(feature = "debug")]
+        log::debug!(
+            "Failed to append the report to {}: {_error}",
+            path.display()
+        );
+    }
+}
+
+/// Returns the CPU features required by any of the builds that the host supports
+///
+/// The features are detected like in [`Build::find`], so with the `all-cpus` feature, on Linux,
+/// they must be supported by all the CPUs of the host.
+fn host_features() -> Vec<&'static str> {
+    let features = Build::builds()
+        .flat_map(|build| build.features().iter().copied())
+        .collect::<BTreeSet<_>>();
+
+    cfg_if::cfg_if! {
+        if #[cfg(all(feature = "all-cpus", target_os = "linux"))] {
+            let detector = crate::detect::AllCpus::new(features.iter().copied());
+        } else {
+            let detector = crate::detect::DefaultDetector;
+        }
+    }
+
+    features
+        .into_iter()
+        .filter(|feature| detector.detect(feature).unwrap_or(false))
+        .collect()
+}
+
+fn to_json_line(
+    build: &Build<'_>,
+    decode_time: Duration,
+    host_features: &[&str],
+    model: Option<&CpuModel>,
+) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut line = format!("{{\"timestamp\":{timestamp},\"host\":{{");
+    if let Some(model) = model {
+        line.push_str("\"vendor\":");
+        push_json_string(&mut line, &model.vendor);
+        let _ = write!(
+            line,
+            ",\"family\":{},\"model\":{},\"stepping\":{},\"cpu\":",
+            model.family, model.model, model.stepping
+        );
+        match model.name() {
+            Some(name) => push_json_string(&mut line, name),
+            None => line.push_str("null"),
+        }
+        line.push(',');
+    }
+    line.push_str("\"features\":");
+    push_json_array(&mut line, host_features);
+    line.push_str("},\"build\":{\"cpus\":");
+    push_json_array(&mut line, build.cpus());
+    line.push_str(",\"features\":");
+    push_json_array(&mut line, build.features());
+    let _ = writeln!(
+        line,
+        ",\"source\":{}}},\"decode_us\":{}}}",
+        build.is_source(),
+        decode_time.as_micros()
+    );
+
+    line
+}
+
+fn push_json_array(output: &mut String, values: &[&str]) {
+    output.push('[');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        push_json_string(output, value);
+    }
+    output.push(']');
+}
+
+fn push_json_string(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04x}", u32::from(c));
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(not(feature = "stub"))]
+    fn to_json_line() {
+        use std::time::Duration;
+
+        use crate::Build;
+        use crate::detect::CpuModel;
+
+        let build = Build::source();
+        let model = CpuModel {
+            vendor: "GenuineIntel".into(),
+            family: 6,
+            model: 0x9e,
+            stepping: 10,
+        };
+
+        let line = super::to_json_line(
+            build,
+            Duration::from_micros(1234),
+            &["avx", "sse"],
+            Some(&model),
+        );
+        let (_, line) = line.split_once(",\"host\"").unwrap();
+        assert_eq!(
+            line,
+            ":{\"vendor\":\"GenuineIntel\",\"family\":6,\"model\":158,\"stepping\":10,\"cpu\":\"skylake\",\
+            \"features\":[\"avx\",\"sse\"]},\"build\":{\"cpus\":[],\"features\":[],\"source\":true},\"decode_us\":1234}\n"
+        );
+
+        let line = super::to_json_line(build, Duration::ZERO, &[], None);
+        assert!(line.contains(",\"host\":{\"features\":[]},"), "{line}");
+    }
+
+    #[test]
+    fn push_json_string() {
+        let mut output = String::new();
+        super::push_json_string(&mut output, "a\"b\\c\n");
+        assert_eq!(output, r#""a\"b\\c\u000a""#);
+    }
+}
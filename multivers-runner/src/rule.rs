@@ -0,0 +1,55 @@
+use crate::build::Build;
+use crate::detect::CpuModel;
+
+/// A rule that makes the runner prefer the build of a CPU over the build of another one on some hosts
+/// (e.g., on CPUs where AVX-512 instructions reduce the frequency).
+///
+/// The rules are set in `[package.metadata.multivers]` and embedded in the runner (see [`Build::rules`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule<'a> {
+    /// Vendor of the hosts the rule applies to (any if `None`)
+    pub(crate) vendor: Option<&'a str>,
+
+    /// Family of the hosts the rule applies to (any if `None`)
+    pub(crate) family: Option<u32>,
+
+    /// Models of the hosts the rule applies to (any if empty)
+    pub(crate) models: &'a [u32],
+
+    /// CPU whose build is preferred
+    pub(crate) prefer: &'a str,
+
+    /// CPU whose build is not selected when the preferred one is supported
+    pub(crate) over: &'a str,
+}
+
+impl Rule<'_> {
+    /// Returns true if the rule applies to a host with the given CPU model
+    pub fn matches(&self, model: &CpuModel) -> bool {
+        self.vendor.is_none_or(|vendor| vendor == model.vendor)
+            && self.family.is_none_or(|family| family == model.family)
+            && (self.models.is_empty() || self.models.contains(&model.model))
+    }
+
+    /// Returns true if the build is the one of the preferred CPU
+    pub fn prefers(&self, build: &Build<'_>) -> bool {
+        build.cpus().contains(&self.prefer)
+    }
+
+    /// Returns true if the build must not be selected when the build of the preferred CPU is supported
+    ///
+    /// A build shared by both CPUs (i.e., they have the same CPU features) is never avoided.
+    pub fn avoids(&self, build: &Build<'_>) -> bool {
+        build.cpus().contains(&self.over) && !self.prefers(build)
+    }
+
+    /// CPU whose build is preferred (e.g., `x86-64-v3`)
+    pub fn prefer(&self) -> &str {
+        self.prefer
+    }
+
+    /// CPU whose build is avoided (e.g., `x86-64-v4`)
+    pub fn over(&self) -> &str {
+        self.over
+    }
+}
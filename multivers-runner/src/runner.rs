@@ -0,0 +1,181 @@
+use std::ffi::c_char;
+#[cfg(feature = "extract")]
+use std::path::Path;
+
+use crate::build::{Build, Executable};
+
+/// Environment variable that contains the path to which the selected build is written instead of being executed
+#[cfg(feature = "extract")]
+const EXTRACT_ENV: &str = "MULTIVERS_EXTRACT";
+
+type BeforeSelectHook<'a> = Box<dyn FnOnce() -> Result<(), proc_exit::Exit> + 'a>;
+type SelectPolicy<'a> = Box<dyn FnOnce() -> Option<Build<'static>> + 'a>;
+type BeforeExecHook<'a> = Box<dyn FnOnce(&Build<'static>) -> Result<(), proc_exit::Exit> + 'a>;
+
+/// Selects one of the builds embedded in the runner and executes it.
+///
+/// [`Runner`] is what the default [`main`](crate::main) function uses.
+/// It can be used to write a custom runner that changes how a build is selected, or that
+/// needs to do something before selecting or executing a build (e.g., logging).
+///
+/// # Example
+///
+/// With the `main` feature disabled:
+///
+/// ```no_run
+/// #![no_main]
+///
+/// use std::ffi::c_char;
+///
+/// use multivers_runner::Runner;
+///
+/// #[unsafe(no_mangle)]
+/// pub unsafe extern "C" fn main(argc: i32, argv: *const *const c_char, envp: *const *const c_char) {
+///     let runner = Runner::new().before_exec(|build| {
+///         eprintln!("Executing build with CPU features: {}", build.features().join(", "));
+///
+///         Ok(())
+///     });
+///
+///     unsafe { runner.main(argc, argv, envp) }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Runner<'a> {
+    before_select: Option<BeforeSelectHook<'a>>,
+    select: Option<SelectPolicy<'a>>,
+    before_exec: Option<BeforeExecHook<'a>>,
+}
+
+impl<'a> Runner<'a> {
+    /// Creates a runner that selects the first build supported by the host (see [`Build::find`]).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a hook called before selecting a build.
+    ///
+    /// If the hook returns an error, the runner exits with it without executing any build.
+    pub fn before_select(
+        mut self,
+        hook: impl FnOnce() -> Result<(), proc_exit::Exit> + 'a,
+    ) -> Self {
+        self.before_select = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the policy used to select the build to execute.
+    ///
+    /// The source build (see [`Build::source`]) is executed if the policy does not select any build.
+    pub fn select(mut self, policy: impl FnOnce() -> Option<Build<'static>> + 'a) -> Self {
+        self.select = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets a hook called with the selected build, before executing it.
+    ///
+    /// If the hook returns an error, the runner exits with it without executing the build.
+    pub fn before_exec(
+        mut self,
+        hook: impl FnOnce(&Build<'static>) -> Result<(), proc_exit::Exit> + 'a,
+    ) -> Self {
+        self.before_exec = Some(Box::new(hook));
+        self
+    }
+
+    /// Selects a build and executes it.
+    ///
+    /// It only returns if the build could not be executed or if a hook returned an error.
+    /// With the `extract` feature, if the environment variable `MULTIVERS_EXTRACT` contains a path, the selected build is written
+    /// to this path instead of being executed (e.g., to run it directly with `cargo multivers bench`).
+    ///
+    /// # Safety
+    ///
+    /// - `argc` must never be negative.
+    /// - `argv` and `envp` must be null-terminated arrays of valid pointers to null-terminated strings.
+    /// - Each element of `argv` and `envp` must be valid for reads of bytes up to and including the null terminator.
+    pub unsafe fn run(
+        self,
+        argc: i32,
+        argv: *const *const c_char,
+        envp: *const *const c_char,
+    ) -> proc_exit::ExitResult {
+        #[cfg(feature = "debug")]
+        let _ =
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
+                .try_init();
+
+        crate::timings::start();
+
+        if let Some(before_select) = self.before_select {
+            before_select()?;
+        }
+
+        let build = crate::timings::measure("select", || {
+            self.select.map_or_else(Build::find, |select| select())
+        })
+        .unwrap_or_default();
+
+        #[cfg(feature = "debug")]
+        log::debug!(
+            "Executing build with the following CPU features: {}",
+            build.features().join(", ")
+        );
+
+        if let Some(before_exec) = self.before_exec {
+            before_exec(&build)?;
+        }
+
+        #[cfg(feature = "extract")]
+        if let Some(path) = std::env::var_os(EXTRACT_ENV).filter(|path| !path.is_empty()) {
+            return extract_to(&build, Path::new(&path));
+        }
+
+        unsafe { build.exec(argc, argv, envp) }?;
+
+        Ok(())
+    }
+
+    /// Selects a build and executes it, or exits the process if it fails.
+    ///
+    /// # Safety
+    ///
+    /// See [`Runner::run`].
+    pub unsafe fn main(
+        self,
+        argc: i32,
+        argv: *const *const c_char,
+        envp: *const *const c_char,
+    ) -> ! {
+        let result = unsafe { self.run(argc, argv, envp) };
+
+        proc_exit::exit(result);
+    }
+}
+
+/// Writes a build to an executable file
+#[cfg(feature = "extract")]
+fn extract_to(build: &Build<'_>, path: &Path) -> proc_exit::ExitResult {
+    let error = || {
+        proc_exit::Code::FAILURE
+            .with_message(format!("Failed to write the build to `{}`", path.display()))
+    };
+
+    let mut file = std::fs::File::create(path).map_err(|_| error())?;
+    build.extract_into(&mut file).map_err(|extract_error| {
+        proc_exit::Code::FAILURE.with_message(format!(
+            "Failed to write the build to `{}`: {extract_error}",
+            path.display()
+        ))
+    })?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(std::fs::Permissions::from_mode(0o755))
+            .map_err(|_| error())?;
+    }
+
+    Ok(())
+}
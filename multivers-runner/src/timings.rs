@@ -0,0 +1,126 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Environment variable that contains the path of the file to which the timings are appended
+/// (or `fd:N` to write them to the file descriptor `N` on Unix)
+pub(crate) const TIMINGS_ENV: &str = "MULTIVERS_TIMINGS";
+
+/// The duration of each phase of the runner, since it started
+struct Timings {
+    start: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+/// The timings of the runner, only recorded when `MULTIVERS_TIMINGS` is set
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+/// Starts recording the timings if `MULTIVERS_TIMINGS` is set
+pub(crate) fn start() {
+    if std::env::var_os(TIMINGS_ENV).is_none_or(|value| value.is_empty()) {
+        return;
+    }
+
+    if let Ok(mut timings) = TIMINGS.lock() {
+        *timings = Some(Timings {
+            start: Instant::now(),
+            phases: Vec::new(),
+        });
+    }
+}
+
+/// Calls a function and records its duration as the given phase, if the timings are recorded
+pub(crate) fn measure<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+
+    if let Ok(mut timings) = TIMINGS.lock()
+        && let Some(timings) = timings.as_mut()
+    {
+        timings.phases.push((phase, start.elapsed()));
+    }
+
+    result
+}
+
+/// Writes the recorded timings as a JSON line, with the total time spent in the runner
+///
+/// Errors are ignored, so that the timings never prevent the build from being executed.
+pub(crate) fn finish() {
+    let Some(timings) = TIMINGS.lock().ok().and_then(|mut timings| timings.take()) else {
+        return;
+    };
+    let Some(destination) = std::env::var_os(TIMINGS_ENV) else {
+        return;
+    };
+
+    let line = to_json_line(&timings.phases, timings.start.elapsed());
+
+    match destination
+        .to_str()
+        .and_then(|value| value.strip_prefix("fd:"))
+    {
+        Some(fd) => write_to_fd(fd, &line),
+        None => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&destination);
+            if let Ok(mut file) = file {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+}
+
+/// Writes a line to the given file descriptor, without closing it
+#[cfg(unix)]
+fn write_to_fd(fd: &str, line: &str) {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    let Ok(fd) = fd.parse() else {
+        return;
+    };
+
+    // SAFETY: the file descriptor is given by the user, and it is not closed since the file is never dropped
+    let mut file = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let _ = file.write_all(line.as_bytes());
+}
+
+/// File descriptors are only supported on Unix
+#[cfg(not(unix))]
+fn write_to_fd(_fd: &str, _line: &str) {}
+
+fn to_json_line(phases: &[(&str, Duration)], total: Duration) -> String {
+    let mut line = String::from("{");
+    for (phase, duration) in phases {
+        let _ = write!(line, "\"{phase}_us\":{},", duration.as_micros());
+    }
+    let _ = writeln!(line, "\"total_us\":{}}}", total.as_micros());
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn to_json_line() {
+        let phases = [
+            ("select", Duration::from_micros(12)),
+            ("source_decompress", Duration::from_millis(3)),
+        ];
+
+        assert_eq!(
+            super::to_json_line(&phases, Duration::from_millis(5)),
+            "{\"select_us\":12,\"source_decompress_us\":3000,\"total_us\":5000}\n"
+        );
+        assert_eq!(
+            super::to_json_line(&[], Duration::ZERO),
+            "{\"total_us\":0}\n"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Archive of the sources of `multivers-runner` embedded in `cargo-multivers`
+//!
+//! The archive is generated from the `multivers-runner` directory of the repository and committed as `src/runner.archive`,
+//! so that it is also part of the package published on crates.io, which cannot contain the nested crate of the runner.
+//! Each file of the archive starts with a line containing its length in bytes and its path relative to the crate of the runner,
+//! followed by its content and a line feed.
+//!
+//! This module is also included by the build script of `cargo-multivers`, which extracts the archive,
+//! and generates it again if the environment variable `MULTIVERS_UPDATE_RUNNER_ARCHIVE` is set
+//! (e.g., `MULTIVERS_UPDATE_RUNNER_ARCHIVE=1 cargo build` after changing the runner).
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Path of the archive, relative to the manifest of `cargo-multivers`
+pub const ARCHIVE_PATH: &str = "src/runner.archive";
+
+/// Environment variable that makes the build script generate the archive again from the sources of the runner
+pub const UPDATE_ENV: &str = "MULTIVERS_UPDATE_RUNNER_ARCHIVE";
+
+/// Directories of the runner crate that contain sources, in addition to its files at the root
+const SOURCE_DIRECTORIES: [&str; 2] = ["build", "src"];
+
+/// Files at the root of the runner crate, other than its manifest
+const ROOT_FILES: [&str; 2] = ["build.rs", "README.md"];
+
+/// Returns the files of an archive with their path relative to the crate of the runner
+pub fn read(mut archive: &[u8]) -> io::Result<Vec<(&str, &[u8])>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid archive of the runner");
+
+    let mut files = Vec::new();
+    while !archive.is_empty() {
+        let (header, rest) = archive
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map(|end| archive.split_at(end))
+            .ok_or_else(invalid)?;
+        let (length, path) = std::str::from_utf8(header)
+            .ok()
+            .and_then(|header| header.split_once(' '))
+            .ok_or_else(invalid)?;
+        let length = length.parse::<usize>().map_err(|_| invalid())?;
+        let (content, rest) = rest
+            .get(1..)
+            .and_then(|rest| rest.split_at_checked(length))
+            .ok_or_else(invalid)?;
+        archive = rest.strip_prefix(b"\n").ok_or_else(invalid)?;
+
+        files.push((path, content));
+    }
+
+    Ok(files)
+}
+
+/// Lists the files of a directory recursively
+fn list_files(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the fields inherited from the workspace (e.g., `edition.workspace = true`) by their values in the workspace,
+/// and removes the lints of the workspace, since the runner is built outside of the workspace
+fn normalize_manifest(manifest: &str, workspace_manifest: &str) -> String {
+    let workspace_fields = workspace_manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[workspace.package]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect::<Vec<_>>();
+
+    let mut normalized = String::new();
+    let mut in_lints = false;
+    for line in manifest.lines() {
+        if line.starts_with('[') {
+            in_lints = line.trim() == "[lints]";
+        }
+        if in_lints {
+            continue;
+        }
+
+        let inherited = line
+            .strip_suffix(".workspace = true")
+            .and_then(|key| workspace_fields.iter().find(|(field, _)| *field == key));
+        match inherited {
+            Some((key, value)) => normalized.push_str(&format!("{key} = {value}")),
+            None => normalized.push_str(line),
+        }
+        normalized.push('\n');
+    }
+
+    normalized
+}
+
+/// Generates the archive of the runner crate in `runner_dir`
+pub fn generate(runner_dir: &Path, workspace_manifest: &str) -> io::Result<Vec<u8>> {
+    let manifest = normalize_manifest(
+        &fs::read_to_string(runner_dir.join("Cargo.toml"))?,
+        workspace_manifest,
+    );
+    let mut files = vec![("Cargo.toml".to_owned(), manifest.into_bytes())];
+
+    let mut paths = ROOT_FILES
+        .iter()
+        .map(|file| runner_dir.join(file))
+        .collect::<Vec<_>>();
+    for directory in SOURCE_DIRECTORIES {
+        list_files(&runner_dir.join(directory), &mut paths)?;
+    }
+    paths.sort();
+    for path in paths {
+        let relative_path = path
+            .strip_prefix(runner_dir)
+            .map_err(io::Error::other)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        files.push((relative_path, fs::read(&path)?));
+    }
+
+    let mut archive = Vec::new();
+    for (path, content) in files {
+        archive.extend_from_slice(format!("{} {path}\n", content.len()).as_bytes());
+        archive.extend_from_slice(&content);
+        archive.push(b'\n');
+    }
+
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{ARCHIVE_PATH, UPDATE_ENV, generate, read};
+
+    /// Fails if the archive does not match the sources of the runner in the repository
+    #[test]
+    fn runner_archive_up_to_date() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let runner_dir = manifest_dir.join("multivers-runner");
+        // The package published on crates.io does not contain the sources of the runner
+        if !runner_dir.is_dir() {
+            return;
+        }
+
+        let workspace_manifest = fs::read_to_string(manifest_dir.join("Cargo.toml")).unwrap();
+        let archive = generate(&runner_dir, &workspace_manifest).unwrap();
+        assert!(
+            fs::read(manifest_dir.join(ARCHIVE_PATH)).is_ok_and(|current| current == archive),
+            "`{ARCHIVE_PATH}` is outdated, run `{UPDATE_ENV}=1 cargo build` to update it"
+        );
+    }
+
+    #[test]
+    fn read_archive() {
+        let files = read(b"5 Cargo.toml\n[lib]\n0 src/empty.rs\n\n").unwrap();
+        assert_eq!(
+            files,
+            [("Cargo.toml", b"[lib]".as_slice()), ("src/empty.rs", b"")]
+        );
+        assert_eq!(read(b"").unwrap(), []);
+
+        read(b"5 Cargo.toml\n[lib").unwrap_err();
+        read(b"5 Cargo.toml\n[lib]").unwrap_err();
+        read(b"x Cargo.toml\n[lib]\n").unwrap_err();
+        read(b"Cargo.toml").unwrap_err();
+    }
+}
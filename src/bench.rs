@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use anyhow::Context;
+
+/// Runs a binary built by `cargo multivers` and the build it selects, to measure the overhead of the runner
+pub struct Bench {
+    runner: PathBuf,
+    args: Vec<String>,
+    runs: u32,
+}
+
+impl Bench {
+    pub fn new(runner: PathBuf, args: Vec<String>, runs: u32) -> Self {
+        Self { runner, args, runs }
+    }
+
+    /// Extracts the selected build, then runs alternately the runner and the build
+    pub fn run(&self) -> anyhow::Result<BenchReport> {
+        // Removed once dropped
+        let temp_dir = tempfile::Builder::new()
+            .prefix("cargo-multivers-bench-")
+            .tempdir()
+            .context("Failed to create a temporary directory to extract the build")?;
+
+        self.run_in(temp_dir.path())
+    }
+
+    fn run_in(&self, temp_dir: &Path) -> anyhow::Result<BenchReport> {
+        let mut variant = temp_dir.join("variant");
+        variant.set_extension(std::env::consts::EXE_EXTENSION);
+        let timings = temp_dir.join("timings.jsonl");
+
+        let status = Command::new(&self.runner)
+            .env("MULTIVERS_EXTRACT", &variant)
+            .status()
+            .with_context(|| format!("Failed to execute `{}`", self.runner.display()))?;
+        anyhow::ensure!(
+            status.success() && variant.exists(),
+            "Failed to extract the build selected by `{}` (was it built by cargo multivers with `--runner-features extract`?)",
+            self.runner.display()
+        );
+
+        // Warm up the caches of the OS
+        self.time(Command::new(&self.runner))?;
+        self.time(Command::new(&variant))?;
+
+        let mut runner_ms = Vec::new();
+        let mut variant_ms = Vec::new();
+        for _ in 0..self.runs {
+            let mut runner = Command::new(&self.runner);
+            runner.env("MULTIVERS_TIMINGS", &timings);
+            runner_ms.push(self.time(runner)?);
+            variant_ms.push(self.time(Command::new(&variant))?);
+        }
+
+        let overhead_ms = runner_ms
+            .iter()
+            .zip(&variant_ms)
+            .map(|(runner, variant)| runner - variant)
+            .collect();
+        let timings = std::fs::read_to_string(&timings).unwrap_or_default();
+
+        Ok(BenchReport {
+            runs: self.runs,
+            runner: Distribution::new(runner_ms),
+            variant: Distribution::new(variant_ms),
+            overhead: Distribution::new(overhead_ms),
+            phases: mean_phases(&timings),
+        })
+    }
+
+    /// Runs a command with the arguments and returns its duration in milliseconds
+    fn time(&self, mut command: Command) -> anyhow::Result<f64> {
+        let program = command.get_program().to_owned();
+        command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        let start = Instant::now();
+        command
+            .status()
+            .with_context(|| format!("Failed to execute `{}`", program.display()))?;
+
+        Ok(start.elapsed().as_secs_f64() * 1000.0)
+    }
+}
+
+/// Returns the mean duration in milliseconds of each phase of the runner, from the lines written with `MULTIVERS_TIMINGS`
+fn mean_phases(timings: &str) -> Vec<(String, f64)> {
+    let mut phases = BTreeMap::<String, (f64, u32)>::new();
+
+    for line in timings.lines() {
+        let Ok(durations) = serde_json::from_str::<serde_json::Map<_, _>>(line) else {
+            continue;
+        };
+
+        for (phase, duration) in durations {
+            let Some(duration) = duration.as_u64() else {
+                continue;
+            };
+            let phase = phase.trim_end_matches("_us").to_owned();
+            let (total, count) = phases.entry(phase).or_default();
+            *total += duration as f64 / 1000.0;
+            *count += 1;
+        }
+    }
+
+    let mut phases = phases
+        .into_iter()
+        .map(|(phase, (total, count))| (phase, total / f64::from(count)))
+        .collect::<Vec<_>>();
+    // The total time of the runner comes after its phases
+    phases.sort_by_key(|(phase, _)| phase == "total");
+
+    phases
+}
+
+/// Distribution of durations in milliseconds
+#[derive(Debug, PartialEq)]
+struct Distribution {
+    min: f64,
+    median: f64,
+    mean: f64,
+    p90: f64,
+    max: f64,
+}
+
+impl Distribution {
+    fn new(mut samples: Vec<f64>) -> Option<Self> {
+        samples.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p * samples.len() as f64).ceil() as usize;
+            samples.get(rank.saturating_sub(1)).copied()
+        };
+
+        Some(Self {
+            min: *samples.first()?,
+            median: percentile(0.5)?,
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            p90: percentile(0.9)?,
+            max: *samples.last()?,
+        })
+    }
+}
+
+/// The durations measured by [`Bench`]
+pub struct BenchReport {
+    runs: u32,
+    runner: Option<Distribution>,
+    variant: Option<Distribution>,
+    overhead: Option<Distribution>,
+    phases: Vec<(String, f64)>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Runs: {}", self.runs)?;
+        writeln!(
+            f,
+            "{:<10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "", "min", "median", "mean", "p90", "max"
+        )?;
+
+        for (name, distribution) in [
+            ("Runner", &self.runner),
+            ("Build", &self.variant),
+            ("Overhead", &self.overhead),
+        ] {
+            let Some(d) = distribution else {
+                continue;
+            };
+            writeln!(
+                f,
+                "{name:<10} {:>7.2} ms {:>7.2} ms {:>7.2} ms {:>7.2} ms {:>7.2} ms",
+                d.min, d.median, d.mean, d.p90, d.max
+            )?;
+        }
+
+        if !self.phases.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Runner phases (mean):")?;
+            for (phase, mean) in &self.phases {
+                writeln!(f, "  {phase:<18} {mean:>7.2} ms")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BenchReport, Distribution};
+
+    #[test]
+    fn distribution() {
+        assert_eq!(Distribution::new(Vec::new()), None);
+        assert_eq!(
+            Distribution::new(vec![5.0, 1.0, 3.0, 2.0, 4.0, 6.0, 7.0, 8.0, 9.0, 10.0]),
+            Some(Distribution {
+                min: 1.0,
+                median: 5.0,
+                mean: 5.5,
+                p90: 9.0,
+                max: 10.0,
+            })
+        );
+    }
+
+    #[test]
+    fn mean_phases() {
+        let timings = "{\"select_us\":100,\"write_us\":2000,\"total_us\":3000}\n\
+                       invalid\n\
+                       {\"select_us\":300,\"write_us\":4000,\"total_us\":5000}\n";
+
+        assert_eq!(
+            super::mean_phases(timings),
+            [
+                ("select".to_owned(), 0.2),
+                ("write".to_owned(), 3.0),
+                ("total".to_owned(), 4.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let report = BenchReport {
+            runs: 2,
+            runner: Distribution::new(vec![3.0, 5.0]),
+            variant: Distribution::new(vec![1.0, 2.0]),
+            overhead: Distribution::new(vec![2.0, 3.0]),
+            phases: vec![("total".to_owned(), 2.5)],
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "\
+Runs: 2
+                  min     median       mean        p90        max
+Runner        3.00 ms    3.00 ms    4.00 ms    5.00 ms    5.00 ms
+Build         1.00 ms    1.00 ms    1.50 ms    2.00 ms    2.00 ms
+Overhead      2.00 ms    2.00 ms    2.50 ms    3.00 ms    3.00 ms
+
+Runner phases (mean):
+  total                 2.50 ms
+"
+        );
+    }
+}
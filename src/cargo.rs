@@ -1,18 +1,44 @@
+use std::ffi::OsStr;
 use std::path::PathBuf;
 
 use escargot::CommandMessages;
 use escargot::error::CargoError;
 
+/// An output of Cargo found in its stream of messages
+pub enum Output {
+    /// An executable artifact
+    Executable(PathBuf),
+    /// A dynamic library artifact of a `cdylib` target
+    Library(PathBuf),
+    /// The output directory of a build script
+    OutDir(PathBuf),
+}
+
 pub trait CommandMessagesExt {
     /// Finds the executable artifact in the stream of messages from Cargo while printing rustc messages.
     fn find_executable(self) -> anyhow::Result<Option<PathBuf>>;
 
     /// Finds executable artifacts in the stream of messages from Cargo while printing rustc messages.
     fn find_executables(self) -> impl Iterator<Item = Result<PathBuf, CargoError>>;
+
+    /// Finds the dynamic library artifact of a `cdylib` target in the stream of messages from Cargo while printing rustc messages.
+    fn find_library(self) -> anyhow::Result<Option<PathBuf>>;
+
+    /// Finds executable and dynamic library artifacts, and output directories of build scripts in the stream of messages from Cargo
+    /// while printing rustc messages.
+    fn find_outputs(self) -> impl Iterator<Item = Result<Output, CargoError>>;
 }
 
 impl CommandMessagesExt for CommandMessages {
     fn find_executables(self) -> impl Iterator<Item = Result<PathBuf, CargoError>> {
+        self.find_outputs().filter_map(|output| match output {
+            Ok(Output::Executable(path)) => Some(Ok(path)),
+            Ok(Output::Library(_) | Output::OutDir(_)) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    fn find_outputs(self) -> impl Iterator<Item = Result<Output, CargoError>> {
         self.into_iter().filter_map(|message| {
             let message = match message {
                 Ok(message) => message,
@@ -23,6 +49,29 @@ impl CommandMessagesExt for CommandMessages {
                     .executable
                     .as_deref()
                     .map(ToOwned::to_owned)
+                    .map(Output::Executable)
+                    .or_else(|| {
+                        // A `cdylib` target also outputs files to link with it on some platforms (e.g., `.dll.lib`)
+                        artifact
+                            .filenames
+                            .iter()
+                            .find(|path| {
+                                artifact
+                                    .target
+                                    .crate_types
+                                    .iter()
+                                    .any(|kind| kind == "cdylib")
+                                    && path.extension()
+                                        == Some(OsStr::new(std::env::consts::DLL_EXTENSION))
+                            })
+                            .map(|path| Output::Library(path.to_path_buf()))
+                    })
+                    .map(Ok),
+                Ok(escargot::format::Message::BuildScriptExecuted(script)) => script
+                    .out_dir
+                    .as_deref()
+                    .map(ToOwned::to_owned)
+                    .map(Output::OutDir)
                     .map(Ok),
                 Ok(escargot::format::Message::CompilerMessage(e)) => {
                     // We ignore the messages that are generated due to the use of `-Ctarget-feature`
@@ -54,6 +103,24 @@ impl CommandMessagesExt for CommandMessages {
         })
     }
 
+    fn find_library(self) -> anyhow::Result<Option<PathBuf>> {
+        let found = self
+            .find_outputs()
+            .filter_map(|output| match output {
+                Ok(Output::Library(path)) => Some(Ok(path)),
+                Ok(Output::Executable(_) | Output::OutDir(_)) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match &found[..] {
+            [] => Ok(None),
+            [path] => Ok(Some(path.clone())),
+            _ => anyhow::bail!(
+                "More than one dynamic library built, missing package selection. Select one using something like `cargo multivers --package my_library`"
+            ),
+        }
+    }
+
     fn find_executable(self) -> anyhow::Result<Option<PathBuf>> {
         let found = self.find_executables().collect::<Result<Vec<_>, _>>()?;
         match &found[..] {
@@ -3,7 +3,13 @@ use std::path::PathBuf;
 
 use clap::ColorChoice;
 
+use itertools::Itertools;
+
+use serde::Serialize;
+
+pub use crate::patches::Compression;
 use crate::rustc::Rustc;
+use crate::shard::Shard;
 
 #[derive(clap::Parser)]
 #[command(name = "cargo", bin_name = "cargo")]
@@ -12,6 +18,205 @@ pub enum Cargo {
     Multivers(Args),
 }
 
+/// Commands that do not build the package
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Aggregates the reports written by runners launched with `MULTIVERS_REPORT=<path>` into a table of the selected builds
+    ReportSummary {
+        /// Paths to the report files
+        #[clap(required = true, value_name = "PATH")]
+        reports: Vec<PathBuf>,
+    },
+
+    /// Runs a binary built by cargo multivers and the build it selects several times, to measure the overhead of the runner
+    Bench {
+        /// Number of times each binary is run
+        #[clap(long, default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+
+        /// Path to the binary built by cargo multivers
+        #[clap(value_name = "BINARY")]
+        binary: PathBuf,
+
+        /// Arguments given to the binary
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Packs existing binaries into a runner, without building a package
+    Pack(Box<PackArgs>),
+
+    /// Merges the versions emitted by the shards of a build (`--shard` and `--emit-variants`) into a runner
+    Merge(Box<MergeArgs>),
+
+    /// Builds a package with the release profile and installs its runner, like `cargo install`
+    Install(Box<InstallArgs>),
+
+    /// Removes the binaries of packages installed with `cargo multivers install`
+    Uninstall(UninstallArgs),
+}
+
+/// Arguments of `cargo multivers pack`
+#[derive(clap::Args)]
+pub struct PackArgs {
+    /// Binaries to pack, each with the comma-separated list of CPU features it requires
+    /// (e.g., `my-binary-v3=avx,avx2,fma`, or `my-binary=` for a binary that requires none)
+    #[clap(
+        value_name = "PATH=CPU-FEATURES",
+        value_parser = parse_build,
+        required_unless_present = "description",
+        conflicts_with = "description"
+    )]
+    pub builds: Vec<(PathBuf, Vec<String>)>,
+
+    /// Path to a JSON file that describes the binaries to pack (e.g., the `builds.json` written by `cargo multivers`)
+    #[clap(long, value_name = "PATH")]
+    pub description: Option<PathBuf>,
+
+    /// Name of the runner (by default, the file name of the binary that requires the fewest CPU features)
+    #[clap(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// Target triple of the binaries
+    #[clap(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Copy the runner to this directory
+    #[clap(long, value_name = "PATH")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Directory for all generated artifacts
+    #[clap(
+        long,
+        value_name = "DIRECTORY",
+        default_value = "target/cargo-multivers"
+    )]
+    pub target_dir: PathBuf,
+
+    #[command(flatten)]
+    pub runner: RunnerArgs,
+}
+
+/// Arguments of `cargo multivers merge`
+#[derive(clap::Args)]
+pub struct MergeArgs {
+    /// Directories given to `--emit-variants` by each shard
+    #[clap(required = true, value_name = "DIRECTORY")]
+    pub directories: Vec<PathBuf>,
+
+    /// Copy the runners to this directory
+    #[clap(long, value_name = "PATH")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Directory for all generated artifacts
+    #[clap(
+        long,
+        value_name = "DIRECTORY",
+        default_value = "target/cargo-multivers"
+    )]
+    pub target_dir: PathBuf,
+
+    #[command(flatten)]
+    pub runner: RunnerArgs,
+}
+
+/// Arguments of `cargo multivers install`
+#[derive(clap::Args)]
+pub struct InstallArgs {
+    /// Directory of the package to install
+    #[clap(
+        long,
+        value_name = "PATH",
+        required_unless_present = "git",
+        conflicts_with = "git"
+    )]
+    pub path: Option<PathBuf>,
+
+    /// Git repository of the package to install (e.g., the path to a local repository)
+    #[clap(long, value_name = "URL")]
+    pub git: Option<String>,
+
+    /// Branch of the repository to install
+    #[clap(long, value_name = "BRANCH", requires = "git", conflicts_with_all = ["tag", "rev"])]
+    pub branch: Option<String>,
+
+    /// Tag of the repository to install
+    #[clap(long, value_name = "TAG", requires = "git", conflicts_with = "rev")]
+    pub tag: Option<String>,
+
+    /// Commit of the repository to install
+    #[clap(long, value_name = "SHA", requires = "git")]
+    pub rev: Option<String>,
+
+    /// Directory to install the binaries in (in its `bin` subdirectory), `$CARGO_INSTALL_ROOT` or `$CARGO_HOME` by default
+    /// (the `install.root` key of the Cargo configuration is not read)
+    #[clap(long, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+
+    /// Overwrite the binaries installed by other packages, or not by `cargo multivers install`
+    #[clap(long)]
+    pub force: bool,
+
+    /// Build for the target triple
+    #[clap(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Comma-separated list of CPUs to use as a target
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        value_name = "CPUs"
+    )]
+    pub cpus: Option<Vec<String>>,
+
+    /// Minimum CPU to support, its build is used when the host supports no other build
+    #[clap(long, value_name = "CPU")]
+    pub baseline_cpu: Option<String>,
+
+    /// Directory for all generated artifacts (a temporary directory, removed once installed, by default)
+    #[clap(long, value_name = "DIRECTORY")]
+    pub target_dir: Option<PathBuf>,
+
+    #[command(flatten, next_help_heading = "Feature Selection")]
+    pub features: clap_cargo::Features,
+
+    #[command(flatten)]
+    pub runner: RunnerArgs,
+
+    /// Arguments given to cargo build
+    #[clap(raw = true)]
+    pub args: Vec<String>,
+}
+
+/// Arguments of `cargo multivers uninstall`
+#[derive(clap::Args)]
+pub struct UninstallArgs {
+    /// Packages to uninstall
+    #[clap(required = true, value_name = "PACKAGE")]
+    pub packages: Vec<String>,
+
+    /// Directory the binaries were installed in, `$CARGO_INSTALL_ROOT` or `$CARGO_HOME` by default
+    /// (the `install.root` key of the Cargo configuration is not read)
+    #[clap(long, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+}
+
+/// Parses a binary to pack and the CPU features it requires (`PATH=CPU-FEATURES`)
+fn parse_build(build: &str) -> Result<(PathBuf, Vec<String>), String> {
+    let (path, features) = build
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected `PATH=CPU-FEATURES`, got `{build}`"))?;
+
+    let features = features
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+
+    Ok((path.into(), features))
+}
+
 /// Type of information to print on stdout
 #[derive(clap::ValueEnum, Clone, Copy)]
 pub enum Print {
@@ -19,15 +224,80 @@ pub enum Print {
     CpuFeatures,
 }
 
+impl clap::ValueEnum for Compression {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Lz4, Self::Zstd, Self::Xz, Self::None]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        let help = match self {
+            Self::Lz4 => "LZ4, fast to decompress",
+            Self::Zstd => {
+                "Zstandard, smaller than LZ4 but slower to decompress (levels 1 to 22, 19 by default)"
+            }
+            Self::Xz => {
+                "XZ, the smallest but the slowest to decompress (levels 0 to 9, 6 by default)"
+            }
+            Self::None => "No compression",
+        };
+
+        Some(clap::builder::PossibleValue::new(self.name()).help(help))
+    }
+}
+
+/// Algorithm that generates the patches from the source build to the other builds
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaEncoder {
+    /// gdelta, fast to generate
+    Gdelta,
+    /// bsdiff, slower to generate, but often smaller when the builds have many small differences
+    Bsdiff,
+}
+
+/// How the patches of the builds are organized in the runner
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaLayout {
+    /// Every build is patched from the source build
+    Star,
+    /// Every build is patched from its nearest build (a minimum spanning tree rooted at the source),
+    /// smaller but slower to generate and to extract
+    Tree,
+}
+
+/// How the builds are stored beside a runner that does not embed them
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalVariants {
+    /// A `<runner>.variants` directory with a file per build, named after its SHA3-256 hash
+    Directory,
+    /// A `<runner>.variants` archive that contains all the builds
+    Archive,
+}
+
 #[derive(clap::Args)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Arguments given to cargo build
     #[clap(raw = true)]
     pub args: Vec<String>,
 
-    /// Build for the target triple
+    /// Build for the target triple (repeat it to build a runner for each target, copied to the `<TRIPLE>` subdirectory of `--out-dir`)
     #[clap(long, value_name = "TRIPLE", help_heading = "Compilation Options")]
-    pub target: Option<String>,
+    pub target: Vec<String>,
+
+    /// Also write to `--out-dir` a POSIX shell script that executes the runner built for the architecture of the host (`uname -m`)
+    #[clap(
+        long,
+        requires = "out_dir",
+        conflicts_with_all = ["emit_variants", "glibc_hwcaps"],
+        help_heading = "Compilation Options"
+    )]
+    pub launcher: bool,
 
     /// Print information on stdout
     #[clap(long, value_name = "INFORMATION")]
@@ -43,6 +313,10 @@ pub struct Args {
     )]
     pub cpus: Option<Vec<String>>,
 
+    /// Minimum CPU to support, its build is used when the host supports no other build
+    #[clap(long, value_name = "CPU", help_heading = "Compilation Options")]
+    pub baseline_cpu: Option<String>,
+
     /// Comma-separated list of CPU features to exclude from the builds
     #[clap(
         long,
@@ -53,14 +327,34 @@ pub struct Args {
     )]
     pub exclude_cpu_features: Option<Vec<String>>,
 
-    /// Specify the version of the runner to use
+    /// Build one version per CPU tuned with `-Ctarget-cpu`, even if CPUs have the same CPU features
+    #[clap(long, help_heading = "Compilation Options")]
+    pub tune_cpus: bool,
+
+    /// Only build the versions of a shard (e.g., `2/4` for the second of four shards), to split the build across CI jobs
     #[clap(
         long,
-        value_name = "VERSION",
-        default_value = "0.3",
-        help_heading = "Runner Options"
+        value_name = "INDEX/COUNT",
+        requires = "emit_variants",
+        help_heading = "Compilation Options"
     )]
-    pub runner_version: String,
+    pub shard: Option<Shard>,
+
+    /// Write the versions and their description to this directory instead of building a runner (see `cargo multivers merge`)
+    #[clap(long, value_name = "DIRECTORY", help_heading = "Compilation Options")]
+    pub emit_variants: Option<PathBuf>,
+
+    /// Build the `cdylib` of the package for each x86-64 level, in the `glibc-hwcaps` layout glibc loads it from,
+    /// instead of building a runner
+    #[clap(
+        long,
+        conflicts_with_all = ["emit_variants", "tune_cpus"],
+        help_heading = "Compilation Options"
+    )]
+    pub glibc_hwcaps: bool,
+
+    #[command(flatten)]
+    pub runner: RunnerArgs,
 
     /// Build artifacts with the specified profile
     #[clap(
@@ -83,14 +377,6 @@ pub struct Args {
     #[clap(long, value_name = "DIRECTORY", help_heading = "Compilation Options")]
     pub target_dir: Option<PathBuf>,
 
-    #[clap(long, value_delimiter = ' ', help_heading = "Runner Options")]
-    /// Space-separated list of features to activate for the runner (only "debug" is available at the moment)
-    pub runner_features: Vec<String>,
-
-    #[clap(long, help_heading = "Runner Options")]
-    /// Path to a custom runner Cargo.toml (by default, one is generated automatically)
-    pub runner_manifest_path: Option<PathBuf>,
-
     #[command(flatten, next_help_heading = "Manifest Options")]
     pub manifest: clap_cargo::Manifest,
 
@@ -104,9 +390,143 @@ pub struct Args {
 impl Args {
     /// Returns the target given on the command line or the default target that rustc uses to build if none is provided
     pub fn target(&self) -> anyhow::Result<Cow<'_, str>> {
-        self.target.as_deref().map_or_else(
-            || Rustc::default_target().map(Cow::Owned),
-            |target| Ok(target.into()),
-        )
+        match self.target.as_slice() {
+            [] => target_or_default(None),
+            [target] => Ok(target.into()),
+            _ => anyhow::bail!("Only one target can be given with `--print`"),
+        }
+    }
+
+    /// Returns the targets given on the command line (without duplicates),
+    /// or the default target that rustc uses to build if none is provided
+    pub fn targets(&self) -> anyhow::Result<Vec<Cow<'_, str>>> {
+        if self.target.is_empty() {
+            return Ok(vec![target_or_default(None)?]);
+        }
+
+        Ok(self
+            .target
+            .iter()
+            .unique()
+            .map(|target| target.into())
+            .collect())
     }
+
+    /// Whether the runners are copied to a subdirectory of the output directory named after their target
+    pub fn out_dir_per_target(&self) -> bool {
+        self.launcher || self.target.iter().unique().count() > 1
+    }
+}
+
+/// Options of the runner that embeds the builds
+#[derive(clap::Args, Clone)]
+pub struct RunnerArgs {
+    /// Specify the version of the runner to use
+    #[clap(
+        long,
+        value_name = "VERSION",
+        default_value = "0.3",
+        help_heading = "Runner Options"
+    )]
+    pub runner_version: String,
+
+    #[clap(long, value_delimiter = ' ', help_heading = "Runner Options")]
+    /// Space-separated list of features to activate for the runner ("debug" or "all-cpus")
+    pub runner_features: Vec<String>,
+
+    #[clap(long, help_heading = "Runner Options")]
+    /// Store the source build uncompressed, so that the runner starts faster when it selects it (the runner is larger)
+    pub uncompressed_source: bool,
+
+    #[clap(
+        long,
+        value_name = "COMPRESSION",
+        default_value = "lz4",
+        help_heading = "Runner Options"
+    )]
+    /// Compression of the builds embedded in the runner
+    pub compression: Compression,
+
+    #[clap(long, value_name = "LEVEL", help_heading = "Runner Options")]
+    /// Compression level (depends on the compression)
+    pub compression_level: Option<i32>,
+
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        value_name = "ENCODERS",
+        default_value = "gdelta",
+        help_heading = "Runner Options"
+    )]
+    /// Comma-separated list of algorithms that generate the patches (the smallest patch of each build is kept)
+    pub delta_encoders: Vec<DeltaEncoder>,
+
+    #[clap(
+        long,
+        value_name = "LAYOUT",
+        default_value = "star",
+        help_heading = "Runner Options"
+    )]
+    /// Layout of the patches of the builds (the sizes of both layouts are reported with `tree`)
+    pub delta_layout: DeltaLayout,
+
+    #[clap(long, value_name = "STUB", num_args = 0..=1, help_heading = "Runner Options")]
+    /// Append the builds to a prebuilt runner stub instead of compiling a runner that embeds them
+    /// (without a path, the stub is built once for the target and the runner features, then reused)
+    pub runner_stub: Option<Option<PathBuf>>,
+
+    #[clap(
+        long,
+        value_name = "LAYOUT",
+        num_args = 0..=1,
+        default_missing_value = "directory",
+        conflicts_with = "runner_stub",
+        help_heading = "Runner Options"
+    )]
+    /// Store the builds in files beside the runner instead of embedding them, the runner checks their hash before running them
+    /// (a `<runner>.variants` directory by default)
+    pub external_variants: Option<ExternalVariants>,
+
+    #[clap(long, help_heading = "Runner Options")]
+    /// Path to a custom runner Cargo.toml (by default, one is generated automatically)
+    pub runner_manifest_path: Option<PathBuf>,
+}
+
+impl RunnerArgs {
+    /// Returns the compression level given on the command line, after checking that the compression supports it
+    pub fn compression_level(&self) -> anyhow::Result<Option<i32>> {
+        let Some(level) = self.compression_level else {
+            return Ok(None);
+        };
+
+        match self.compression.levels() {
+            Some(levels) if levels.contains(&level) => Ok(Some(level)),
+            Some(levels) => anyhow::bail!(
+                "The level of the `{}` compression must be between {} and {}",
+                self.compression.name(),
+                levels.start(),
+                levels.end()
+            ),
+            None => anyhow::bail!(
+                "The `{}` compression has no levels",
+                self.compression.name()
+            ),
+        }
+    }
+}
+
+impl PackArgs {
+    /// Returns the target given on the command line or the default target that rustc uses to build if none is provided
+    pub fn target(&self) -> anyhow::Result<Cow<'_, str>> {
+        target_or_default(self.target.as_deref())
+    }
+}
+
+/// Returns the given target or the default target that rustc uses to build if none is provided
+fn target_or_default(target: Option<&str>) -> anyhow::Result<Cow<'_, str>> {
+    target.map_or_else(
+        || Rustc::default_target().map(Cow::Owned),
+        |target| Ok(target.into()),
+    )
 }
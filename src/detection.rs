@@ -0,0 +1,38 @@
+//! The CPU features the runner can detect on each architecture, shared with the runner
+//!
+//! The code is included from the sources of the runner embedded in `cargo-multivers` (`multivers-runner/build/detection.rs`),
+//! so that `cargo multivers pack` checks the features of the binaries against the tables the runner uses.
+#![allow(dead_code, reason = "only the runner uses the tables of its backends")]
+include!(concat!(
+    env!("OUT_DIR"),
+    "/multivers-runner/build/detection.rs"
+));
+
+#[cfg(test)]
+mod tests {
+    use super::is_detectable;
+
+    #[test]
+    fn detectable_by_each_backend() {
+        // `std::arch`, `CPUID`, and `/proc/cpuinfo`
+        assert!(is_detectable("x86_64", "windows", "avx2", false));
+        assert!(is_detectable("x86_64", "windows", "avx10.1", false));
+        assert!(is_detectable("x86_64", "linux", "lahfsahf", false));
+        assert!(!is_detectable("x86_64", "windows", "unknown", false));
+
+        // The kernel reports `cssc`, which is only detected by a nightly `std::arch` macro elsewhere
+        assert!(is_detectable("aarch64", "linux", "cssc", false));
+        assert!(!is_detectable("aarch64", "macos", "cssc", false));
+        assert!(is_detectable("aarch64", "macos", "cssc", true));
+
+        assert!(!is_detectable(
+            "riscv64",
+            "linux",
+            "unaligned-scalar-mem",
+            false
+        ));
+        assert!(is_detectable("riscv64", "linux", "zba", false));
+        assert!(is_detectable("powerpc64", "linux", "power8-vector", false));
+        assert!(!is_detectable("powerpc", "linux", "power10-vector", true));
+    }
+}
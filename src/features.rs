@@ -11,7 +11,7 @@ use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use itertools::Itertools;
 
 use crate::metadata::{MultiversMetadata, TargetMetadata};
-use crate::rustc::Rustc;
+use crate::rustc::{IGNORED_FEATURES, Rustc};
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CpuFeatures(BTreeSet<String>);
@@ -29,6 +29,23 @@ impl CpuFeatures {
         features_flags.trim_end_matches(',').into()
     }
 
+    /// Builds the `rustc` flags that tune the code for a CPU while only enabling the given CPU features
+    /// (e.g., `-Ctarget-cpu=skylake -Ctarget-feature=+aes,+avx,-avx512f`)
+    ///
+    /// `-Ctarget-cpu` enables all the CPU features of the CPU, so the disabled ones (see [`Cpus::disabled_features`]) are explicitly disabled.
+    pub fn to_tuned_compiler_flags(&self, cpu: &str, disabled: &[String]) -> String {
+        let disabled_flags = disabled
+            .iter()
+            .filter(|feature| !self.0.contains(*feature))
+            .map(|feature| format!(",-{feature}"))
+            .collect::<String>();
+
+        format!(
+            "-Ctarget-cpu={cpu} -Ctarget-feature={}{disabled_flags}",
+            self.to_compiler_flags()
+        )
+    }
+
     /// Consumes the [`CpuFeatures`] and returns a [`Vec`] containing the features
     pub fn into_vec(self) -> Vec<String> {
         self.0.into_iter().collect()
@@ -38,6 +55,15 @@ impl CpuFeatures {
 pub struct Cpus {
     /// Maps a CPU (e.g., `alderlake`) to its list of CPU features (`adx`, `aes`,...)
     features: BTreeMap<String, CpuFeatures>,
+
+    /// The baseline CPU (i.e., the minimum supported CPU) and its CPU features, if any
+    baseline: Option<(String, CpuFeatures)>,
+
+    /// CPU features excluded from the builds
+    excluded_features: Vec<String>,
+
+    /// Maps a CPU to the ignored CPU features (see [`IGNORED_FEATURES`]) it enables, but the default CPU of the target does not
+    ignored_features: BTreeMap<String, BTreeSet<String>>,
 }
 
 impl Cpus {
@@ -50,6 +76,59 @@ impl Cpus {
         self.features.values().sorted().dedup()
     }
 
+    /// Returns an iterator over each CPU (e.g., `alderlake`) and its set of CPU features
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CpuFeatures)> {
+        self.features
+            .iter()
+            .map(|(cpu, features)| (cpu.as_str(), features))
+    }
+
+    /// Returns the CPUs (e.g., `alderlake`) that have the given set of CPU features
+    pub fn cpus_with_features<'a>(
+        &'a self,
+        features: &'a CpuFeatures,
+    ) -> impl Iterator<Item = &'a str> {
+        self.features
+            .iter()
+            .filter(move |(_, cpu_features)| *cpu_features == features)
+            .map(|(cpu, _)| cpu.as_str())
+    }
+
+    /// Returns true if the CPU is part of the CPUs to build with
+    pub fn contains(&self, cpu: &str) -> bool {
+        self.features.contains_key(cpu)
+    }
+
+    /// Returns the CPU features set of the baseline CPU, if one was set
+    pub fn baseline(&self) -> Option<&CpuFeatures> {
+        self.baseline.as_ref().map(|(_, features)| features)
+    }
+
+    /// Returns the baseline CPU, if one was set
+    pub fn baseline_cpu(&self) -> Option<&str> {
+        self.baseline.as_ref().map(|(cpu, _)| cpu.as_str())
+    }
+
+    /// Returns the CPU features to disable when tuning the code for a CPU with `-Ctarget-cpu`
+    ///
+    /// They are the excluded features, and the ignored features (e.g., `pmuv3`) that the CPU enables,
+    /// since they are not part of the CPU features of the build and would make it require more than them.
+    pub fn disabled_features(&self, cpu: &str) -> Vec<String> {
+        self.excluded_features
+            .iter()
+            .cloned()
+            .chain(
+                self.ignored_features
+                    .get(cpu)
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
     /// Returns a sorted and deduplicated iterator of CPU features supported by these CPUs
     pub fn features(&self) -> impl Iterator<Item = &str> {
         self.features
@@ -68,6 +147,9 @@ pub struct CpusBuilder {
     triple: Triple,
     cpus: Option<Vec<String>>,
     metadata_cpus: Option<Vec<String>>,
+    default_cpus: Option<Vec<String>>,
+    baseline: Option<String>,
+    metadata_baseline: Option<String>,
 }
 
 impl CpusBuilder {
@@ -81,6 +163,9 @@ impl CpusBuilder {
             triple,
             cpus: None,
             metadata_cpus: None,
+            default_cpus: None,
+            baseline: None,
+            metadata_baseline: None,
         })
     }
 
@@ -89,6 +174,13 @@ impl CpusBuilder {
         self
     }
 
+    /// Sets the CPUs to build with when neither [`CpusBuilder::cpus`] nor the metadata set them,
+    /// instead of all the CPUs of the target
+    pub fn default_cpus(mut self, cpus: impl Into<Option<Vec<String>>>) -> Self {
+        self.default_cpus = cpus.into();
+        self
+    }
+
     pub fn metadata(mut self, metadata: &MultiversMetadata) -> anyhow::Result<Self> {
         if let Some(cpus) = metadata
             .get(&self.triple.architecture)
@@ -99,9 +191,22 @@ impl CpusBuilder {
             self.metadata_cpus = Some(cpus.to_vec());
         }
 
+        self.metadata_baseline = metadata
+            .baseline(&self.triple.architecture)
+            .map(ToOwned::to_owned);
+
         Ok(self)
     }
 
+    /// Sets the baseline CPU, i.e., the minimum CPU that must be supported.
+    ///
+    /// The baseline CPU is always built and its build is used as the fallback by the runner.
+    /// CPUs that do not support all the features of the baseline CPU are not included in the final list.
+    pub fn baseline(mut self, cpu: impl Into<Option<String>>) -> Self {
+        self.baseline = cpu.into();
+        self
+    }
+
     /// Excludes the given list of CPU features when building the set of CPUs and their features.
     ///
     /// If a CPU no longer has any feature, it is not included in the final list.
@@ -111,24 +216,57 @@ impl CpusBuilder {
     }
 
     pub fn build(&self) -> anyhow::Result<Cpus> {
-        let iter = if let Some(cpus) = self.cpus.as_ref().or(self.metadata_cpus.as_ref()) {
+        let mut cpus = if let Some(cpus) = self
+            .cpus
+            .as_ref()
+            .or(self.metadata_cpus.as_ref())
+            .or(self.default_cpus.as_ref())
+        {
             cpus.to_owned()
         } else {
             Rustc::cpus_from_target(&self.target)
                 .context("Failed to get the set of CPUs for the target")?
+        };
+
+        let baseline = self.baseline.as_ref().or(self.metadata_baseline.as_ref());
+        if let Some(baseline) = baseline {
+            anyhow::ensure!(
+                Self::is_cpu_for_target_valid(&self.triple, baseline),
+                "The baseline CPU `{baseline}` is not supported by the target `{}`",
+                self.target
+            );
+
+            // The baseline must be built even if it is not part of the list of CPUs
+            if !cpus.contains(baseline) {
+                cpus.push(baseline.clone());
+            }
         }
-        .into_par_iter();
 
-        let features: BTreeMap<_, _> = iter
+        let enabled_features: BTreeMap<_, _> = cpus
+            .into_par_iter()
             .filter(|cpu| Self::is_cpu_for_target_valid(&self.triple, cpu))
             .map(|cpu| {
-                let features = Rustc::features_from_cpu(&self.target, &cpu)?;
+                let features = Rustc::enabled_features(&self.target, Some(&cpu))?;
 
                 Ok((cpu, features))
             })
             .collect::<anyhow::Result<_>>()?;
 
-        let features = features
+        // The ignored features that the default CPU enables are already required by every build
+        let default_features = Rustc::enabled_features(&self.target, None)?;
+        let (features, ignored_features): (BTreeMap<_, _>, BTreeMap<_, _>) = enabled_features
+            .into_iter()
+            .map(|(cpu, features)| {
+                let (ignored, features): (BTreeSet<_>, BTreeSet<_>) = features
+                    .into_iter()
+                    .partition(|feature| IGNORED_FEATURES.contains(&feature.as_str()));
+                let ignored = ignored.difference(&default_features).cloned().collect();
+
+                ((cpu.clone(), features), (cpu, ignored))
+            })
+            .unzip();
+
+        let mut features: BTreeMap<_, _> = features
             .into_iter()
             .filter_map(|(cpu, mut features)| {
                 for exclude in self.excluded_features.iter().flatten() {
@@ -142,7 +280,28 @@ impl CpusBuilder {
             })
             .collect();
 
-        Ok(Cpus { features })
+        let baseline = baseline
+            .map(|cpu| {
+                let features = features.get(cpu).cloned().with_context(|| {
+                    format!("The baseline CPU `{cpu}` has no CPU features left once the excluded features are removed")
+                })?;
+
+                anyhow::Ok((cpu.clone(), features))
+            })
+            .transpose()?;
+
+        if let Some((_, baseline)) = &baseline {
+            // A build that does not require all the features of the baseline would only be
+            // selected on CPUs older than the baseline, which are not supported.
+            features.retain(|_, features: &mut CpuFeatures| features.0.is_superset(&baseline.0));
+        }
+
+        Ok(Cpus {
+            features,
+            baseline,
+            excluded_features: self.excluded_features.clone().unwrap_or_default(),
+            ignored_features,
+        })
     }
 
     fn is_cpu_for_target_valid(triple: &Triple, cpu: &str) -> bool {
@@ -189,3 +348,27 @@ impl CpusBuilder {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Cpus;
+
+    #[test]
+    fn disabled_features_include_ignored_features_of_cpu() {
+        let cpus = Cpus::builder("aarch64-unknown-linux-gnu")
+            .unwrap()
+            .cpus(vec!["neoverse-n1".to_owned()])
+            .exclude_features(vec!["sve".to_owned()])
+            .build()
+            .unwrap();
+        let disabled = cpus.disabled_features("neoverse-n1");
+        assert!(disabled.contains(&"pmuv3".to_owned()));
+        assert!(disabled.contains(&"sve".to_owned()));
+
+        let (_, features) = cpus.iter().next().unwrap();
+        let flags = features.to_tuned_compiler_flags("neoverse-n1", &disabled);
+        assert!(flags.starts_with("-Ctarget-cpu=neoverse-n1 -Ctarget-feature=+"));
+        assert!(flags.contains(",-pmuv3"));
+        assert!(!flags.contains("+pmuv3"));
+    }
+}
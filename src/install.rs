@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
+
+use console::style;
+
+use crate::cli::{Args, InstallArgs, UninstallArgs};
+use crate::multivers::Multivers;
+
+/// Name of the file, in the root directory, that records the binaries installed by `cargo multivers install`
+const INSTALL_RECORD_FILENAME: &str = ".multivers.json";
+
+/// A package installed by `cargo multivers install`
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub version: String,
+
+    /// Where the package was installed from (`path+<directory>` or `git+<url>`)
+    pub source: String,
+
+    /// File names of the binaries installed in the `bin` directory of the root
+    pub bins: BTreeSet<String>,
+}
+
+/// The packages installed in a root directory, to replace or remove their binaries
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub packages: BTreeMap<String, InstalledPackage>,
+}
+
+impl InstallRecord {
+    /// Reads the record of the root directory, or returns an empty one if nothing was installed in it
+    pub fn read(root: &Path) -> anyhow::Result<Self> {
+        let path = root.join(INSTALL_RECORD_FILENAME);
+        match std::fs::read(&path) {
+            Ok(record) => serde_json::from_slice(&record)
+                .with_context(|| format!("Failed to parse `{}`", path.display())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => {
+                Err(error).with_context(|| format!("Failed to read `{}`", path.display()))
+            }
+        }
+    }
+
+    /// Writes the record to the root directory
+    pub fn write(&self, root: &Path) -> anyhow::Result<()> {
+        let path = root.join(INSTALL_RECORD_FILENAME);
+        let record =
+            serde_json::to_vec_pretty(self).context("Failed to encode the install record")?;
+
+        std::fs::write(&path, record)
+            .with_context(|| format!("Failed to write `{}`", path.display()))
+    }
+
+    /// Returns the package that installed a binary
+    pub fn owner(&self, bin: &str) -> Option<&str> {
+        self.packages
+            .iter()
+            .find(|(_, package)| package.bins.contains(bin))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Returns the root directory given on the command line, or the one of `cargo install` (`$CARGO_INSTALL_ROOT`, `$CARGO_HOME`, or `~/.cargo`)
+///
+/// Unlike `cargo install`, the `install.root` key of the Cargo configuration files is not read, since they are not parsed.
+fn install_root(root: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(root) = root {
+        return Ok(root.to_path_buf());
+    }
+
+    std::env::var_os("CARGO_INSTALL_ROOT")
+        .or_else(|| std::env::var_os("CARGO_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".cargo")))
+        .context("Failed to find the home directory, give the root directory with `--root`")
+}
+
+/// Removes a binary and its external variants from the `bin` directory, if they exist
+fn remove_bin(bin_dir: &Path, bin: &str) -> anyhow::Result<()> {
+    let variants = bin_dir.join(format!("{bin}.variants"));
+    for path in [bin_dir.join(bin), variants] {
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        }
+        .or_else(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(error),
+        })
+        .with_context(|| format!("Failed to remove `{}`", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Copies a file or a directory (of external variants) recursively
+fn copy_recursively(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)
+            .with_context(|| format!("Failed to create directory `{}`", to.display()))?;
+        for entry in std::fs::read_dir(from)
+            .with_context(|| format!("Failed to read directory `{}`", from.display()))?
+        {
+            let entry =
+                entry.with_context(|| format!("Failed to read directory `{}`", from.display()))?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to).with_context(|| {
+            format!("Failed to copy `{}` to `{}`", from.display(), to.display())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Builds a package with the release profile and installs its runner (or its single version) like `cargo install`
+pub struct Install {
+    args: InstallArgs,
+}
+
+impl Install {
+    pub fn new(args: InstallArgs) -> Self {
+        Self { args }
+    }
+
+    /// Clones the repository given to `--git` into the directory, and checks out the requested revision
+    fn clone_repository(&self, url: &str, directory: &Path) -> anyhow::Result<()> {
+        let mut clone = std::process::Command::new("git");
+        clone.args(["clone", "--quiet"]);
+        if let Some(reference) = self.args.branch.as_ref().or(self.args.tag.as_ref()) {
+            clone.args(["--branch", reference]);
+        }
+        let status = clone
+            .arg("--")
+            .arg(url)
+            .arg(directory)
+            .status()
+            .context("Failed to execute `git clone`")?;
+        anyhow::ensure!(status.success(), "Failed to clone the repository `{url}`");
+
+        if let Some(rev) = &self.args.rev {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(directory)
+                .args(["checkout", "--quiet", "--detach", rev])
+                .status()
+                .context("Failed to execute `git checkout`")?;
+            anyhow::ensure!(
+                status.success(),
+                "Failed to check out the revision `{rev}` of `{url}`"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the arguments of `cargo multivers` to build the package into the output directory
+    fn build_args(
+        &self,
+        manifest_path: PathBuf,
+        package: String,
+        target_dir: &Path,
+        out_dir: &Path,
+    ) -> Args {
+        let mut manifest = clap_cargo::Manifest::default();
+        manifest.manifest_path = Some(manifest_path);
+        let mut workspace = clap_cargo::Workspace::default();
+        workspace.package = vec![package];
+
+        Args {
+            command: None,
+            args: self.args.args.clone(),
+            target: self.args.target.iter().cloned().collect(),
+            launcher: false,
+            print: None,
+            cpus: self.args.cpus.clone(),
+            baseline_cpu: self.args.baseline_cpu.clone(),
+            exclude_cpu_features: None,
+            tune_cpus: false,
+            shard: None,
+            emit_variants: None,
+            glibc_hwcaps: false,
+            runner: self.args.runner.clone(),
+            profile: "release".to_owned(),
+            color: clap::ColorChoice::Auto,
+            out_dir: Some(out_dir.to_path_buf()),
+            target_dir: Some(target_dir.to_path_buf()),
+            manifest,
+            workspace,
+            features: self.args.features.clone(),
+        }
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = install_root(self.args.root.as_deref())?;
+        // Like `cargo install`, the package is built in a temporary directory (removed once dropped), unless one is given
+        let temporary_dir;
+        let work_dir = match &self.args.target_dir {
+            Some(target_dir) => target_dir.as_path(),
+            None => {
+                temporary_dir = tempfile::Builder::new()
+                    .prefix("cargo-multivers-install-")
+                    .tempdir()
+                    .context("Failed to create a temporary directory to build the package")?;
+                temporary_dir.path()
+            }
+        };
+
+        self.install(&root, work_dir)
+    }
+
+    fn install(&self, root: &Path, work_dir: &Path) -> anyhow::Result<()> {
+        let (directory, source) = match (&self.args.path, &self.args.git) {
+            (Some(path), _) => {
+                let path = std::fs::canonicalize(path)
+                    .with_context(|| format!("Failed to find `{}`", path.display()))?;
+                let source = format!("path+{}", path.display());
+
+                (path, source)
+            }
+            (None, Some(url)) => {
+                let directory = work_dir.join("source");
+                let _ = std::fs::remove_dir_all(&directory);
+                self.clone_repository(url, &directory)?;
+                let reference = self
+                    .args
+                    .rev
+                    .as_ref()
+                    .or(self.args.branch.as_ref())
+                    .or(self.args.tag.as_ref())
+                    .map(|reference| format!("#{reference}"))
+                    .unwrap_or_default();
+
+                (directory, format!("git+{url}{reference}"))
+            }
+            (None, None) => {
+                anyhow::bail!("A package to install must be given with `--path` or `--git`")
+            }
+        };
+
+        let manifest_path = directory.join("Cargo.toml");
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(&manifest_path)
+            .no_deps()
+            .exec()
+            .context("Failed to execute `cargo metadata`")?;
+        let package = metadata.root_package().with_context(|| {
+            format!(
+                "`{}` is a virtual manifest, give the directory of the package to install",
+                manifest_path.display()
+            )
+        })?;
+
+        println!(
+            "{:>12} {} v{} ({source})",
+            style("Installing").bold().green(),
+            package.name,
+            package.version,
+        );
+
+        let out_dir = work_dir.join("out");
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let args = self.build_args(
+            manifest_path.clone(),
+            package.name.to_string(),
+            &work_dir.join("target"),
+            &out_dir,
+        );
+        let target = args.target()?.into_owned();
+        let bins = Multivers::from_args(&args, target)?
+            .build()?
+            .into_iter()
+            .map(OsString::into_string)
+            .collect::<Result<BTreeSet<_>, _>>()
+            .map_err(|name| anyhow::anyhow!("Invalid binary name `{}`", name.to_string_lossy()))?;
+
+        let mut record = InstallRecord::read(root)?;
+        let bin_dir = root.join("bin");
+        for bin in &bins {
+            match record.owner(bin) {
+                Some(owner) if owner == package.name.as_str() => {}
+                Some(owner) if !self.args.force => anyhow::bail!(
+                    "The binary `{bin}` is already installed by the package `{owner}`, use `--force` to overwrite it"
+                ),
+                None if bin_dir.join(bin).exists() && !self.args.force => anyhow::bail!(
+                    "The binary `{bin}` already exists in `{}`, use `--force` to overwrite it",
+                    bin_dir.display()
+                ),
+                _ => {}
+            }
+        }
+
+        std::fs::create_dir_all(&bin_dir)
+            .with_context(|| format!("Failed to create directory `{}`", bin_dir.display()))?;
+        // The binaries that the package no longer builds are removed, and the ones of other packages are taken over (with `--force`)
+        if let Some(previous) = record.packages.remove(package.name.as_str()) {
+            for bin in previous.bins.difference(&bins) {
+                remove_bin(&bin_dir, bin)?;
+            }
+        }
+        for installed in record.packages.values_mut() {
+            installed.bins.retain(|bin| !bins.contains(bin));
+        }
+        record
+            .packages
+            .retain(|_, installed| !installed.bins.is_empty());
+
+        for bin in &bins {
+            remove_bin(&bin_dir, bin)?;
+            copy_recursively(&out_dir.join(bin), &bin_dir.join(bin))?;
+            let variants = format!("{bin}.variants");
+            if out_dir.join(&variants).exists() {
+                copy_recursively(&out_dir.join(&variants), &bin_dir.join(&variants))?;
+            }
+
+            println!(
+                "{:>12} {}",
+                style("Installed").bold().green(),
+                bin_dir.join(bin).display()
+            );
+        }
+
+        record.packages.insert(
+            package.name.to_string(),
+            InstalledPackage {
+                version: package.version.to_string(),
+                source,
+                bins,
+            },
+        );
+        record.write(root)
+    }
+}
+
+/// Removes the binaries of packages installed by `cargo multivers install`
+pub struct Uninstall {
+    args: UninstallArgs,
+}
+
+impl Uninstall {
+    pub fn new(args: UninstallArgs) -> Self {
+        Self { args }
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = install_root(self.args.root.as_deref())?;
+        let mut record = InstallRecord::read(&root)?;
+        let bin_dir = root.join("bin");
+
+        for name in &self.args.packages {
+            let package = record.packages.remove(name).with_context(|| {
+                format!(
+                    "The package `{name}` is not installed in `{}`",
+                    root.display()
+                )
+            })?;
+
+            for bin in &package.bins {
+                remove_bin(&bin_dir, bin)?;
+
+                println!(
+                    "{:>12} {}",
+                    style("Removing").bold().green(),
+                    bin_dir.join(bin).display()
+                );
+            }
+        }
+
+        record.write(&root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstallRecord, InstalledPackage};
+
+    #[test]
+    fn install_record() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            InstallRecord::read(root.path()).unwrap(),
+            InstallRecord::default()
+        );
+
+        let mut record = InstallRecord::default();
+        record.packages.insert(
+            "my-package".to_owned(),
+            InstalledPackage {
+                version: "1.0.0".to_owned(),
+                source: "path+/path/to/my-package".to_owned(),
+                bins: ["my-binary".to_owned()].into(),
+            },
+        );
+        record.write(root.path()).unwrap();
+
+        let read = InstallRecord::read(root.path()).unwrap();
+        assert_eq!(read, record);
+        assert_eq!(read.owner("my-binary"), Some("my-package"));
+        assert_eq!(read.owner("other-binary"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use console::style;
+
+use target_lexicon::{Architecture, OperatingSystem, Triple};
+
+/// A POSIX shell script that executes the runner built for the architecture of the host (`uname -m`)
+///
+/// The runner of each target is expected in the `<target>` subdirectory of the directory of the launcher,
+/// after resolving the symbolic links to the launcher (with `readlink -f`, if it is available).
+/// The target is only chosen by the architecture of the host, so targets of different operating systems
+/// or environments with the same architecture (e.g., `x86_64-unknown-linux-gnu` and `x86_64-apple-darwin`) are rejected.
+pub struct Launcher {
+    script: String,
+}
+
+impl Launcher {
+    /// Generates the launcher of the runners named `name` built for each target
+    pub fn new<'a>(name: &str, targets: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+        let mut architectures = Vec::<(String, &'a str)>::new();
+        for target in targets {
+            let triple = Triple::from_str(target).map_err(|error| {
+                anyhow::anyhow!("Failed to parse the target `{target}`: {error}")
+            })?;
+            anyhow::ensure!(
+                !matches!(triple.operating_system, OperatingSystem::Windows),
+                "The launcher is a POSIX shell script, so it cannot execute the runner of the target `{target}`"
+            );
+
+            let machines = machines(triple.architecture);
+            if let Some((_, other)) = architectures
+                .iter()
+                .find(|(other_machines, _)| *other_machines == machines)
+            {
+                anyhow::bail!(
+                    "The launcher cannot choose between the targets `{other}` and `{target}`, since it only selects a target by the architecture of the host (`uname -m`)"
+                );
+            }
+            architectures.push((machines, target));
+        }
+
+        let name = quote(name);
+        let mut script = "#!/bin/sh
+# Executes the runner built for the architecture of the host (generated by cargo multivers)
+path=$(readlink -f -- \"$0\" 2>/dev/null) && [ -n \"$path\" ] || path=$0
+directory=$(dirname -- \"$path\")
+case \"$(uname -m)\" in
+"
+        .to_owned();
+        for (machines, target) in architectures {
+            let _ = writeln!(
+                script,
+                "    {machines}) exec \"$directory\"/{}/{name} \"$@\" ;;",
+                quote(target)
+            );
+        }
+        let _ = write!(
+            script,
+            "    *) echo {name}\": no runner for the architecture $(uname -m)\" >&2; exit 1 ;;
+esac
+"
+        );
+
+        Ok(Self { script })
+    }
+
+    /// Writes the launcher to a file named `name` in the directory, and makes it executable
+    pub fn write_to(&self, directory: &Path, name: &OsStr) -> anyhow::Result<()> {
+        let path = directory.join(name);
+        std::fs::write(&path, &self.script)
+            .with_context(|| format!("Failed to write `{}`", path.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("Failed to make `{}` executable", path.display()))?;
+        }
+
+        println!(
+            "{:>12} launcher ({})",
+            style("Finished").bold().green(),
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Returns the pattern of the names of the machines with the architecture, as printed by `uname -m`
+fn machines(architecture: Architecture) -> String {
+    match architecture {
+        Architecture::X86_64 | Architecture::X86_64h => "x86_64|amd64".to_owned(),
+        Architecture::X86_32(_) => "i?86".to_owned(),
+        Architecture::Aarch64(_) => "aarch64|arm64".to_owned(),
+        Architecture::Arm(_) => "arm*".to_owned(),
+        Architecture::Riscv64(_) => "riscv64".to_owned(),
+        Architecture::Powerpc => "ppc".to_owned(),
+        Architecture::Powerpc64 => "ppc64".to_owned(),
+        Architecture::Powerpc64le => "ppc64le".to_owned(),
+        Architecture::LoongArch64 => "loongarch64".to_owned(),
+        architecture => architecture.to_string(),
+    }
+}
+
+/// Quotes a string for the shell
+fn quote(string: &str) -> String {
+    format!("'{}'", string.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Launcher, quote};
+
+    #[test]
+    fn launcher() {
+        let launcher = Launcher::new(
+            "my-binary",
+            ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-musl"],
+        )
+        .unwrap();
+
+        assert!(launcher.script.starts_with("#!/bin/sh\n"));
+        assert!(
+            launcher
+                .script
+                .contains("path=$(readlink -f -- \"$0\" 2>/dev/null)")
+        );
+        assert!(launcher.script.contains(
+            "    x86_64|amd64) exec \"$directory\"/'x86_64-unknown-linux-gnu'/'my-binary' \"$@\" ;;\n"
+        ));
+        assert!(launcher.script.contains(
+            "    aarch64|arm64) exec \"$directory\"/'aarch64-unknown-linux-musl'/'my-binary' \"$@\" ;;\n"
+        ));
+    }
+
+    #[test]
+    fn launcher_same_architecture() {
+        assert!(
+            Launcher::new(
+                "my-binary",
+                ["x86_64-unknown-linux-gnu", "x86_64-unknown-linux-musl"]
+            )
+            .is_err()
+        );
+        assert!(
+            Launcher::new(
+                "my-binary",
+                ["x86_64-unknown-linux-gnu", "x86_64-apple-darwin"]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn launcher_windows() {
+        assert!(Launcher::new("my-binary.exe", ["x86_64-pc-windows-msvc"]).is_err());
+    }
+
+    #[test]
+    fn quote_string() {
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
@@ -1,31 +1,70 @@
 //! Cargo subcommand to build multiple versions of the same binary, each with a different CPU features set, merged into a single portable optimized binary.
+use std::collections::BTreeMap;
 use std::io::Write;
 
 use anyhow::Context;
 
 use clap::Parser;
 
-use crate::cli::{Cargo, Print};
+use crate::bench::Bench;
+use crate::cli::{Cargo, Command, Print};
 use crate::features::Cpus;
+use crate::install::{Install, Uninstall};
+use crate::launcher::Launcher;
 use crate::multivers::Multivers;
+use crate::pack::Pack;
+use crate::report::ReportSummary;
+use crate::shard::Merge;
 
+#[cfg(test)]
+mod archive;
+mod bench;
 mod cargo;
 mod cli;
+mod detection;
 mod features;
+mod install;
+mod launcher;
 mod metadata;
 mod multivers;
+mod pack;
+mod patches;
+mod payload;
+mod report;
 mod runner;
 mod rustc;
+mod shard;
 
 fn main() -> anyhow::Result<()> {
     let Cargo::Multivers(args) = Cargo::parse();
 
+    match args.command {
+        Some(Command::ReportSummary { reports }) => {
+            let summary = ReportSummary::from_paths(reports)?;
+            print!("{summary}");
+
+            return Ok(());
+        }
+        Some(Command::Bench { runs, binary, args }) => {
+            let report = Bench::new(binary, args, runs).run()?;
+            print!("{report}");
+
+            return Ok(());
+        }
+        Some(Command::Pack(args)) => return Pack::from_args(*args)?.run(),
+        Some(Command::Merge(args)) => return Merge::new(*args).run(),
+        Some(Command::Install(args)) => return Install::new(*args).run(),
+        Some(Command::Uninstall(args)) => return Uninstall::new(args).run(),
+        None => {}
+    }
+
     if matches!(args.print, Some(Print::CpuFeatures)) {
         let target = args.target()?.into_owned();
 
         let cpus = Cpus::builder(target)
             .context("Failed to get the set of CPU features for the target")?
             .cpus(args.cpus)
+            .baseline(args.baseline_cpu)
             .build()?;
         let mut stdout = std::io::stdout().lock();
         for feature in cpus.features() {
@@ -35,5 +74,27 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    Multivers::from_args(args)?.build()
+    let targets = args.targets()?;
+    anyhow::ensure!(
+        targets.len() == 1 || args.emit_variants.is_none(),
+        "Only one target can be given with `--emit-variants`"
+    );
+
+    // The runners with the same name are executed by the same launcher
+    let mut runners = BTreeMap::<_, Vec<_>>::new();
+    for target in &targets {
+        for name in Multivers::from_args(&args, target.clone().into_owned())?.build()? {
+            runners.entry(name).or_default().push(target.as_ref());
+        }
+    }
+
+    if args.launcher
+        && let Some(out_dir) = &args.out_dir
+    {
+        for (name, targets) in runners {
+            Launcher::new(&name.to_string_lossy(), targets)?.write_to(out_dir, &name)?;
+        }
+    }
+
+    Ok(())
 }
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use cargo_metadata::Package;
 
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use serde_json::Value;
 
@@ -30,10 +31,43 @@ impl<'de> Deserialize<'de> for ArchitectureWrapper {
     }
 }
 
+/// A rule that makes the runner prefer the build of a CPU over the build of another one on some hosts
+/// (e.g., on CPUs where AVX-512 instructions reduce the frequency).
+///
+/// The rule applies to the hosts that match all the given vendor, family, and models.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SelectionRule {
+    /// Vendor of the host as reported by `CPUID` (e.g., `GenuineIntel` or `AuthenticAMD`)
+    #[serde(default)]
+    pub vendor: Option<String>,
+
+    /// Family of the host as reported by `CPUID` (e.g., `6` or `0x19`)
+    #[serde(default)]
+    pub family: Option<u32>,
+
+    /// Models of the host as reported by `CPUID` (e.g., `[0x8c, 0x8d]`)
+    #[serde(default)]
+    pub models: Vec<u32>,
+
+    /// CPU whose build is preferred (e.g., `x86-64-v3`)
+    pub prefer: String,
+
+    /// CPU whose build is not selected when the preferred one is supported (e.g., `x86-64-v4`)
+    pub over: String,
+}
+
 /// The options set for a given target
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Default, Deserialize)]
 pub struct TargetMetadata {
+    #[serde(default)]
     cpus: Option<Vec<String>>,
+
+    #[serde(default)]
+    baseline: Option<String>,
+
+    #[serde(default)]
+    rules: Vec<SelectionRule>,
 }
 
 impl TargetMetadata {
@@ -41,17 +75,69 @@ impl TargetMetadata {
     pub fn cpus(&self) -> Option<&[String]> {
         self.cpus.as_deref()
     }
+
+    /// Returns the baseline CPU explicitly set for this target.
+    pub fn baseline(&self) -> Option<&str> {
+        self.baseline.as_deref()
+    }
+
+    /// Returns the rules used by the runner to select a build for this target.
+    pub fn rules(&self) -> &[SelectionRule] {
+        &self.rules
+    }
 }
 
-impl<'de> Deserialize<'de> for TargetMetadata {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let mut metadata = HashMap::<String, Option<Vec<String>>>::deserialize(deserializer)?;
-        let cpus = metadata.remove("cpus").unwrap_or_default();
+/// Options of the crate generated to build the runner, set in the `[package.metadata.multivers.runner]` section.
+///
+/// They are ignored with `--runner-manifest-path`.
+///
+/// # Example
+///
+/// ```toml
+/// [package.metadata.multivers.runner]
+/// profile = { opt-level = 3, strip = "debuginfo" }
+/// main = "runner/main.rs"
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunnerMetadata {
+    /// Settings of the `release` profile of the runner that override the default ones (e.g., `opt-level = 3`)
+    #[serde(default)]
+    pub profile: BTreeMap<String, Value>,
+
+    /// Edition of the runner crate
+    #[serde(default)]
+    pub edition: Option<String>,
+
+    /// Additional dependencies of the runner crate (e.g., for a custom `main`)
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Value>,
+
+    /// Features of the runner crate enabled by default (e.g., `debug` or a feature of an additional dependency)
+    #[serde(default)]
+    pub features: Vec<String>,
+
+    /// Path of the source file that defines the `main` of the runner, relative to the manifest of the package.
+    ///
+    /// The `main` function of `multivers-runner` is then disabled, so the file must define it (e.g., with `multivers_runner::Runner`).
+    #[serde(default)]
+    pub main: Option<PathBuf>,
+}
 
-        Ok(Self { cpus })
+impl RunnerMetadata {
+    /// Makes the path of the `main` of the runner and the relative `path` of its additional dependencies
+    /// relative to the given directory instead of the manifest of the package
+    pub fn relative_to(mut self, directory: &Path) -> Self {
+        self.main = self.main.map(|main| directory.join(main));
+        for dependency in self.dependencies.values_mut() {
+            if let Some(Value::String(path)) = dependency.get_mut("path")
+                && Path::new(path).is_relative()
+            {
+                *path = directory.join(&*path).to_string_lossy().into_owned();
+            }
+        }
+
+        self
     }
 }
 
@@ -60,12 +146,26 @@ impl<'de> Deserialize<'de> for TargetMetadata {
 /// # Example
 ///
 /// ```toml
+/// [package.metadata.multivers]
+/// baseline = "x86-64-v2"
+///
 /// [package.metadata.multivers.x86_64]
 /// cpus = ["alderlake", "skylake", "sandybridge", "ivybridge"]
+///
+/// [[package.metadata.multivers.x86_64.rules]]
+/// vendor = "GenuineIntel"
+/// family = 6
+/// prefer = "skylake"
+/// over = "alderlake"
 /// ```
 #[derive(PartialEq, Eq, Debug)]
 pub struct MultiversMetadata {
     targets: HashMap<Architecture, TargetMetadata>,
+
+    /// Baseline CPU used for the targets that do not set their own
+    baseline: Option<String>,
+
+    runner: Option<RunnerMetadata>,
 }
 
 impl Default for MultiversMetadata {
@@ -79,10 +179,16 @@ impl Default for MultiversMetadata {
                     "x86-64-v3".into(),
                     "x86-64-v4".into(),
                 ]),
+                baseline: None,
+                rules: Vec::new(),
             },
         )]);
 
-        Self { targets }
+        Self {
+            targets,
+            baseline: None,
+            runner: None,
+        }
     }
 }
 
@@ -116,8 +222,21 @@ impl MultiversMetadata {
             return Ok(None);
         };
 
-        let targets: HashMap<ArchitectureWrapper, _> = serde_json::from_value(metadata)?;
-        if targets.is_empty() {
+        // The keys that are not options are architectures
+        let mut metadata: serde_json::Map<String, Value> = serde_json::from_value(metadata)?;
+        let baseline = metadata
+            .remove("baseline")
+            .map(serde_json::from_value)
+            .transpose()?;
+
+        let runner = metadata
+            .remove("runner")
+            .map(serde_json::from_value)
+            .transpose()?;
+
+        let targets: HashMap<ArchitectureWrapper, _> =
+            serde_json::from_value(Value::Object(metadata))?;
+        if targets.is_empty() && baseline.is_none() && runner.is_none() {
             return Ok(None);
         }
 
@@ -126,6 +245,8 @@ impl MultiversMetadata {
                 .into_iter()
                 .map(|(key, value)| (key.0, value))
                 .collect(),
+            baseline,
+            runner,
         }))
     }
 
@@ -134,22 +255,43 @@ impl MultiversMetadata {
         self.targets.get(k)
     }
 
+    /// Returns the baseline CPU of a given [`Architecture`].
+    ///
+    /// The baseline set for the architecture takes precedence over the one set for all the architectures.
+    pub fn baseline(&self, k: &Architecture) -> Option<&str> {
+        self.get(k)
+            .and_then(TargetMetadata::baseline)
+            .or(self.baseline.as_deref())
+    }
+
+    /// Returns the options of the runner crate.
+    pub fn runner(&self) -> RunnerMetadata {
+        self.runner.clone().unwrap_or_default()
+    }
+
     pub fn update(&mut self, other: &Self) {
         for (k, v) in other.targets.clone() {
             self.targets.insert(k, v);
         }
+        if other.baseline.is_some() {
+            self.baseline.clone_from(&other.baseline);
+        }
+        if other.runner.is_some() {
+            self.runner.clone_from(&other.runner);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::path::Path;
 
     use serde_json::{Value, json};
 
     use target_lexicon::Architecture;
 
-    use crate::metadata::TargetMetadata;
+    use crate::metadata::{SelectionRule, TargetMetadata};
 
     use super::MultiversMetadata;
 
@@ -170,9 +312,13 @@ mod tests {
                 targets: HashMap::from([(
                     Architecture::X86_64,
                     TargetMetadata {
-                        cpus: Some(Vec::new())
+                        cpus: Some(Vec::new()),
+                        baseline: None,
+                        rules: Vec::new(),
                     }
-                ),])
+                ),]),
+                baseline: None,
+                runner: None,
             }
         );
     }
@@ -190,7 +336,9 @@ mod tests {
         assert_eq!(
             metadata,
             MultiversMetadata {
-                targets: HashMap::from([(Architecture::X86_64, TargetMetadata { cpus: None }),])
+                targets: HashMap::from([(Architecture::X86_64, TargetMetadata::default()),]),
+                baseline: None,
+                runner: None,
             }
         );
     }
@@ -220,6 +368,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runner() {
+        let absolute = std::env::current_dir().unwrap();
+        let value = json!({
+            "multivers": {
+                "runner": {
+                    "profile": {"opt-level": 3},
+                    "main": "runner/main.rs",
+                    "dependencies": {
+                        "cfg-if": "1",
+                        "local": {"path": "local"},
+                        "absolute": {"path": absolute}
+                    }
+                }
+            }
+        });
+
+        let metadata = MultiversMetadata::from_value_with_default(&value).unwrap();
+        let runner = metadata.runner().relative_to(Path::new("package"));
+        assert_eq!(runner.profile["opt-level"], json!(3));
+        assert_eq!(
+            runner.main,
+            Some(Path::new("package/runner/main.rs").into())
+        );
+        assert_eq!(runner.dependencies["cfg-if"], json!("1"));
+        assert_eq!(
+            runner.dependencies["local"],
+            json!({"path": Path::new("package/local")})
+        );
+        assert_eq!(runner.dependencies["absolute"], json!({"path": absolute}));
+        assert!(metadata.get(&Architecture::X86_64).is_some());
+
+        let value = json!({
+            "multivers": {
+                "runner": {
+                    "opt-level": 3
+                }
+            }
+        });
+        MultiversMetadata::from_value(&value).unwrap_err();
+    }
+
     #[test]
     fn test_target_invalid() {
         let value = json!({
@@ -297,9 +487,13 @@ mod tests {
                             "skylake".into(),
                             "sandybridge".into(),
                             "ivybridge".into()
-                        ])
+                        ]),
+                        baseline: None,
+                        rules: Vec::new(),
                     }
-                ),])
+                ),]),
+                baseline: None,
+                runner: None,
             }
         );
 
@@ -323,11 +517,15 @@ mod tests {
                                 "skylake".into(),
                                 "sandybridge".into(),
                                 "ivybridge".into()
-                            ])
+                            ]),
+                            baseline: None,
+                            rules: Vec::new(),
                         }
                     ),
-                    (Architecture::Powerpc, TargetMetadata { cpus: None })
-                ])
+                    (Architecture::Powerpc, TargetMetadata::default())
+                ]),
+                baseline: None,
+                runner: None,
             }
         );
 
@@ -343,9 +541,11 @@ mod tests {
             default,
             MultiversMetadata {
                 targets: HashMap::from([
-                    (Architecture::X86_64, TargetMetadata { cpus: None }),
-                    (Architecture::Powerpc, TargetMetadata { cpus: None })
-                ])
+                    (Architecture::X86_64, TargetMetadata::default()),
+                    (Architecture::Powerpc, TargetMetadata::default())
+                ]),
+                baseline: None,
+                runner: None,
             }
         );
     }
@@ -371,9 +571,13 @@ mod tests {
                             "skylake".into(),
                             "sandybridge".into(),
                             "ivybridge".into()
-                        ])
+                        ]),
+                        baseline: None,
+                        rules: Vec::new(),
                     }
-                ),])
+                ),]),
+                baseline: None,
+                runner: None,
             }
         );
 
@@ -396,11 +600,15 @@ mod tests {
                                 "x86-64-v2".into(),
                                 "x86-64-v3".into(),
                                 "x86-64-v4".into(),
-                            ])
+                            ]),
+                            baseline: None,
+                            rules: Vec::new(),
                         }
                     ),
-                    (Architecture::Powerpc, TargetMetadata { cpus: None })
-                ])
+                    (Architecture::Powerpc, TargetMetadata::default())
+                ]),
+                baseline: None,
+                runner: None,
             }
         );
 
@@ -414,8 +622,128 @@ mod tests {
         assert_eq!(
             metadata,
             MultiversMetadata {
-                targets: HashMap::from([(Architecture::X86_64, TargetMetadata { cpus: None }),])
+                targets: HashMap::from([(Architecture::X86_64, TargetMetadata::default()),]),
+                baseline: None,
+                runner: None,
             }
         );
     }
+
+    #[test]
+    fn test_baseline() {
+        let value = json!({
+            "multivers": {
+                "baseline": "x86-64-v2"
+            }
+        });
+        let metadata = MultiversMetadata::from_value(&value).unwrap().unwrap();
+        assert_eq!(metadata.baseline(&Architecture::X86_64), Some("x86-64-v2"));
+        assert_eq!(metadata.baseline(&Architecture::Powerpc), Some("x86-64-v2"));
+
+        let value = json!({
+            "multivers": {
+                "baseline": "x86-64-v2",
+                "x86_64": {
+                    "baseline": "x86-64-v3"
+                }
+            }
+        });
+        let metadata = MultiversMetadata::from_value(&value).unwrap().unwrap();
+        assert_eq!(metadata.baseline(&Architecture::X86_64), Some("x86-64-v3"));
+        assert_eq!(metadata.baseline(&Architecture::Powerpc), Some("x86-64-v2"));
+    }
+
+    #[test]
+    fn test_rules() {
+        let value = json!({
+            "multivers": {
+                "x86_64": {
+                    "rules": [
+                        {
+                            "vendor": "GenuineIntel",
+                            "family": 6,
+                            "models": [140, 141],
+                            "prefer": "x86-64-v3",
+                            "over": "x86-64-v4"
+                        },
+                        {
+                            "prefer": "x86-64-v2",
+                            "over": "x86-64-v3"
+                        }
+                    ]
+                }
+            }
+        });
+        let metadata = MultiversMetadata::from_value(&value).unwrap().unwrap();
+        assert_eq!(
+            metadata.get(&Architecture::X86_64).unwrap().rules(),
+            [
+                SelectionRule {
+                    vendor: Some("GenuineIntel".into()),
+                    family: Some(6),
+                    models: vec![140, 141],
+                    prefer: "x86-64-v3".into(),
+                    over: "x86-64-v4".into(),
+                },
+                SelectionRule {
+                    vendor: None,
+                    family: None,
+                    models: Vec::new(),
+                    prefer: "x86-64-v2".into(),
+                    over: "x86-64-v3".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules_invalid() {
+        let value = json!({
+            "multivers": {
+                "x86_64": {
+                    "rules": [
+                        {
+                            "vendor": "GenuineIntel",
+                            "prefer": "x86-64-v3"
+                        }
+                    ]
+                }
+            }
+        });
+
+        MultiversMetadata::from_value(&value).unwrap_err();
+    }
+
+    #[test]
+    fn test_baseline_invalid() {
+        let value = json!({
+            "multivers": {
+                "baseline": ["x86-64-v2"]
+            }
+        });
+
+        MultiversMetadata::from_value(&value).unwrap_err();
+    }
+
+    #[test]
+    fn test_baseline_with_default() {
+        let value = json!({
+            "multivers": {
+                "baseline": "x86-64-v2"
+            }
+        });
+        let metadata = MultiversMetadata::from_value_with_default(&value).unwrap();
+        assert_eq!(metadata.baseline(&Architecture::X86_64), Some("x86-64-v2"));
+        assert_eq!(
+            metadata.get(&Architecture::X86_64).unwrap().cpus(),
+            MultiversMetadata::default()
+                .get(&Architecture::X86_64)
+                .unwrap()
+                .cpus()
+        );
+        assert_eq!(
+            MultiversMetadata::default().baseline(&Architecture::X86_64),
+            None
+        );
+    }
 }
@@ -1,5 +1,5 @@
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -11,9 +11,9 @@ use clap::ColorChoice;
 
 use escargot::CargoBuild;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use target_lexicon::{Environment, Triple};
+use target_lexicon::{Architecture, Environment, OperatingSystem, Triple};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
@@ -22,47 +22,83 @@ use console::{Term, style};
 use sha3::{Digest, Sha3_256};
 
 use crate::cargo::CommandMessagesExt;
-use crate::cli::Args;
-use crate::features::{CpuFeatures, Cpus, CpusBuilder};
-use crate::metadata::MultiversMetadata;
-use crate::runner::RunnerBuilder;
+use crate::cli::{Args, Compression, DeltaEncoder, DeltaLayout, ExternalVariants};
+use crate::features::{Cpus, CpusBuilder};
+use crate::metadata::{MultiversMetadata, SelectionRule, TargetMetadata};
+use crate::pack::Packer;
+use crate::rustc::Rustc;
+use crate::shard::{Plan, Shard, Variants};
 
-#[derive(Serialize)]
-struct BuildDescription {
-    path: PathBuf,
+#[derive(Serialize, Deserialize)]
+pub struct BuildDescription {
+    pub path: PathBuf,
+
+    pub features: Vec<String>,
+
+    /// CPUs whose set of CPU features produced this build
+    #[serde(default)]
+    pub cpus: Vec<String>,
 
-    features: Vec<String>,
+    /// Whether this build is the one of the baseline CPU
+    #[serde(default)]
+    pub baseline: bool,
 
     #[serde(skip)]
-    hash: Option<Vec<u8>>,
+    pub hash: Option<Vec<u8>>,
 
     #[serde(skip)]
-    original_filename: Option<OsString>,
+    pub original_filename: Option<OsString>,
 }
 
 #[derive(Serialize)]
-struct BuildsDescription {
-    builds: Vec<BuildDescription>,
+pub struct BuildsDescription {
+    pub builds: Vec<BuildDescription>,
+
+    /// Rules used by the runner to select a build depending on the CPU of the host
+    pub rules: Vec<SelectionRule>,
+
+    /// Whether the runner stores the source build uncompressed
+    pub uncompressed_source: bool,
+
+    /// Compression of the builds embedded in the runner
+    pub compression: Compression,
+
+    /// Level of the compression (the default level of the compression if `None`)
+    pub compression_level: Option<i32>,
+
+    /// Algorithms that generate the patches, the runner keeps the smallest patch of each build
+    pub delta_encoders: Vec<DeltaEncoder>,
+
+    /// Whether the builds are patched from the source, or from their nearest build
+    pub delta_layout: DeltaLayout,
+
+    /// How the builds are stored beside the runner (`None` if they are embedded in it)
+    pub external_variants: Option<ExternalVariants>,
 }
 
+/// CPUs built with `--glibc-hwcaps` when neither `--cpus` nor the metadata of the package set them (instead of all the CPUs of the target):
+/// the baseline of x86-64 and the levels of the `glibc-hwcaps` subdirectories
+const GLIBC_HWCAPS_CPUS: [&str; 4] = ["x86-64", "x86-64-v2", "x86-64-v3", "x86-64-v4"];
+
 /// Build multiple versions of the same binary, each with a different CPU features set, merged into a single portable optimized binary
 pub struct Multivers {
     metadata: Metadata,
-    target: String,
-    runner: RunnerBuilder,
+    packer: Packer,
     workspace: clap_cargo::Workspace,
-    target_dir: PathBuf,
-    out_dir: Option<PathBuf>,
     features: clap_cargo::Features,
     cpus: CpusBuilder,
     progress: ProgressBar,
     profile: String,
     cargo_args: Vec<String>,
-    runner_features: Vec<String>,
+    tune_cpus: bool,
+    shard: Option<Shard>,
+    emit_variants: Option<PathBuf>,
+    glibc_hwcaps: bool,
 }
 
 impl Multivers {
-    pub fn from_args(args: Args) -> anyhow::Result<Self> {
+    /// Builds the packages of the command line for one of its targets
+    pub fn from_args(args: &Args, target: String) -> anyhow::Result<Self> {
         let metadata = args
             .manifest
             .metadata()
@@ -72,25 +108,44 @@ impl Multivers {
         // We must set a target, otherwise RUSTFLAGS will be used by the build scripts as well
         // (which we don't want since we might build with features not supported by the CPU building the package).
         // See https://github.com/rust-lang/cargo/issues/4423
-        let target = args.target()?.into_owned();
+        if args.glibc_hwcaps {
+            let triple = Triple::from_str(&target).context("Failed to parse the target")?;
+            anyhow::ensure!(
+                triple.architecture == Architecture::X86_64
+                    && triple.operating_system == OperatingSystem::Linux
+                    && triple.environment == Environment::Gnu,
+                "The glibc-hwcaps layout is only supported by glibc on x86-64 Linux, not on the target `{target}`"
+            );
+        }
         let cpus = Cpus::builder(target.clone())
             .context("Failed to get the set of CPU features for the target")?
-            .exclude_features(args.exclude_cpu_features)
-            .cpus(args.cpus);
-
-        let target_dir = args.target_dir.unwrap_or_else(|| {
+            .exclude_features(args.exclude_cpu_features.clone())
+            .cpus(args.cpus.clone())
+            .default_cpus(
+                args.glibc_hwcaps
+                    .then(|| GLIBC_HWCAPS_CPUS.map(ToOwned::to_owned).to_vec()),
+            )
+            .baseline(args.baseline_cpu.clone());
+
+        let target_dir = args.target_dir.clone().unwrap_or_else(|| {
             metadata
                 .target_directory
                 .join(clap::crate_name!())
                 .into_std_path_buf()
         });
 
-        let runner = if let Some(path) = args.runner_manifest_path {
-            RunnerBuilder::from_manifest_path(target_dir.clone(), path)
+        let out_dir = if args.out_dir_per_target() {
+            args.out_dir.as_ref().map(|out_dir| out_dir.join(&target))
         } else {
-            RunnerBuilder::generate_crate_sources(target_dir.clone(), &args.runner_version)
-                .context("Failed to generate the source files of the runner")?
+            args.out_dir.clone()
         };
+        let mut packer = Packer::from_args(target, target_dir, out_dir, args.runner.clone())?;
+        // The runner is built offline too when the package is (`--frozen` implies `--offline`)
+        packer.offline(
+            args.args
+                .iter()
+                .any(|arg| arg == "--offline" || arg == "--frozen"),
+        );
 
         let progress = indicatif::ProgressBar::new(0).with_style(
             ProgressStyle::with_template(
@@ -108,42 +163,127 @@ impl Multivers {
 
         Ok(Self {
             metadata,
-            target,
-            runner,
-            workspace: args.workspace,
-            target_dir,
-            out_dir: args.out_dir,
-            features: args.features,
+            packer,
+            workspace: args.workspace.clone(),
+            features: args.features.clone(),
             cpus,
             progress,
-            cargo_args: args.args,
-            profile: args.profile,
-            runner_features: args.runner_features,
+            cargo_args: args.args.clone(),
+            profile: args.profile.clone(),
+            tune_cpus: args.tune_cpus,
+            shard: args.shard,
+            emit_variants: args.emit_variants.clone(),
+            glibc_hwcaps: args.glibc_hwcaps,
         })
     }
 
-    fn cpu_features(&self, metadata: &MultiversMetadata) -> anyhow::Result<Vec<CpuFeatures>> {
-        let cpus = self.cpus.clone().metadata(metadata)?.build()?;
+    /// Returns everything that determines the versions of a package, to check that the shards built the same versions
+    fn plan(&self, variants: Vec<String>, shards: usize) -> anyhow::Result<Plan> {
+        let features = if self.features.all_features {
+            vec!["--all-features".to_owned()]
+        } else {
+            self.features
+                .no_default_features
+                .then(|| "--no-default-features".to_owned())
+                .into_iter()
+                .chain(self.features.features.iter().cloned())
+                .collect()
+        };
+
+        Ok(Plan {
+            target: self.packer.target().to_owned(),
+            profile: self.profile.clone(),
+            features,
+            cargo_args: self.cargo_args.clone(),
+            rust_flags: std::env::var("RUSTFLAGS").unwrap_or_default(),
+            toolchain: Rustc::version()?,
+            variants,
+            shards,
+        })
+    }
+
+    /// Returns the CPUs to build with
+    fn cpus(&self, metadata: &MultiversMetadata) -> anyhow::Result<Cpus> {
+        self.cpus.clone().metadata(metadata)?.build()
+    }
+
+    /// Returns the selection rules of the target, after checking that they only refer to CPUs that are built
+    fn rules(
+        metadata: &MultiversMetadata,
+        triple: &Triple,
+        cpus: &Cpus,
+    ) -> anyhow::Result<Vec<SelectionRule>> {
+        let rules = metadata
+            .get(&triple.architecture)
+            .map(TargetMetadata::rules)
+            .unwrap_or_default();
+
+        for rule in rules {
+            for cpu in [&rule.prefer, &rule.over] {
+                anyhow::ensure!(
+                    cpus.contains(cpu),
+                    "The CPU `{cpu}` of a selection rule is not part of the CPUs to build with"
+                );
+            }
+        }
 
-        Ok(cpus.features_sets().cloned().collect())
+        Ok(rules.to_vec())
     }
 
-    fn build_package(&self, package: &Package) -> anyhow::Result<BuildsDescription> {
+    /// Builds the versions of a package (only the ones of the shard with `--shard`),
+    /// and returns them with the description of all the versions of the plan
+    fn build_package(&self, package: &Package) -> anyhow::Result<(BuildsDescription, Vec<String>)> {
         let triple: Triple =
-            Triple::from_str(&self.target).context("Failed to parse the target")?;
+            Triple::from_str(self.packer.target()).context("Failed to parse the target")?;
         let manifest_path = package.manifest_path.as_std_path();
         let features_list = self.features.features.join(" ");
         let mut rust_flags = std::env::var("RUSTFLAGS").unwrap_or_default();
 
         let metadata = MultiversMetadata::from_package_with_default(package)
             .context("Failed to parse package's metadata")?;
-        let cpu_features = self.cpu_features(&metadata)?;
+        let cpus = self.cpus(&metadata)?;
+        let rules = Self::rules(&metadata, &triple, &cpus)?;
+        // Each version is either built for a set of CPU features, or tuned for a CPU
+        let variants = if self.tune_cpus {
+            cpus.iter()
+                .map(|(cpu, features)| (Some(cpu), features))
+                .collect::<Vec<_>>()
+        } else {
+            cpus.features_sets()
+                .map(|features| (None, features))
+                .collect()
+        };
 
-        if cpu_features.is_empty() {
+        if variants.is_empty() {
             anyhow::bail!("Empty set of CPU features");
         }
 
-        self.progress.set_length(cpu_features.len() as u64);
+        let variants = variants
+            .into_iter()
+            .map(|(cpu, features)| {
+                let flags = features.to_compiler_flags();
+                let description = match cpu {
+                    Some(cpu) => format!("{flags} (tuned for {cpu})"),
+                    None => flags,
+                };
+
+                (cpu, features, description)
+            })
+            .collect::<Vec<_>>();
+        let planned = variants
+            .iter()
+            .map(|(_, _, description)| description.clone())
+            .collect();
+        // The other versions are built by the other shards
+        let shard = self.shard.unwrap_or_default();
+        let variants = variants
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| shard.contains(*i))
+            .map(|(_, variant)| variant)
+            .collect::<Vec<_>>();
+
+        self.progress.set_length(variants.len() as u64);
         self.progress.set_prefix("Building");
 
         if triple.environment == Environment::Msvc {
@@ -158,10 +298,10 @@ impl Multivers {
 
         let mut final_style_set = false;
         let mut hasher = Sha3_256::new();
-        let mut builds = cpu_features
+        let builds = variants
             .into_iter()
             .enumerate()
-            .map(|(i, cpu_features)| {
+            .map(|(i, (cpu, cpu_features, description))| {
                 if !final_style_set && i > 0 {
                     self.progress.disable_steady_tick();
                     self.progress.set_style(
@@ -174,17 +314,19 @@ impl Multivers {
                     );
                     final_style_set = true;
                 }
-                let target_features_flags = cpu_features.to_compiler_flags();
+                let rust_flags = match cpu {
+                    Some(cpu) => format!("{rust_flags} {}", cpu_features.to_tuned_compiler_flags(cpu, &cpus.disabled_features(cpu))),
+                    None => format!("{rust_flags} -Ctarget-feature={}", cpu_features.to_compiler_flags()),
+                };
                 self.progress.println(format!(
-                    "{:>12} {target_features_flags}",
+                    "{:>12} {description}",
                     style("Compiling").bold().green()
                 ));
 
-                let rust_flags = format!("{rust_flags} -Ctarget-feature={target_features_flags}");
                 let cargo = CargoBuild::new()
                     .arg(format!("--profile={}", self.profile))
-                    .target(&self.target)
-                    .target_dir(&self.target_dir)
+                    .target(self.packer.target())
+                    .target_dir(self.packer.target_dir())
                     .manifest_path(manifest_path)
                     .args(&self.cargo_args)
                     .env("RUSTFLAGS", rust_flags);
@@ -199,22 +341,29 @@ impl Multivers {
 
                 let cargo = cargo.exec()?;
 
-                let bin_path = cargo
-                    .find_executable()?
-                    .context("Failed to find a binary")?;
+                let bin_path = if self.glibc_hwcaps {
+                    cargo.find_library()?.context("Failed to find a dynamic library")?
+                } else {
+                    cargo.find_executable()?.context("Failed to find a binary")?
+                };
 
                 self.progress.inc(1);
 
-                hasher.update(target_features_flags.as_bytes());
+                hasher.update(description.as_bytes());
                 let filename = format!("{:x}", base16ct::HexDisplay(&hasher.finalize_reset()));
 
                 let output_path_parent = self
-                    .target_dir
-                    .join(&self.target)
+                    .packer
+                    .target_dir()
+                    .join(self.packer.target())
                     .join(profile_dir);
                 let mut output_path = output_path_parent
                     .join(filename);
-                output_path.set_extension(std::env::consts::EXE_EXTENSION);
+                output_path.set_extension(if self.glibc_hwcaps {
+                    std::env::consts::DLL_EXTENSION
+                } else {
+                    std::env::consts::EXE_EXTENSION
+                });
 
                 std::fs::create_dir_all(&output_path_parent)
                     .with_context(|| format!("Failed to create directory `{}`", output_path_parent.display()))?;
@@ -226,9 +375,19 @@ impl Multivers {
                     hasher.finalize_reset().to_vec()
                 });
 
+                let (baseline, build_cpus) = match cpu {
+                    Some(cpu) => (cpus.baseline_cpu() == Some(cpu), vec![cpu.to_owned()]),
+                    None => (
+                        cpus.baseline() == Some(cpu_features),
+                        cpus.cpus_with_features(cpu_features).map(ToOwned::to_owned).collect(),
+                    ),
+                };
+
                 let build = BuildDescription {
                     path: output_path,
-                    features: cpu_features.into_vec(),
+                    baseline,
+                    cpus: build_cpus,
+                    features: cpu_features.clone().into_vec(),
                     hash,
                     original_filename: bin_path.file_name().map(ToOwned::to_owned),
                 };
@@ -236,39 +395,38 @@ impl Multivers {
                 Ok(build)
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
-        builds.sort_unstable_by(|build1, build2| {
-            // First, we sort based on the hash of each build.
-            build1
-                .hash
-                .cmp(&build2.hash)
-                // Then, based on the features.
-                .then_with(|| build1.features.len().cmp(&build2.features.len()))
-        });
-        // So that we can remove the duplicated builds and we remove the ones requiring more features.
-        builds.dedup_by(|a, b| match (&a.hash, &b.hash) {
-            (Some(a), Some(b)) => a == b,
-            _ => false,
-        });
-        // Finally, we sort them to put the builds requiring more features at the top.
-        builds.sort_unstable_by(|build1, build2| {
-            build1.features.len().cmp(&build2.features.len()).reverse()
-        });
+        let builds = self.packer.describe(builds, rules);
 
         self.progress.finish_and_clear();
 
-        Ok(BuildsDescription { builds })
+        Ok((builds, planned))
     }
 
-    pub fn build(&self) -> anyhow::Result<()> {
+    /// Builds the selected packages, and returns the file names of their runners (or of their single versions)
+    pub fn build(&self) -> anyhow::Result<Vec<OsString>> {
         let (selected_packages, _) = self.workspace.partition_packages(&self.metadata);
 
-        let has_bins = selected_packages
-            .iter()
-            .any(|&package| package.targets.iter().any(cargo_metadata::Target::is_bin));
-        if !has_bins {
-            anyhow::bail!(
-                "No binary package detected. Only binaries can be built using cargo multivers."
-            );
+        if self.glibc_hwcaps {
+            let has_cdylibs = selected_packages.iter().any(|&package| {
+                package
+                    .targets
+                    .iter()
+                    .any(cargo_metadata::Target::is_cdylib)
+            });
+            if !has_cdylibs {
+                anyhow::bail!(
+                    "No cdylib package detected. Only `cdylib` libraries can be built in the glibc-hwcaps layout."
+                );
+            }
+        } else {
+            let has_bins = selected_packages
+                .iter()
+                .any(|&package| package.targets.iter().any(cargo_metadata::Target::is_bin));
+            if !has_bins {
+                anyhow::bail!(
+                    "No binary package detected. Only binaries can be built using cargo multivers."
+                );
+            }
         }
 
         let profile_dir = if self.profile == "dev" {
@@ -277,6 +435,7 @@ impl Multivers {
             &self.profile
         };
 
+        let mut names = Vec::new();
         for selected_package in selected_packages {
             println!(
                 "{:>12} {} v{} ({})",
@@ -286,7 +445,16 @@ impl Multivers {
                 self.metadata.workspace_root
             );
 
-            let builds = self.build_package(selected_package)?;
+            let (builds, planned) = self.build_package(selected_package)?;
+            let runner = MultiversMetadata::from_package_with_default(selected_package)
+                .context("Failed to parse package's metadata")?
+                .runner()
+                .relative_to(
+                    selected_package
+                        .manifest_path
+                        .parent()
+                        .map_or_else(|| Path::new(""), |directory| directory.as_std_path()),
+                );
 
             let original_filename = builds
                 .builds
@@ -296,86 +464,35 @@ impl Multivers {
                     format!("multivers-runner{}", std::env::consts::EXE_SUFFIX).into()
                 });
 
-            if let [build] = builds.builds.as_slice() {
-                let output_path = self
-                    .target_dir
-                    .join(&self.target)
-                    .join(profile_dir)
-                    .join(&original_filename);
-
-                std::fs::rename(&build.path, &output_path).with_context(|| {
-                    format!(
-                        "Failed to rename `{}` to `{}`",
-                        build.path.display(),
-                        output_path.display()
-                    )
-                })?;
-
-                if let Some(out_dir) = self.out_dir.as_deref() {
-                    std::fs::create_dir_all(out_dir).with_context(|| {
-                        format!("Failed to create output directory `{}`", out_dir.display())
-                    })?;
-                    let to = out_dir.join(&original_filename);
-                    std::fs::copy(&output_path, &to).with_context(|| {
-                        format!(
-                            "Failed to copy final binary `{}` to `{}`",
-                            output_path.display(),
-                            to.display()
-                        )
-                    })?;
-                }
+            names.push(original_filename.clone());
+
+            if let Some(directory) = &self.emit_variants {
+                let shard = self.shard.unwrap_or_default();
+                let variants = Variants {
+                    package: selected_package.name.to_string(),
+                    plan: self.plan(planned, shard.count)?,
+                    shard,
+                    original_filename,
+                    builds: builds.builds,
+                    rules: builds.rules,
+                    runner,
+                };
 
-                println!(
-                    "{:>12} 1 version, no runner needed ({})",
-                    style("Finished").bold().green(),
-                    output_path.display()
-                );
+                variants.write_to(&directory.join(selected_package.name.as_ref()))?;
+            } else if self.glibc_hwcaps {
+                self.packer
+                    .lay_out_glibc_hwcaps(&builds, &original_filename, profile_dir)?;
             } else {
-                let encoded =
-                    serde_json::to_vec_pretty(&builds).context("Failed to encode the builds")?;
-
-                let package_output_directory = self.target_dir.join(selected_package.name.as_ref());
-                std::fs::create_dir_all(&package_output_directory)
-                    .context("Failed to create temporary output directory")?;
-                let builds_path = package_output_directory.join("builds.json");
-                std::fs::write(&builds_path, encoded)
-                    .with_context(|| format!("Failed to write to `{}`", builds_path.display()))?;
-
-                println!(
-                    "{:>12} {} versions compressed into a runner",
-                    style("Compiling").bold().green(),
-                    builds.builds.len(),
-                );
-
-                let bin_path = self.runner.build(
-                    &self.target,
-                    &builds_path,
+                self.packer.pack(
+                    &builds,
+                    selected_package.name.as_ref(),
                     &original_filename,
-                    self.runner_features.iter(),
+                    profile_dir,
+                    &runner,
                 )?;
-
-                if let Some(out_dir) = self.out_dir.as_deref() {
-                    std::fs::create_dir_all(out_dir).with_context(|| {
-                        format!("Failed to create output directory `{}`", out_dir.display())
-                    })?;
-                    let to = out_dir.join(&original_filename);
-                    std::fs::copy(&bin_path, &to).with_context(|| {
-                        format!(
-                            "Failed to copy final binary `{}` to `{}`",
-                            bin_path.display(),
-                            to.display()
-                        )
-                    })?;
-                }
-
-                println!(
-                    "{:>12} ({})",
-                    style("Finished").bold().green(),
-                    bin_path.display()
-                );
             }
         }
 
-        Ok(())
+        Ok(names)
     }
 }
@@ -0,0 +1,754 @@
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use serde::Deserialize;
+
+use indicatif::HumanBytes;
+
+use console::style;
+
+use sha3::{Digest, Sha3_256};
+
+use crate::cli::{Compression, DeltaEncoder, DeltaLayout, ExternalVariants, PackArgs, RunnerArgs};
+use crate::detection;
+use crate::features::Cpus;
+use crate::metadata::{RunnerMetadata, SelectionRule};
+use crate::multivers::{BuildDescription, BuildsDescription};
+use crate::payload::Payload;
+use crate::runner::{Runner, RunnerBuilder};
+use crate::rustc::Rustc;
+
+/// Packs builds into a runner, with the options of the runner
+pub struct Packer {
+    target: String,
+    target_dir: PathBuf,
+    out_dir: Option<PathBuf>,
+    runner_manifest_path: Option<PathBuf>,
+    runner_version: String,
+    offline: bool,
+    runner_features: Vec<String>,
+    uncompressed_source: bool,
+    compression: Compression,
+    compression_level: Option<i32>,
+    delta_encoders: Vec<DeltaEncoder>,
+    delta_layout: DeltaLayout,
+    runner_stub: Option<Option<PathBuf>>,
+    external_variants: Option<ExternalVariants>,
+}
+
+impl Packer {
+    pub fn from_args(
+        target: String,
+        target_dir: PathBuf,
+        out_dir: Option<PathBuf>,
+        args: RunnerArgs,
+    ) -> anyhow::Result<Self> {
+        let compression_level = args.compression_level()?;
+        // The build script of the runner reads the builds description from another directory
+        let target_dir = std::path::absolute(&target_dir)
+            .with_context(|| format!("Failed to find the directory `{}`", target_dir.display()))?;
+
+        Ok(Self {
+            target,
+            target_dir,
+            out_dir,
+            runner_manifest_path: args.runner_manifest_path,
+            runner_version: args.runner_version,
+            offline: false,
+            runner_features: args.runner_features,
+            uncompressed_source: args.uncompressed_source,
+            compression: args.compression,
+            compression_level,
+            delta_encoders: args.delta_encoders,
+            delta_layout: args.delta_layout,
+            runner_stub: args.runner_stub,
+            external_variants: args.external_variants,
+        })
+    }
+
+    /// Builds the runner without accessing the network, like `cargo build --offline`
+    pub fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Returns the builder of the runner, whose crate is generated with the options of the package unless a custom runner is used
+    fn runner(&self, metadata: &RunnerMetadata) -> anyhow::Result<RunnerBuilder> {
+        let mut runner = if let Some(path) = &self.runner_manifest_path {
+            RunnerBuilder::from_manifest_path(self.target_dir.clone(), path)
+        } else {
+            RunnerBuilder::generate_crate_sources(
+                self.target_dir.clone(),
+                &self.runner_version,
+                metadata,
+            )
+            .context("Failed to generate the source files of the runner")?
+        };
+        runner.offline(self.offline);
+
+        Ok(runner)
+    }
+
+    /// Returns the target of the builds
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the directory for all generated artifacts
+    pub fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    /// Removes the duplicated builds, sorts them by preference, and describes them with the options of the runner
+    pub fn describe(
+        &self,
+        mut builds: Vec<BuildDescription>,
+        rules: Vec<SelectionRule>,
+    ) -> BuildsDescription {
+        builds.sort_unstable_by(|build1, build2| {
+            // First, we sort based on the hash of each build.
+            build1
+                .hash
+                .cmp(&build2.hash)
+                // Then, we put the baseline first.
+                .then_with(|| build1.baseline.cmp(&build2.baseline).reverse())
+                // Then, based on the features.
+                .then_with(|| build1.features.len().cmp(&build2.features.len()))
+        });
+        // So that we can remove the duplicated builds and we remove the ones requiring more features
+        // (the baseline is always kept), while keeping track of the CPUs of the removed builds.
+        builds.dedup_by(|removed, kept| match (&removed.hash, &kept.hash) {
+            (Some(a), Some(b)) if a == b => {
+                kept.cpus.append(&mut removed.cpus);
+                kept.cpus.sort_unstable();
+                true
+            }
+            _ => false,
+        });
+        // Finally, we sort them to put the builds requiring more features at the top.
+        builds.sort_unstable_by(|build1, build2| {
+            build1.features.len().cmp(&build2.features.len()).reverse()
+        });
+
+        BuildsDescription {
+            builds,
+            rules,
+            uncompressed_source: self.uncompressed_source,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            delta_encoders: self.delta_encoders.clone(),
+            delta_layout: self.delta_layout,
+            external_variants: self.external_variants,
+        }
+    }
+
+    /// Packs the builds into a runner named `original_filename`, or copies the build if there is only one
+    ///
+    /// The runner is written in the `profile_dir` directory of the target, and the description of the builds
+    /// in the `name` directory of the target directory.
+    /// Its crate is generated with the options of `runner`.
+    /// With external variants, the builds are written beside the runner (and beside its copy in the output directory).
+    pub fn pack(
+        &self,
+        builds: &BuildsDescription,
+        name: &str,
+        original_filename: &OsStr,
+        profile_dir: &str,
+        runner: &RunnerMetadata,
+    ) -> anyhow::Result<()> {
+        if let [build] = builds.builds.as_slice() {
+            let output_directory = self.target_dir.join(&self.target).join(profile_dir);
+            std::fs::create_dir_all(&output_directory).with_context(|| {
+                format!(
+                    "Failed to create output directory `{}`",
+                    output_directory.display()
+                )
+            })?;
+            let output_path = output_directory.join(original_filename);
+
+            std::fs::copy(&build.path, &output_path).with_context(|| {
+                format!(
+                    "Failed to copy `{}` to `{}`",
+                    build.path.display(),
+                    output_path.display()
+                )
+            })?;
+
+            self.copy_to_out_dir(&output_path, original_filename)?;
+
+            println!(
+                "{:>12} 1 version, no runner needed ({})",
+                style("Finished").bold().green(),
+                output_path.display()
+            );
+        } else if let Some(stub) = &self.runner_stub {
+            println!(
+                "{:>12} {} versions to a runner stub",
+                style("Appending").bold().green(),
+                builds.builds.len(),
+            );
+
+            let runner = self.append_to_stub(
+                stub.as_deref(),
+                builds,
+                original_filename,
+                profile_dir,
+                runner,
+            )?;
+            self.finish_runner(runner, original_filename)?;
+        } else {
+            let encoded =
+                serde_json::to_vec_pretty(builds).context("Failed to encode the builds")?;
+
+            let package_output_directory = self.target_dir.join(name);
+            std::fs::create_dir_all(&package_output_directory)
+                .context("Failed to create temporary output directory")?;
+            let builds_path = package_output_directory.join("builds.json");
+            std::fs::write(&builds_path, encoded)
+                .with_context(|| format!("Failed to write to `{}`", builds_path.display()))?;
+
+            println!(
+                "{:>12} {} versions compressed into a runner",
+                style("Compiling").bold().green(),
+                builds.builds.len(),
+            );
+
+            let runner = self.runner(runner)?.build(
+                &self.target,
+                &builds_path,
+                original_filename,
+                self.runner_features(),
+            )?;
+            if let Some(layout) = builds.external_variants {
+                self.write_variants(layout, builds, &runner.path)?;
+                if let Some(out_dir) = self.out_dir.as_deref() {
+                    self.write_variants(layout, builds, &out_dir.join(original_filename))?;
+                }
+            }
+            self.finish_runner(runner, original_filename)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the library of each x86-64 level in the `glibc-hwcaps/<level>` subdirectory of the `profile_dir` directory
+    /// of the target (and of the output directory), and the source build in the directory itself
+    ///
+    /// A build shared by several levels is only written for the lowest one, since glibc also loads the libraries of the lower levels.
+    pub fn lay_out_glibc_hwcaps(
+        &self,
+        builds: &BuildsDescription,
+        original_filename: &OsStr,
+        profile_dir: &str,
+    ) -> anyhow::Result<()> {
+        // Like in the runner, the source is the build of the baseline CPU, or the one requiring the fewest features
+        let source = builds
+            .builds
+            .iter()
+            .position(|build| build.baseline)
+            .unwrap_or_else(|| builds.builds.len().saturating_sub(1));
+        let layout = builds
+            .builds
+            .iter()
+            .enumerate()
+            .map(|(i, build)| {
+                if i == source {
+                    return Ok((PathBuf::new(), build));
+                }
+
+                let level = GLIBC_HWCAPS_LEVELS
+                    .iter()
+                    .find(|&level| build.cpus.iter().any(|cpu| cpu == level))
+                    .with_context(|| {
+                        format!(
+                            "The build for `{}` is not the one of an x86-64 level that glibc-hwcaps supports ({})",
+                            build.cpus.join(", "),
+                            GLIBC_HWCAPS_LEVELS.join(", ")
+                        )
+                    })?;
+
+                Ok((Path::new("glibc-hwcaps").join(level), build))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let output_directory = self.target_dir.join(&self.target).join(profile_dir);
+        for directory in std::iter::once(output_directory.as_path()).chain(self.out_dir.as_deref())
+        {
+            // The libraries of a previous build may be for other levels
+            for level in GLIBC_HWCAPS_LEVELS {
+                let path = directory
+                    .join("glibc-hwcaps")
+                    .join(level)
+                    .join(original_filename);
+                std::fs::remove_file(&path)
+                    .or_else(|error| match error.kind() {
+                        std::io::ErrorKind::NotFound => Ok(()),
+                        _ => Err(error),
+                    })
+                    .with_context(|| format!("Failed to remove `{}`", path.display()))?;
+            }
+
+            for (subdirectory, build) in &layout {
+                let library_directory = directory.join(subdirectory);
+                std::fs::create_dir_all(&library_directory).with_context(|| {
+                    format!(
+                        "Failed to create output directory `{}`",
+                        library_directory.display()
+                    )
+                })?;
+                let path = library_directory.join(original_filename);
+                std::fs::copy(&build.path, &path).with_context(|| {
+                    format!(
+                        "Failed to copy `{}` to `{}`",
+                        build.path.display(),
+                        path.display()
+                    )
+                })?;
+            }
+        }
+
+        println!(
+            "{:>12} {} versions in the glibc-hwcaps layout ({})",
+            style("Finished").bold().green(),
+            layout.len(),
+            output_directory.display()
+        );
+
+        Ok(())
+    }
+
+    /// Returns the features of the runner, with the one of the decoder of the compression
+    /// (or the one that reads the builds beside the runner)
+    fn runner_features(&self) -> impl Iterator<Item = &str> {
+        let compression = if self.external_variants.is_some() {
+            Some("external")
+        } else {
+            self.compression.runner_feature()
+        };
+
+        self.runner_features
+            .iter()
+            .map(String::as_str)
+            .chain(compression)
+    }
+
+    /// Writes the builds beside the runner at `runner_path`, in a `.variants` directory or archive
+    ///
+    /// Each build is identified by its SHA3-256 hash, which the runner checks before running it.
+    fn write_variants(
+        &self,
+        layout: ExternalVariants,
+        builds: &BuildsDescription,
+        runner_path: &Path,
+    ) -> anyhow::Result<()> {
+        let path = variants_path(runner_path);
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        }
+        .or_else(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(error),
+        })
+        .with_context(|| format!("Failed to remove `{}`", path.display()))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create output directory `{}`", parent.display())
+            })?;
+        }
+
+        let mut archive = match layout {
+            ExternalVariants::Directory => {
+                std::fs::create_dir(&path)
+                    .with_context(|| format!("Failed to create `{}`", path.display()))?;
+                None
+            }
+            ExternalVariants::Archive => {
+                let mut archive = BufWriter::new(
+                    std::fs::File::create(&path)
+                        .with_context(|| format!("Failed to create `{}`", path.display()))?,
+                );
+                archive
+                    .write_all(VARIANTS_ARCHIVE_MAGIC)
+                    .with_context(|| format!("Failed to write to `{}`", path.display()))?;
+                Some(archive)
+            }
+        };
+
+        for (index, build) in builds.builds.iter().enumerate() {
+            let mut variant = std::fs::File::open(&build.path)
+                .with_context(|| format!("Failed to read `{}`", build.path.display()))?;
+            let length = variant
+                .metadata()
+                .with_context(|| format!("Failed to read `{}`", build.path.display()))?
+                .len();
+
+            if let Some(archive) = &mut archive {
+                // The hash precedes the build, so it is written once the build is copied
+                let start = archive
+                    .stream_position()
+                    .and_then(|start| {
+                        archive.write_all(&[0; 32])?;
+                        archive.write_all(&length.to_le_bytes())?;
+                        Ok(start)
+                    })
+                    .with_context(|| format!("Failed to write to `{}`", path.display()))?;
+                let hash = copy_hashed(&mut variant, &mut *archive, length, &build.path)?;
+                archive
+                    .seek(SeekFrom::Start(start))
+                    .and_then(|_| archive.write_all(&hash))
+                    .and_then(|()| archive.seek(SeekFrom::End(0)))
+                    .with_context(|| format!("Failed to write to `{}`", path.display()))?;
+            } else {
+                // The file is named after its hash, so it is renamed once it is written
+                let partial_path = path.join(format!("{index}.partial"));
+                let partial = std::fs::File::create(&partial_path)
+                    .with_context(|| format!("Failed to create `{}`", partial_path.display()))?;
+                let hash = copy_hashed(&mut variant, partial, length, &build.path)?;
+                let variant_path = path.join(format!("{:x}", base16ct::HexDisplay(&hash)));
+                std::fs::rename(&partial_path, &variant_path)
+                    .with_context(|| format!("Failed to write `{}`", variant_path.display()))?;
+            }
+        }
+        if let Some(mut archive) = archive {
+            archive
+                .flush()
+                .with_context(|| format!("Failed to write to `{}`", path.display()))?;
+        }
+
+        println!(
+            "{:>12} {} versions beside the runner ({})",
+            style("Writing").bold().green(),
+            builds.builds.len(),
+            path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Appends the builds to a runner stub, which is built (or reused if it was already built) if no path is given
+    ///
+    /// The runner is written in the `profile_dir` directory of the target.
+    fn append_to_stub(
+        &self,
+        stub: Option<&Path>,
+        builds: &BuildsDescription,
+        original_filename: &OsStr,
+        profile_dir: &str,
+        runner: &RunnerMetadata,
+    ) -> anyhow::Result<Runner> {
+        let stub = match stub {
+            Some(stub) => stub.to_path_buf(),
+            None => self
+                .runner(runner)?
+                .build_stub(&self.target, self.runner_features())?,
+        };
+
+        let payload = Payload::generate(builds)?;
+
+        let output_directory = self.target_dir.join(&self.target).join(profile_dir);
+        std::fs::create_dir_all(&output_directory).with_context(|| {
+            format!(
+                "Failed to create output directory `{}`",
+                output_directory.display()
+            )
+        })?;
+        let path = output_directory.join(original_filename);
+        payload.append_to(&stub, &path)?;
+
+        Ok(Runner {
+            path,
+            delta_report: Some(payload.report),
+        })
+    }
+
+    /// Reports the patches of a runner, copies it to the output directory, and prints its path
+    fn finish_runner(&self, runner: Runner, original_filename: &OsStr) -> anyhow::Result<()> {
+        let bin_path = runner.path;
+
+        if let Some(report) = runner.delta_report {
+            let runner_size = std::fs::metadata(&bin_path)
+                .with_context(|| format!("Failed to read `{}`", bin_path.display()))?
+                .len();
+            let (selected_name, selected) = report.selected();
+
+            // The size of the runner with the other layout is estimated from the size of its patches
+            for (name, layout) in report.layouts() {
+                println!(
+                    "{:>12} {name} layout: {} generated in {:.1}s, runner of {}{}",
+                    style("Patches").bold().green(),
+                    HumanBytes(layout.size),
+                    layout.seconds,
+                    HumanBytes((runner_size + layout.size).saturating_sub(selected.size)),
+                    if name == selected_name {
+                        " (selected)"
+                    } else {
+                        ""
+                    },
+                );
+            }
+        }
+
+        self.copy_to_out_dir(&bin_path, original_filename)?;
+
+        println!(
+            "{:>12} ({})",
+            style("Finished").bold().green(),
+            bin_path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Copies the final binary to the output directory, if one was given
+    fn copy_to_out_dir(&self, bin_path: &Path, original_filename: &OsStr) -> anyhow::Result<()> {
+        if let Some(out_dir) = self.out_dir.as_deref() {
+            std::fs::create_dir_all(out_dir).with_context(|| {
+                format!("Failed to create output directory `{}`", out_dir.display())
+            })?;
+            let to = out_dir.join(original_filename);
+            std::fs::copy(bin_path, &to).with_context(|| {
+                format!(
+                    "Failed to copy final binary `{}` to `{}`",
+                    bin_path.display(),
+                    to.display()
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The x86-64 levels of the `glibc-hwcaps` subdirectories, from the lowest one
+const GLIBC_HWCAPS_LEVELS: [&str; 3] = ["x86-64-v2", "x86-64-v3", "x86-64-v4"];
+
+/// Bytes that start an archive of variants, followed by each build preceded by its SHA3-256 hash
+/// and its length as a 64-bit little-endian integer
+const VARIANTS_ARCHIVE_MAGIC: &[u8; 8] = b"MVVARNT1";
+
+/// Copies a build of the given length, and returns its SHA3-256 hash
+fn copy_hashed(
+    build: &mut impl Read,
+    writer: impl Write,
+    length: u64,
+    build_path: &Path,
+) -> anyhow::Result<[u8; 32]> {
+    let mut hashed = Hashed {
+        writer,
+        hasher: Sha3_256::new(),
+    };
+    let copied = std::io::copy(build, &mut hashed)
+        .with_context(|| format!("Failed to copy `{}`", build_path.display()))?;
+    anyhow::ensure!(
+        copied == length,
+        "`{}` changed while it was copied",
+        build_path.display()
+    );
+
+    Ok(hashed.hasher.finalize().into())
+}
+
+/// A writer that hashes the bytes written to it
+struct Hashed<W> {
+    writer: W,
+    hasher: Sha3_256,
+}
+
+impl<W: Write> Write for Hashed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(buf.get(..written).unwrap_or_default());
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns the path of the variants of a runner, which the runner finds by adding `.variants` to its path
+fn variants_path(runner_path: &Path) -> PathBuf {
+    let mut path = runner_path.as_os_str().to_owned();
+    path.push(".variants");
+
+    path.into()
+}
+
+/// The binaries to pack, as described in a JSON file
+#[derive(Deserialize)]
+struct Description {
+    builds: Vec<BuildDescription>,
+
+    #[serde(default)]
+    rules: Vec<SelectionRule>,
+}
+
+/// Packs existing binaries into a runner, without building a package
+pub struct Pack {
+    packer: Packer,
+    builds: Vec<BuildDescription>,
+    rules: Vec<SelectionRule>,
+    name: Option<String>,
+    runner: RunnerMetadata,
+}
+
+impl Pack {
+    pub fn new(
+        packer: Packer,
+        builds: Vec<BuildDescription>,
+        rules: Vec<SelectionRule>,
+        name: Option<String>,
+    ) -> Self {
+        Self {
+            packer,
+            builds,
+            rules,
+            name,
+            runner: RunnerMetadata::default(),
+        }
+    }
+
+    /// Sets the options of the runner crate (from the metadata of the package of the binaries)
+    pub fn runner(mut self, runner: RunnerMetadata) -> Self {
+        self.runner = runner;
+
+        self
+    }
+
+    pub fn from_args(args: PackArgs) -> anyhow::Result<Self> {
+        let target = args.target()?.into_owned();
+
+        let (builds, rules) = if let Some(path) = &args.description {
+            let description = std::fs::read(path)
+                .with_context(|| format!("Failed to read `{}`", path.display()))?;
+            let description: Description = serde_json::from_slice(&description)
+                .with_context(|| format!("Failed to parse `{}`", path.display()))?;
+
+            // Relative paths are relative to the description
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            let builds = description
+                .builds
+                .into_iter()
+                .map(|build| BuildDescription {
+                    path: directory.join(build.path),
+                    ..build
+                })
+                .collect();
+
+            (builds, description.rules)
+        } else {
+            let builds = args
+                .builds
+                .into_iter()
+                .map(|(path, features)| BuildDescription {
+                    path,
+                    features,
+                    cpus: Vec::new(),
+                    baseline: false,
+                    hash: None,
+                    original_filename: None,
+                })
+                .collect();
+
+            (builds, Vec::new())
+        };
+
+        let packer = Packer::from_args(target, args.target_dir, args.out_dir, args.runner)?;
+
+        Ok(Self::new(packer, builds, rules, args.name))
+    }
+
+    /// Checks the CPU features and the selection rules, removes the duplicated binaries, and packs them into a runner
+    pub fn run(self) -> anyhow::Result<()> {
+        let target = self.packer.target();
+        let cpus = Cpus::builder(target)
+            .context("Failed to get the set of CPU features for the target")?
+            .build()?;
+        let known = cpus.features().collect::<BTreeSet<_>>();
+        // The runner only detects the features of its unstable `std::arch` macros with its `nightly` feature and a nightly toolchain
+        let (arch, os) = Rustc::arch_and_os(target)?;
+        let nightly = self
+            .packer
+            .runner_features()
+            .any(|feature| feature == "nightly")
+            && Rustc::version()
+                .is_ok_and(|version| version.contains("-nightly") || version.contains("-dev"));
+
+        for build in &self.builds {
+            for feature in &build.features {
+                anyhow::ensure!(
+                    known.contains(feature.as_str()),
+                    "The CPU feature `{feature}` of `{}` is not a CPU feature of the target `{target}`",
+                    build.path.display()
+                );
+                anyhow::ensure!(
+                    detection::is_detectable(&arch, &os, feature, nightly),
+                    "The CPU feature `{feature}` of `{}` cannot be detected by the runner on the target `{target}`",
+                    build.path.display()
+                );
+            }
+        }
+
+        for rule in &self.rules {
+            for cpu in [&rule.prefer, &rule.over] {
+                anyhow::ensure!(
+                    self.builds.iter().any(|build| build.cpus.contains(cpu)),
+                    "The CPU `{cpu}` of a selection rule is not the CPU of any binary"
+                );
+            }
+        }
+
+        let mut hasher = Sha3_256::new();
+        let builds = self
+            .builds
+            .into_iter()
+            .map(|build| {
+                // Like the builds description, the binaries are read from another directory
+                let path = std::fs::canonicalize(&build.path)
+                    .with_context(|| format!("Failed to find `{}`", build.path.display()))?;
+                let bytes = std::fs::read(&path)
+                    .with_context(|| format!("Failed to read `{}`", path.display()))?;
+                hasher.update(&bytes);
+
+                Ok(BuildDescription {
+                    hash: Some(hasher.finalize_reset().to_vec()),
+                    original_filename: path.file_name().map(ToOwned::to_owned),
+                    path,
+                    ..build
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let builds = self.packer.describe(builds, self.rules);
+        anyhow::ensure!(!builds.builds.is_empty(), "No binaries to pack");
+
+        // The builds requiring the fewest features are at the bottom
+        let original_filename = self.name.map(OsString::from).or_else(|| {
+            builds
+                .builds
+                .iter()
+                .rev()
+                .find_map(|build| build.original_filename.clone())
+        });
+        let original_filename = original_filename.context("Failed to name the runner")?;
+
+        println!(
+            "{:>12} binaries for {target}",
+            style("Packing").bold().green(),
+        );
+
+        self.packer.pack(
+            &builds,
+            &original_filename.to_string_lossy(),
+            &original_filename,
+            "release",
+            &self.runner,
+        )
+    }
+}
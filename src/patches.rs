@@ -0,0 +1,8 @@
+//! Generation of the compressed patches of the builds, shared with the build script of the runner
+//!
+//! The code is included from the sources of the runner embedded in `cargo-multivers` (`multivers-runner/build/patches.rs`),
+//! so that the payload appended to a runner stub is encoded like the builds embedded in a runner.
+include!(concat!(
+    env!("OUT_DIR"),
+    "/multivers-runner/build/patches.rs"
+));
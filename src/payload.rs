@@ -0,0 +1,235 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context;
+
+use rayon::prelude::*;
+
+use serde::Serialize;
+
+use crate::cli::{self, DeltaEncoder};
+use crate::metadata::SelectionRule;
+use crate::multivers::BuildsDescription;
+use crate::patches::{Compression, Delta, DeltaLayout, DeltaReport, Patch, Patches};
+
+/// Bytes that end a runner stub to which a payload is appended
+///
+/// The stub finds its payload by reading the trailer before them: the length of the payload and the length of the manifest,
+/// as 64-bit little-endian integers.
+const MAGIC: &[u8; 8] = b"MVPAYLD1";
+
+/// A build stored in the payload, as read by the runner stub
+#[derive(Serialize)]
+struct BuildEntry {
+    /// Offset of the build in the payload
+    offset: usize,
+    length: usize,
+    stored: bool,
+    features: Vec<String>,
+    cpus: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patch: Option<PatchEntry>,
+}
+
+/// How a build is patched from its parent
+#[derive(Serialize)]
+struct PatchEntry {
+    /// Index of the parent in the patched builds, or `None` if it is the source
+    parent: Option<usize>,
+    delta: Delta,
+}
+
+/// Describes the builds of the payload and the selection rules
+#[derive(Serialize)]
+struct Manifest<'a> {
+    compression: Compression,
+    source: BuildEntry,
+    patches: Vec<BuildEntry>,
+    rules: &'a [SelectionRule],
+}
+
+/// The builds appended to a prebuilt runner stub (`--runner-stub`), so that no runner is compiled for them
+///
+/// The patches are generated with the same code as the build script of the runner (see [`crate::patches`]),
+/// with the same compression, delta encoders, and layout.
+pub struct Payload {
+    data: Vec<u8>,
+    manifest: Vec<u8>,
+    pub report: DeltaReport,
+}
+
+impl Payload {
+    /// Compresses the source and generates the patches of the other builds
+    pub fn generate(description: &BuildsDescription) -> anyhow::Result<Self> {
+        if description.delta_encoders.is_empty() {
+            anyhow::bail!("At least one delta encoder is required to generate the patches");
+        }
+
+        // The source is the build of the baseline CPU, or the one requiring the fewest features
+        // (the builds are sorted to put the ones requiring more features at the top)
+        let mut builds = description.builds.iter().collect::<Vec<_>>();
+        let source_index = builds
+            .iter()
+            .position(|build| build.baseline)
+            .unwrap_or_else(|| builds.len().saturating_sub(1));
+        if source_index >= builds.len() {
+            anyhow::bail!("No builds to append to the runner stub");
+        }
+        let source_build = builds.remove(source_index);
+
+        let read = |path: &Path| {
+            std::fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))
+        };
+        let source = read(&source_build.path)?;
+        let targets = builds
+            .iter()
+            .map(|build| read(&build.path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let compression = description.compression;
+        let level = description.compression_level;
+        let encoders = description
+            .delta_encoders
+            .iter()
+            .map(|&encoder| Delta::from(encoder))
+            .collect::<Vec<_>>();
+        let layout = DeltaLayout::from(description.delta_layout);
+
+        let mut patches = layout
+            .pairs(targets.len())
+            .par_iter()
+            .map(|&(parent, i)| {
+                let parent_data = match parent {
+                    Some(parent) => targets.get(parent),
+                    None => Some(&source),
+                };
+                let (parent_data, target) = parent_data
+                    .zip(targets.get(i))
+                    .context("Invalid pair of builds to patch")?;
+
+                let patch = Patch::encode(&encoders, compression, level, parent_data, target)?;
+
+                Ok(((parent, i), patch))
+            })
+            .collect::<anyhow::Result<Patches>>()?;
+        let parents = layout.parents(targets.len(), &patches);
+        let report = DeltaReport::new(layout, &patches, &parents);
+
+        let mut data = if description.uncompressed_source {
+            source
+        } else {
+            compression.compress(level, &source)?
+        };
+        let source_entry = BuildEntry {
+            offset: 0,
+            length: data.len(),
+            stored: description.uncompressed_source,
+            features: source_build.features.clone(),
+            cpus: source_build.cpus.clone(),
+            patch: None,
+        };
+
+        let patch_entries = builds
+            .iter()
+            .zip(parents)
+            .enumerate()
+            .map(|(i, (build, parent))| {
+                let patch = patches
+                    .remove(&(parent, i))
+                    .context("Missing patch of a build")?;
+                let entry = BuildEntry {
+                    offset: data.len(),
+                    length: patch.compressed.len(),
+                    stored: false,
+                    features: build.features.clone(),
+                    cpus: build.cpus.clone(),
+                    patch: Some(PatchEntry {
+                        parent,
+                        delta: patch.delta,
+                    }),
+                };
+                data.extend_from_slice(&patch.compressed);
+
+                Ok(entry)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let manifest = serde_json::to_vec(&Manifest {
+            compression,
+            source: source_entry,
+            patches: patch_entries,
+            rules: &description.rules,
+        })
+        .context("Failed to encode the manifest of the payload")?;
+
+        Ok(Self {
+            data,
+            manifest,
+            report,
+        })
+    }
+
+    /// Copies the runner stub to the output path and appends the payload to it
+    pub fn append_to(&self, stub: &Path, output: &Path) -> anyhow::Result<()> {
+        std::fs::copy(stub, output).with_context(|| {
+            format!(
+                "Failed to copy the runner stub `{}` to `{}`",
+                stub.display(),
+                output.display()
+            )
+        })?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(output)
+            .with_context(|| format!("Failed to open `{}`", output.display()))?;
+        let trailer = [self.data.len(), self.manifest.len()]
+            .into_iter()
+            .flat_map(|length| (length as u64).to_le_bytes())
+            .chain(*MAGIC)
+            .collect::<Vec<_>>();
+        for bytes in [&self.data, &self.manifest, &trailer] {
+            file.write_all(bytes).with_context(|| {
+                format!("Failed to write the payload to `{}`", output.display())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<DeltaEncoder> for Delta {
+    fn from(encoder: DeltaEncoder) -> Self {
+        match encoder {
+            DeltaEncoder::Gdelta => Self::Gdelta,
+            DeltaEncoder::Bsdiff => Self::Bsdiff,
+        }
+    }
+}
+
+impl From<cli::DeltaLayout> for DeltaLayout {
+    fn from(layout: cli::DeltaLayout) -> Self {
+        match layout {
+            cli::DeltaLayout::Star => Self::Star,
+            cli::DeltaLayout::Tree => Self::Tree,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::patches::spanning_tree;
+
+    #[test]
+    fn spanning_tree_prefers_nearest_build() {
+        // Build 1 is closer to build 0 than to the source, build 0 is closer to the source
+        let sizes = |parent: Option<usize>, build: usize| match (parent, build) {
+            (None, 0) => 10,
+            (None, 1) => 100,
+            (Some(0), 1) => 5,
+            _ => 1000,
+        };
+        assert_eq!(spanning_tree(2, sizes), [None, Some(0)]);
+        assert_eq!(spanning_tree(0, sizes), []);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::Context;
+
+use serde::Deserialize;
+
+/// The build selected at a launch, as written by the runner in a report
+#[derive(Deserialize)]
+struct LaunchBuild {
+    #[serde(default)]
+    cpus: Vec<String>,
+    features: Vec<String>,
+    source: bool,
+}
+
+/// A line of a report written by the runner when `MULTIVERS_REPORT` is set
+#[derive(Deserialize)]
+struct Launch {
+    build: LaunchBuild,
+    decode_us: u64,
+}
+
+impl Launch {
+    /// Returns the name of the selected build (e.g., `x86-64-v4`, or `x86-64-v3, haswell`)
+    fn variant(&self) -> String {
+        let mut variant = if self.build.cpus.is_empty() {
+            format!("{} CPU features", self.build.features.len())
+        } else {
+            self.build.cpus.join(", ")
+        };
+
+        if self.build.source {
+            variant.push_str(" (source)");
+        }
+
+        variant
+    }
+}
+
+#[derive(Default)]
+struct VariantSummary {
+    launches: u64,
+    total_decode_us: u64,
+}
+
+/// Aggregates the reports written by runners into the number of launches of each build
+#[derive(Default)]
+pub struct ReportSummary {
+    variants: BTreeMap<String, VariantSummary>,
+    launches: u64,
+}
+
+impl ReportSummary {
+    /// Aggregates the given report files
+    pub fn from_paths(paths: impl IntoIterator<Item = impl AsRef<Path>>) -> anyhow::Result<Self> {
+        let mut summary = Self::default();
+
+        for path in paths {
+            let path = path.as_ref();
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Failed to open the report `{}`", path.display()))?;
+            summary
+                .add_lines(BufReader::new(file))
+                .with_context(|| format!("Failed to read the report `{}`", path.display()))?;
+        }
+
+        Ok(summary)
+    }
+
+    fn add_lines(&mut self, reader: impl BufRead) -> anyhow::Result<()> {
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let launch: Launch = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid launch at line {}", i + 1))?;
+
+            let variant = self.variants.entry(launch.variant()).or_default();
+            variant.launches += 1;
+            variant.total_decode_us += launch.decode_us;
+            self.launches += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ReportSummary {
+    /// Writes a table of the builds sorted by number of launches
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut variants = self.variants.iter().collect::<Vec<_>>();
+        variants.sort_by_key(|(_, variant)| std::cmp::Reverse(variant.launches));
+
+        let width = variants
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("Build".len()))
+            .max()
+            .unwrap_or_default();
+
+        writeln!(
+            f,
+            "{:<width$}  {:>8}  {:>6}  {:>11}",
+            "Build", "Launches", "Share", "Mean decode"
+        )?;
+        for (name, variant) in variants {
+            let share = variant.launches as f64 * 100.0 / self.launches as f64;
+            let mean_decode_ms = variant.total_decode_us as f64 / variant.launches as f64 / 1000.0;
+
+            writeln!(
+                f,
+                "{name:<width$}  {:>8}  {share:>5.1}%  {mean_decode_ms:>8.1} ms",
+                variant.launches
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReportSummary;
+
+    #[test]
+    fn summary() {
+        let reports = r#"{"timestamp":1,"host":{"features":["avx2"]},"build":{"cpus":["x86-64-v3"],"features":["avx2"],"source":false},"decode_us":3000}
+
+{"timestamp":2,"host":{"features":["avx2"]},"build":{"cpus":["x86-64-v3"],"features":["avx2"],"source":false},"decode_us":5000}
+{"timestamp":3,"host":{"features":[]},"build":{"cpus":[],"features":[],"source":true},"decode_us":1000}
+"#;
+
+        let mut summary = ReportSummary::default();
+        summary.add_lines(reports.as_bytes()).unwrap();
+
+        assert_eq!(
+            summary.to_string(),
+            "\
+Build                    Launches   Share  Mean decode
+x86-64-v3                       2   66.7%       4.0 ms
+0 CPU features (source)         1   33.3%       1.0 ms
+"
+        );
+    }
+
+    #[test]
+    fn invalid_line() {
+        let mut summary = ReportSummary::default();
+        let error = summary.add_lines(&b"{}\n"[..]).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid launch at line 1");
+    }
+}
@@ -1,18 +1,62 @@
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use anyhow::Context;
 
-use escargot::CargoBuild;
+use cargo_metadata::semver::{Version, VersionReq};
+
+use console::style;
+
+use escargot::{CargoBuild, CommandMessages};
 
 use itertools::Itertools;
 
-use crate::cargo::CommandMessagesExt;
+use serde_json::Value;
+
+use crate::cargo::{CommandMessagesExt, Output};
+use crate::metadata::RunnerMetadata;
+use crate::patches::{DeltaReport, LayoutReport};
+
+include!(concat!(env!("OUT_DIR"), "/runner_sources.rs"));
+
+/// Lock file of the crate that builds the embedded runner, to build it with the same dependencies on every machine
+const EMBEDDED_RUNNER_LOCK: &[u8] = include_bytes!("runner.lock");
+
+/// Name of the file in which the build script of the runner reports the patches it generated
+const DELTA_REPORT_FILENAME: &str = "delta_report.json";
+
+impl DeltaReport {
+    /// Returns the reports of the layouts that were generated, with their names
+    pub fn layouts(&self) -> impl Iterator<Item = (&'static str, LayoutReport)> {
+        std::iter::once(("star", self.star)).chain(self.tree.map(|tree| ("tree", tree)))
+    }
+
+    /// Returns the report of the layout embedded in the runner, with its name
+    pub fn selected(&self) -> (&'static str, LayoutReport) {
+        match self.tree {
+            Some(tree) => ("tree", tree),
+            None => ("star", self.star),
+        }
+    }
+}
+
+/// A runner built by [`RunnerBuilder`]
+pub struct Runner {
+    pub path: PathBuf,
+
+    /// The patches reported by the build script of the runner, if it reported them
+    pub delta_report: Option<DeltaReport>,
+}
 
 pub struct RunnerBuilder {
     output_directory: PathBuf,
     manifest_path: PathBuf,
+    /// The runner is built with the lock file of the embedded runner
+    locked: bool,
+    offline: bool,
 }
 
 impl RunnerBuilder {
@@ -26,70 +70,224 @@ impl RunnerBuilder {
         Self {
             output_directory,
             manifest_path,
+            locked: false,
+            offline: false,
         }
     }
 
-    /// Generates the sources of the crate to build the runner
+    /// Builds the runner without accessing the network, like `cargo build --offline`
+    pub fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Returns the version of the embedded runner if it matches the requested version
+    fn embedded_runner_version(multivers_runner_version: &str) -> Option<&'static str> {
+        let version = Version::parse(EMBEDDED_RUNNER_VERSION).ok()?;
+        let requirement = VersionReq::parse(multivers_runner_version).ok()?;
+
+        requirement
+            .matches(&version)
+            .then_some(EMBEDDED_RUNNER_VERSION)
+    }
+
+    /// Generates the sources of the crate to build the runner, with the options set in the metadata of the package
     pub fn generate_crate_sources(
         output_directory: impl Into<PathBuf>,
         multivers_runner_version: &str,
+        metadata: &RunnerMetadata,
     ) -> anyhow::Result<Self> {
         let output_directory = output_directory.into();
         let root_directory = output_directory.join("package-runner");
         let src_directory = root_directory.join("src");
         let manifest_path = root_directory.join("Cargo.toml");
         let main_path = src_directory.join("main.rs");
-        let local_multivers_runner_dependency =
-            Path::new(env!("CARGO_MANIFEST_DIR")).join("multivers-runner");
-
-        let local_path = if local_multivers_runner_dependency.exists() {
-            let local_multivers_runner_dependency = local_multivers_runner_dependency
-                .to_string_lossy()
-                .replace('\\', "/");
-            format!(r#", path = "{local_multivers_runner_dependency}""#)
+
+        // The sources of the runner embedded in cargo-multivers are used when they match the requested version,
+        // so that it is built without downloading it, and with the dependencies of its lock file
+        let embedded = Self::embedded_runner_version(multivers_runner_version).is_some();
+        let source = if embedded {
+            let runner_directory = root_directory.join("multivers-runner");
+            for (path, content) in EMBEDDED_RUNNER_SOURCES {
+                write_if_changed(&runner_directory.join(path), content)?;
+            }
+            write_if_changed(&root_directory.join("Cargo.lock"), EMBEDDED_RUNNER_LOCK)?;
+
+            r#"path = "multivers-runner""#.to_owned()
         } else {
-            String::new()
+            format!(r#"version = "{multivers_runner_version}""#)
+        };
+        // The decoder of the compression is enabled by a feature (e.g., `lz4`, one of the default features of `multivers-runner`),
+        // and a custom `main` replaces the one of `multivers-runner`
+        let features = if metadata.main.is_some() {
+            ""
+        } else {
+            r#", features = ["main"]"#
+        };
+        let dependencies = std::iter::once(format!(
+            "multivers-runner = {{ {source}, default-features = false{features} }}"
+        ))
+        .chain(
+            metadata
+                .dependencies
+                .iter()
+                .map(|(name, dependency)| format!("{} = {}", toml_key(name), to_toml(dependency))),
+        )
+        .join("\n");
+
+        let default_features = metadata
+            .features
+            .iter()
+            .map(|feature| toml_string(feature))
+            .join(", ");
+
+        let mut profile = BTreeMap::from([
+            ("lto".to_owned(), Value::from(true)),
+            ("strip".to_owned(), Value::from("symbols")),
+            ("opt-level".to_owned(), Value::from("z")),
+            ("codegen-units".to_owned(), Value::from(1)),
+            ("panic".to_owned(), Value::from("abort")),
+        ]);
+        profile.extend(metadata.profile.clone());
+        let profile = profile
+            .iter()
+            .map(|(key, value)| format!("{} = {}", toml_key(key), to_toml(value)))
+            .join("\n");
+
+        let edition = metadata.edition.as_deref().unwrap_or("2024");
+        let bin = match &metadata.main {
+            Some(main) => {
+                let main = std::path::absolute(main)
+                    .with_context(|| format!("Failed to find `{}`", main.display()))?;
+                format!(
+                    "[[bin]]\nname = \"package-multivers\"\npath = {}\n",
+                    toml_string(&main.to_string_lossy())
+                )
+            }
+            None => String::new(),
         };
-        let dependency = format!(
-            r#"multivers-runner = {{ version = "{multivers_runner_version}"{local_path} }}"#
-        );
 
         let manifest = format!(
             r#"[package]
 name = "package-multivers"
 publish = false
-edition = "2024"
+edition = {edition}
 
+{bin}
 [dependencies]
-{dependency}
+{dependencies}
 
 [features]
+default = [{default_features}]
 debug = ["multivers-runner/debug"]
+all-cpus = ["multivers-runner/all-cpus"]
+stub = ["multivers-runner/stub"]
+lz4 = ["multivers-runner/lz4"]
+zstd = ["multivers-runner/zstd"]
+xz = ["multivers-runner/xz"]
+external = ["multivers-runner/external"]
+extract = ["multivers-runner/extract"]
+nightly = ["multivers-runner/nightly"]
 
 [profile.release]
-lto = true
-strip = "symbols"
-opt-level = "z"
-codegen-units = 1
-panic = "abort"
+{profile}
 
 [workspace]
-        "#
+        "#,
+            edition = toml_string(edition),
         );
         let main = b"#![no_main]
 pub use multivers_runner::main;
         ";
 
-        std::fs::create_dir_all(&src_directory)?;
-        std::fs::write(&manifest_path, manifest)?;
-        std::fs::write(main_path, main)?;
+        write_if_changed(&manifest_path, manifest.as_bytes())?;
+        write_if_changed(&main_path, main)?;
 
         Ok(Self {
             output_directory,
             manifest_path,
+            // Cargo adds the additional dependencies to the lock file
+            locked: embedded && metadata.dependencies.is_empty(),
+            offline: false,
         })
     }
 
+    /// Returns the command that builds the runner with the given features in a target directory
+    ///
+    /// Cargo is executed with only the environment variables that do not change the code generated for the runner (see [`is_allowed_env`]),
+    /// so that the runner does not require the CPU features it detects (e.g., with `RUSTFLAGS=-Ctarget-cpu=native`).
+    /// `CARGO_ENCODED_RUSTFLAGS` is also set to an empty string, since it takes precedence over the `rustflags`
+    /// of the Cargo configuration files (e.g., `build.rustflags` in `.cargo/config.toml`).
+    /// It also drops `CARGO_UNSTABLE_BUILD_STD`, with which the build of the runner fails if `panic_abort` is not specified
+    /// (since its profile specifies `panic=abort`).
+    fn cargo(&self, target: &str, target_dir: &Path, features: String) -> Command {
+        let cargo = CargoBuild::new()
+            .release()
+            .target(target)
+            .target_dir(target_dir)
+            .manifest_path(&self.manifest_path)
+            .features(features);
+        let cargo = if self.locked {
+            cargo.arg("--locked")
+        } else {
+            cargo
+        };
+        let cargo = if self.offline {
+            cargo.arg("--offline")
+        } else {
+            cargo
+        };
+
+        let (allowed, ignored): (Vec<_>, Vec<_>) = std::env::vars_os()
+            .filter(|(name, _)| {
+                name.to_str()
+                    .is_some_and(|name| is_allowed_env(name) || changes_codegen(name))
+            })
+            .partition(|(name, _)| name.to_str().is_some_and(is_allowed_env));
+        if !ignored.is_empty() {
+            let ignored = ignored
+                .iter()
+                .map(|(name, _)| format!("`{}`", name.to_string_lossy()))
+                .sorted()
+                .join(", ");
+            eprintln!(
+                "{}: ignoring {ignored} to build the runner, since it changes the code generated for it",
+                style("warning").bold().yellow(),
+            );
+        }
+
+        let mut command = cargo.into_command();
+        command
+            .env_clear()
+            .envs(allowed)
+            .env("CARGO_ENCODED_RUSTFLAGS", "");
+
+        command
+    }
+
+    /// Builds a runner stub, to which the builds are appended instead of being embedded when it is compiled
+    ///
+    /// The stub does not depend on the builds, so Cargo only builds it again when the target or the features change.
+    pub fn build_stub<S>(
+        &self,
+        target: &str,
+        features: impl Iterator<Item = S>,
+    ) -> anyhow::Result<PathBuf>
+    where
+        S: Display,
+    {
+        let features = features
+            .map(|feature| feature.to_string())
+            .chain(std::iter::once("stub".to_owned()))
+            .join(" ");
+
+        let command = self.cargo(target, &self.output_directory.join("stub"), features);
+
+        CommandMessages::with_command(command)
+            .context("Failed to execute cargo to build the runner stub")?
+            .find_executable()?
+            .context("Failed to build the runner stub")
+    }
+
     /// Builds a runner that includes the given builds
     pub fn build<S>(
         &self,
@@ -97,56 +295,322 @@ pub use multivers_runner::main;
         builds_path: &Path,
         original_filename: &OsStr,
         mut features: impl Iterator<Item = S>,
-    ) -> anyhow::Result<PathBuf>
+    ) -> anyhow::Result<Runner>
     where
         S: Display,
     {
         let features = features.join(" ");
 
-        // We do not propagate `CARGO_UNSTABLE_BUILD_STD` since if `panic_abort` is not
-        // specified, the build of the runner will fail (since its profile specifies `panic=abort`).
-        // A proper fix could be to clear the whole environment before spawning this `cargo build`,
-        // but until `CargoBuild` exposes the `Command` or this function, we can only do this.
-        let cargo = CargoBuild::new()
-            .release()
-            .target(target)
-            .target_dir(&self.output_directory)
-            .manifest_path(&self.manifest_path)
-            .features(features)
-            .env_remove("CARGO_UNSTABLE_BUILD_STD")
-            .env("MULTIVERS_BUILDS_DESCRIPTION_PATH", builds_path);
+        let mut command = self.cargo(target, &self.output_directory, features);
+        command.env("MULTIVERS_BUILDS_DESCRIPTION_PATH", builds_path);
 
-        let cargo = cargo
-            .exec()
+        let cargo = CommandMessages::with_command(command)
             .context("Failed to execute cargo to build the runner")?;
 
-        let bin_path = cargo
-            .find_executable()?
-            .context("Failed to build the runner")?;
+        let mut bin_path = None;
+        let mut delta_report = None;
+        for output in cargo.find_outputs() {
+            match output? {
+                Output::Executable(path) => bin_path = Some(path),
+                Output::Library(_) => {}
+                Output::OutDir(out_dir) => {
+                    // Only the build script of `multivers-runner` writes this file
+                    let report_path = out_dir.join(DELTA_REPORT_FILENAME);
+                    if let Ok(report) = std::fs::read(&report_path) {
+                        delta_report =
+                            Some(serde_json::from_slice(&report).with_context(|| {
+                                format!("Failed to parse `{}`", report_path.display())
+                            })?);
+                    }
+                }
+            }
+        }
+        let bin_path = bin_path.context("Failed to build the runner")?;
 
         let mut output_path = bin_path.clone();
         output_path.set_file_name(original_filename);
 
         std::fs::rename(&bin_path, &output_path)?;
 
-        Ok(output_path)
+        Ok(Runner {
+            path: output_path,
+            delta_report,
+        })
+    }
+}
+
+/// Environment variables given to Cargo to build the runner, with the ones that start with [`ALLOWED_ENV_PREFIXES`]
+///
+/// They locate the toolchains and configure the linkers, but do not change the code generated for the runner.
+const ALLOWED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TERM",
+    "TMPDIR",
+    "TMP",
+    "TEMP",
+    "CARGO",
+    "CARGO_HOME",
+    "CARGO_MAKEFLAGS",
+    "CARGO_NET_OFFLINE",
+    "CARGO_BUILD_JOBS",
+    "MAKEFLAGS",
+    "RUSTUP_HOME",
+    "RUSTUP_TOOLCHAIN",
+    "RUSTC",
+    "RUSTC_WRAPPER",
+    "CC",
+    "CXX",
+    "AR",
+    "LD",
+    "CFLAGS",
+    "CXXFLAGS",
+    "SDKROOT",
+    "MACOSX_DEPLOYMENT_TARGET",
+    // Network access to download the dependencies of the runner
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+    "NO_PROXY",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "SSH_AUTH_SOCK",
+    // Windows
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "PROGRAMDATA",
+    "PROGRAMFILES",
+    "PROGRAMFILES(X86)",
+    "INCLUDE",
+    "LIB",
+    "LIBPATH",
+];
+
+/// Prefixes of the environment variables given to Cargo to build the runner (see [`ALLOWED_ENV`])
+const ALLOWED_ENV_PREFIXES: &[&str] = &[
+    "LC_",
+    "CARGO_HTTP_",
+    "CARGO_NET_",
+    "CARGO_REGISTRIES_",
+    "CARGO_REGISTRY_",
+    "CARGO_SOURCE_",
+    "CARGO_TERM_",
+    "GIT_",
+    "CC_",
+    "CXX_",
+    "AR_",
+    "CFLAGS_",
+    "CXXFLAGS_",
+    "TARGET_",
+    "HOST_",
+    "PKG_CONFIG",
+    "VS",
+    "VC",
+    "WINDOWSSDK",
+    "UCRT",
+];
+
+/// Prefixes of the environment variables that change the code generated for the runner
+/// (e.g., `RUSTFLAGS=-Ctarget-cpu=native` makes it require the CPU features it detects)
+const CODEGEN_ENV_PREFIXES: &[&str] = &[
+    "RUSTFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_BUILD_RUSTFLAGS",
+    "CARGO_BUILD_RUSTC",
+    "CARGO_PROFILE_",
+    "CARGO_UNSTABLE_",
+];
+
+/// Returns true if an environment variable is given to Cargo to build the runner
+///
+/// The linker of a target (`CARGO_TARGET_<triple>_LINKER`) is given, but not its other settings (e.g., its `RUSTFLAGS`).
+fn is_allowed_env(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+
+    ALLOWED_ENV.contains(&name.as_str())
+        || ALLOWED_ENV_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || (name.starts_with("CARGO_TARGET_") && name.ends_with("_LINKER"))
+}
+
+/// Returns true if an environment variable changes the code generated for the runner
+fn changes_codegen(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+
+    CODEGEN_ENV_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || (name.starts_with("CARGO_TARGET_") && name.ends_with("_RUSTFLAGS"))
+}
+
+/// Formats a string as a TOML string
+fn toml_string(string: &str) -> String {
+    Value::from(string).to_string()
+}
+
+/// Formats a key of a TOML table, which is only quoted if it is not a bare key
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        key.to_owned()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// Formats a value of the metadata of a package (which Cargo converts from TOML to JSON) as TOML
+fn to_toml(value: &Value) -> String {
+    match value {
+        // TOML has no null value, and Cargo never converts a value to it
+        Value::Null => "\"\"".to_owned(),
+        // JSON strings, booleans, and numbers are valid in TOML
+        Value::Bool(_) | Value::Number(_) | Value::String(_) => value.to_string(),
+        Value::Array(values) => format!("[{}]", values.iter().map(to_toml).join(", ")),
+        Value::Object(values) => format!(
+            "{{ {} }}",
+            values
+                .iter()
+                .map(|(key, value)| format!("{} = {}", toml_key(key), to_toml(value)))
+                .join(", ")
+        ),
+    }
+}
+
+/// Writes a file (and its parent directories) only if its content changed,
+/// so that Cargo does not build again the runner because its sources were written again
+fn write_if_changed(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if std::fs::read(path).is_ok_and(|current| current == content) {
+        return Ok(());
     }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(path, content)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use cargo_metadata::MetadataCommand;
 
-    use crate::runner::RunnerBuilder;
+    use serde_json::json;
+
+    use crate::metadata::RunnerMetadata;
+    use crate::patches::DeltaReport;
+    use crate::runner::{RunnerBuilder, changes_codegen, is_allowed_env};
 
     #[test]
     fn metadata_works() {
         let tmp = tempfile::tempdir().unwrap();
-        let runner = RunnerBuilder::generate_crate_sources(tmp.path(), "0.3").unwrap();
+        let runner =
+            RunnerBuilder::generate_crate_sources(tmp.path(), "0.3", &RunnerMetadata::default())
+                .unwrap();
+        // Fails if the lock file of the embedded runner is outdated
         let metadata = MetadataCommand::new()
             .manifest_path(&runner.manifest_path)
+            .other_options(vec!["--locked".to_owned()])
             .exec()
             .unwrap();
         assert_eq!(metadata.root_package().unwrap().name, "package-multivers");
     }
+
+    #[test]
+    fn metadata_with_runner_options() {
+        let tmp = tempfile::tempdir().unwrap();
+        let metadata = RunnerMetadata {
+            profile: BTreeMap::from([("opt-level".to_owned(), json!(3))]),
+            edition: Some("2021".to_owned()),
+            dependencies: BTreeMap::from([("cfg-if".to_owned(), json!({"version": "1"}))]),
+            features: vec!["debug".to_owned()],
+            main: None,
+        };
+        let runner = RunnerBuilder::generate_crate_sources(tmp.path(), "0.3", &metadata).unwrap();
+        let manifest = std::fs::read_to_string(&runner.manifest_path).unwrap();
+        assert!(manifest.contains("opt-level = 3"));
+        assert!(manifest.contains(r#"panic = "abort""#));
+
+        let metadata = MetadataCommand::new()
+            .manifest_path(&runner.manifest_path)
+            .exec()
+            .unwrap();
+        let package = metadata.root_package().unwrap();
+        assert_eq!(package.edition.as_str(), "2021");
+        assert_eq!(package.features["default"], ["debug"]);
+        assert!(
+            package
+                .dependencies
+                .iter()
+                .any(|dependency| dependency.name == "cfg-if")
+        );
+    }
+
+    #[test]
+    fn embedded_runner_version() {
+        assert!(
+            RunnerBuilder::embedded_runner_version("0.3")
+                .is_some_and(|version| version.starts_with("0.3."))
+        );
+        assert_eq!(RunnerBuilder::embedded_runner_version("0.2"), None);
+        assert_eq!(RunnerBuilder::embedded_runner_version("invalid"), None);
+    }
+
+    #[test]
+    fn runner_env() {
+        assert!(is_allowed_env("PATH"));
+        assert!(is_allowed_env(
+            "CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_LINKER"
+        ));
+        assert!(is_allowed_env("CC_x86_64_unknown_linux_gnu"));
+        assert!(is_allowed_env("https_proxy"));
+        assert!(is_allowed_env("GIT_SSH_COMMAND"));
+        assert!(is_allowed_env("CARGO_BUILD_JOBS"));
+        assert!(!is_allowed_env("RUSTFLAGS"));
+        assert!(!is_allowed_env(
+            "CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUSTFLAGS"
+        ));
+
+        assert!(changes_codegen("RUSTFLAGS"));
+        assert!(changes_codegen("CARGO_ENCODED_RUSTFLAGS"));
+        assert!(changes_codegen("CARGO_BUILD_RUSTFLAGS"));
+        assert!(!changes_codegen("CARGO_BUILD_JOBS"));
+        assert!(changes_codegen("CARGO_PROFILE_RELEASE_OPT_LEVEL"));
+        assert!(changes_codegen(
+            "CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUSTFLAGS"
+        ));
+        assert!(!changes_codegen(
+            "CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_LINKER"
+        ));
+    }
+
+    #[test]
+    fn delta_report() {
+        let report: DeltaReport = serde_json::from_str(
+            r#"{"star":{"size":1000,"seconds":1.5},"tree":{"size":600,"seconds":4.0}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            report.layouts().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["star", "tree"]
+        );
+        assert_eq!(report.selected().0, "tree");
+        assert_eq!(report.selected().1.size, 600);
+
+        let report: DeltaReport =
+            serde_json::from_str(r#"{"star":{"size":1000,"seconds":1.5},"tree":null}"#).unwrap();
+        assert_eq!(report.layouts().count(), 1);
+        assert_eq!(report.selected().0, "star");
+    }
 }
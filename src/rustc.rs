@@ -22,6 +22,20 @@ static RUSTC: LazyLock<PathBuf> = LazyLock::new(|| {
         .unwrap_or_else(|| "rustc".into())
 });
 
+/// CPU features that rustc enables for some CPUs, but that are not part of the CPU features of a build
+pub const IGNORED_FEATURES: &[&str] = &[
+    // See https://github.com/rust-lang/rust/issues/116344
+    "x87",
+    // AArch64 features that rustc emits as target_feature cfg values for Neoverse CPUs
+    // but which are not reported to user space, so they cannot be detected at run-time.
+    "lor",   // Limited Ordering Regions (ARMv8.1)
+    "pan",   // Privileged Access Never (ARMv8.1)
+    "pmuv3", // Performance Monitor Unit v3
+    "ras",   // Reliability, Availability and Serviceability (ARMv8.2)
+    "spe",   // Statistical Profiling Extension (ARMv8.2)
+    "vh",    // Virtualization Host Extensions (ARMv8.1)
+];
+
 /// Wrapper around the `rustc` command
 pub struct Rustc;
 
@@ -47,33 +61,37 @@ impl Rustc {
             .context("Failed to detect default target")
     }
 
-    /// Returns all CPU features supported by a given CPU on a target
-    pub fn features_from_cpu(target: &str, cpu: &str) -> anyhow::Result<BTreeSet<String>> {
-        let ignored_features = [
-            // See https://github.com/rust-lang/rust/issues/116344
-            "x87",
-            // AArch64 features that rustc emits as target_feature cfg values for Neoverse CPUs
-            // but which `is_aarch64_feature_detected!` cannot detect at run-time or are unknown.
-            "lor",   // Limited Ordering Regions (ARMv8.1)
-            "pan",   // Privileged Access Never (ARMv8.1)
-            "pmuv3", // Performance Monitor Unit v3
-            "ras",   // Reliability, Availability and Serviceability (ARMv8.2)
-            "spe",   // Statistical Profiling Extension (ARMv8.2)
-            "vh",    // Virtualization Host Extensions (ARMv8.1)
-        ];
+    /// Returns the verbose version of rustc (`rustc -vV`), which identifies the toolchain
+    pub fn version() -> anyhow::Result<String> {
+        let rustc_v = Self::command().arg("-vV").output().with_context(|| {
+            format!(
+                "Failed to execute rustc ({})",
+                Self::command().get_program().display()
+            )
+        })?;
 
-        let cfg = Self::command()
-            .args([
-                "--print=cfg",
-                "--target",
-                target,
-                &format!("-Ctarget-cpu={cpu}"),
-            ])
-            .output()?;
+        anyhow::ensure!(
+            rustc_v.status.success(),
+            "Failed to get the version of rustc"
+        );
+
+        String::from_utf8(rustc_v.stdout).context("Invalid version of rustc")
+    }
+
+    /// Returns all CPU features that rustc enables for a given CPU on a target (or for the default CPU of the target),
+    /// including the [`IGNORED_FEATURES`]
+    pub fn enabled_features(target: &str, cpu: Option<&str>) -> anyhow::Result<BTreeSet<String>> {
+        let mut command = Self::command();
+        command.args(["--print=cfg", "--target", target]);
+        if let Some(cpu) = cpu {
+            command.arg(format!("-Ctarget-cpu={cpu}"));
+        }
+        let cfg = command.output()?;
 
         anyhow::ensure!(
             cfg.status.success() && cfg.stderr.is_empty(),
-            "Invalid CPU `{cpu}`"
+            "Invalid CPU `{}`",
+            cpu.unwrap_or("default")
         );
 
         let features = cfg
@@ -89,12 +107,34 @@ impl Rustc {
 
                 line.strip_suffix('"').map(ToOwned::to_owned)
             })
-            .filter(|feature| !ignored_features.contains(&feature.as_str()))
             .collect();
 
         Ok(features)
     }
 
+    /// Returns the architecture and the operating system of a target, as in `CARGO_CFG_TARGET_ARCH` and `CARGO_CFG_TARGET_OS`
+    pub fn arch_and_os(target: &str) -> anyhow::Result<(String, String)> {
+        let cfg = Self::command()
+            .args(["--print=cfg", "--target", target])
+            .output()?;
+
+        anyhow::ensure!(cfg.status.success(), "Invalid target `{target}`");
+
+        let cfg = cfg.stdout.lines().map_while(Result::ok).collect::<Vec<_>>();
+        let value = |key: &str| {
+            cfg.iter()
+                .find_map(|line| {
+                    line.strip_prefix(key)?
+                        .strip_prefix("=\"")?
+                        .strip_suffix('"')
+                })
+                .map(ToOwned::to_owned)
+                .with_context(|| format!("Failed to get the `{key}` of the target `{target}`"))
+        };
+
+        Ok((value("target_arch")?, value("target_os")?))
+    }
+
     /// Returns all the known CPUs of a given target
     pub fn cpus_from_target(target: &str) -> anyhow::Result<Vec<String>> {
         let cpus = Self::command()
@@ -162,16 +202,25 @@ mod tests {
     }
 
     #[test]
-    fn test_features_from_cpu_not_empty() {
+    fn test_enabled_features_not_empty() {
         let target = Rustc::default_target().unwrap();
         let cpus = Rustc::cpus_from_target(&target).unwrap();
-        let features = Rustc::features_from_cpu(&target, cpus.first().unwrap()).unwrap();
+        let features = Rustc::enabled_features(&target, cpus.first().map(String::as_str)).unwrap();
         assert!(!features.is_empty());
     }
 
     #[test]
-    fn test_features_from_cpu_invalid() {
+    fn test_arch_and_os() {
+        let (arch, os) = Rustc::arch_and_os("x86_64-unknown-linux-gnu").unwrap();
+        assert_eq!(arch, "x86_64");
+        assert_eq!(os, "linux");
+
+        Rustc::arch_and_os("invalid").unwrap_err();
+    }
+
+    #[test]
+    fn test_enabled_features_invalid() {
         let target = Rustc::default_target().unwrap();
-        Rustc::features_from_cpu(&target, "invalid invalid").unwrap_err();
+        Rustc::enabled_features(&target, Some("invalid invalid")).unwrap_err();
     }
 }
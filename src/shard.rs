@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
+
+use console::style;
+
+use crate::cli::MergeArgs;
+use crate::metadata::{RunnerMetadata, SelectionRule};
+use crate::multivers::BuildDescription;
+use crate::pack::{Pack, Packer};
+
+/// Name of the file that describes the variants written in a directory by `--emit-variants`
+const VARIANTS_FILENAME: &str = "variants.json";
+
+/// A subset of the variants to build, given as `INDEX/COUNT` (e.g., `2/4` builds the second quarter of the variants)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// Index of the shard, starting at 1
+    pub index: usize,
+    /// Number of shards
+    pub count: usize,
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Self { index: 1, count: 1 }
+    }
+}
+
+impl Shard {
+    /// Returns true if the variant at the given position (starting at 0) is built by this shard
+    pub fn contains(self, variant: usize) -> bool {
+        variant % self.count + 1 == self.index
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(shard: &str) -> Result<Self, Self::Err> {
+        let (index, count) = shard
+            .split_once('/')
+            .ok_or_else(|| format!("expected `INDEX/COUNT`, got `{shard}`"))?;
+        let parse = |number: &str| {
+            number
+                .parse::<usize>()
+                .map_err(|_| format!("invalid number `{number}`"))
+        };
+        let (index, count) = (parse(index)?, parse(count)?);
+
+        if index == 0 || index > count {
+            return Err(format!("the index must be between 1 and {count}"));
+        }
+
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Everything that determines the versions of a package, which must be the same for all the shards
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub target: String,
+    pub profile: String,
+    /// Features of the package given to Cargo, and the arguments given to `cargo build`
+    pub features: Vec<String>,
+    pub cargo_args: Vec<String>,
+    pub rust_flags: String,
+    /// Output of `rustc -vV`
+    pub toolchain: String,
+    /// Description of each variant (its CPU features, and the CPU it is tuned for)
+    pub variants: Vec<String>,
+    /// Number of shards
+    pub shards: usize,
+}
+
+impl Plan {
+    /// Returns the names of the fields that differ between two plans
+    fn differences(&self, other: &Self) -> Vec<&'static str> {
+        [
+            ("target", self.target == other.target),
+            ("profile", self.profile == other.profile),
+            ("features", self.features == other.features),
+            ("cargo arguments", self.cargo_args == other.cargo_args),
+            ("RUSTFLAGS", self.rust_flags == other.rust_flags),
+            ("toolchain", self.toolchain == other.toolchain),
+            ("variants", self.variants == other.variants),
+            ("number of shards", self.shards == other.shards),
+        ]
+        .into_iter()
+        .filter_map(|(name, same)| (!same).then_some(name))
+        .collect()
+    }
+}
+
+/// The variants of a package built by a shard, written with the binaries by `--emit-variants`
+#[derive(Serialize, Deserialize)]
+pub struct Variants {
+    pub package: String,
+    pub plan: Plan,
+    pub shard: Shard,
+    /// File name of the runner
+    pub original_filename: OsString,
+    /// The builds of the shard, whose paths are relative to the directory of this file
+    pub builds: Vec<BuildDescription>,
+    pub rules: Vec<SelectionRule>,
+    /// Options of the runner crate set in the metadata of the package
+    #[serde(default)]
+    pub runner: RunnerMetadata,
+}
+
+impl Variants {
+    /// Copies the binaries in the directory and writes their description next to them
+    pub fn write_to(mut self, directory: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create the directory `{}`", directory.display()))?;
+
+        for build in &mut self.builds {
+            let filename = build
+                .path
+                .file_name()
+                .context("Invalid path of a build")?
+                .to_owned();
+            let to = directory.join(&filename);
+            std::fs::copy(&build.path, &to).with_context(|| {
+                format!(
+                    "Failed to copy build `{}` to `{}`",
+                    build.path.display(),
+                    to.display()
+                )
+            })?;
+            build.path = filename.into();
+        }
+
+        let path = directory.join(VARIANTS_FILENAME);
+        let encoded = serde_json::to_vec_pretty(&self).context("Failed to encode the variants")?;
+        std::fs::write(&path, encoded)
+            .with_context(|| format!("Failed to write to `{}`", path.display()))?;
+
+        println!(
+            "{:>12} {} versions of shard {} ({})",
+            style("Emitted").bold().green(),
+            self.builds.len(),
+            self.shard,
+            directory.display()
+        );
+
+        Ok(())
+    }
+
+    /// Reads the description of the variants in a directory, with the paths of the binaries relative to the current directory
+    fn read_from(directory: &Path) -> anyhow::Result<Self> {
+        let path = directory.join(VARIANTS_FILENAME);
+        let variants =
+            std::fs::read(&path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+        let mut variants: Self = serde_json::from_slice(&variants)
+            .with_context(|| format!("Failed to parse `{}`", path.display()))?;
+
+        for build in &mut variants.builds {
+            build.path = directory.join(&build.path);
+        }
+
+        Ok(variants)
+    }
+}
+
+/// Merges the variants emitted by the shards of a build into runners (`cargo multivers merge`)
+pub struct Merge {
+    args: MergeArgs,
+}
+
+impl Merge {
+    pub fn new(args: MergeArgs) -> Self {
+        Self { args }
+    }
+
+    /// Checks that all the shards of each package were built with the same plan, then packs their variants into a runner
+    pub fn run(self) -> anyhow::Result<()> {
+        // Each directory given to `--emit-variants` contains a directory per package
+        let mut packages = BTreeMap::<String, Vec<Variants>>::new();
+        for directory in &self.args.directories {
+            let entries = std::fs::read_dir(directory).with_context(|| {
+                format!("Failed to read the directory `{}`", directory.display())
+            })?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.join(VARIANTS_FILENAME).is_file() {
+                    let variants = Variants::read_from(&path)?;
+                    packages
+                        .entry(variants.package.clone())
+                        .or_default()
+                        .push(variants);
+                }
+            }
+        }
+        anyhow::ensure!(!packages.is_empty(), "No variants to merge");
+
+        for (package, mut shards) in packages {
+            shards.sort_by_key(|variants| variants.shard.index);
+            let (first, others) = shards.split_first().context("A package has no shards")?;
+
+            for variants in others {
+                let differences = first.plan.differences(&variants.plan);
+                anyhow::ensure!(
+                    differences.is_empty(),
+                    "The shards {} and {} of `{package}` were not built with the same plan (different {})",
+                    first.shard,
+                    variants.shard,
+                    differences.join(", ")
+                );
+            }
+            let indices = shards
+                .iter()
+                .map(|variants| variants.shard.index)
+                .collect::<Vec<_>>();
+            let expected = (1..=first.plan.shards).collect::<Vec<_>>();
+            anyhow::ensure!(
+                indices == expected,
+                "Expected the shards {expected:?} of `{package}`, got {indices:?}"
+            );
+
+            println!(
+                "{:>12} {} shards of {package}",
+                style("Merging").bold().green(),
+                shards.len(),
+            );
+
+            let target = first.plan.target.clone();
+            let original_filename = first.original_filename.to_string_lossy().into_owned();
+            let rules = first.rules.clone();
+            let runner = first.runner.clone();
+            let builds = shards
+                .into_iter()
+                .flat_map(|variants| variants.builds)
+                .collect();
+            let packer = Packer::from_args(
+                target,
+                self.args.target_dir.clone(),
+                self.args.out_dir.clone(),
+                self.args.runner.clone(),
+            )?;
+
+            Pack::new(packer, builds, rules, Some(original_filename))
+                .runner(runner)
+                .run()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shard;
+
+    #[test]
+    fn parse_shard() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn shards_cover_all_variants() {
+        let shards = (1..=3).map(|index| Shard { index, count: 3 });
+        for variant in 0..10 {
+            assert_eq!(
+                shards
+                    .clone()
+                    .filter(|shard| shard.contains(variant))
+                    .count(),
+                1
+            );
+        }
+    }
+}
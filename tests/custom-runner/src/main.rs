@@ -1,2 +1,25 @@
 #![no_main]
-pub use multivers_runner::main;
+
+use std::ffi::c_char;
+
+use multivers_runner::{Build, Runner};
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn main(argc: i32, argv: *const *const c_char, envp: *const *const c_char) {
+    let runner = Runner::new()
+        .before_select(|| {
+            eprintln!("custom runner: {} builds", Build::builds().count());
+
+            Ok(())
+        })
+        .before_exec(|build| {
+            eprintln!(
+                "custom runner: executing build with CPU features: {}",
+                build.features().join(",")
+            );
+
+            Ok(())
+        });
+
+    unsafe { runner.main(argc, argv, envp) }
+}
@@ -0,0 +1,18 @@
+//! A library loaded from C, whose builds differ with the CPU features
+
+/// Returns the dot product of two arrays of `len` floats
+///
+/// # Safety
+///
+/// `a` and `b` must point to `len` floats.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dot(a: *const f32, b: *const f32, len: usize) -> f32 {
+    let (a, b) = unsafe {
+        (
+            std::slice::from_raw_parts(a, len),
+            std::slice::from_raw_parts(b, len),
+        )
+    };
+
+    a.iter().zip(b).map(|(a, b)| a.mul_add(*b, 0.0)).sum()
+}
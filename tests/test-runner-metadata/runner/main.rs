@@ -0,0 +1,19 @@
+#![no_main]
+
+use std::ffi::c_char;
+
+use multivers_runner::Runner;
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn main(argc: i32, argv: *const *const c_char, envp: *const *const c_char) {
+    let runner = Runner::new().before_exec(|build| {
+        eprintln!(
+            "runner from metadata: executing build with CPU features: {}",
+            build.features().join(",")
+        );
+
+        Ok(())
+    });
+
+    unsafe { runner.main(argc, argv, envp) }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    print!("{}", cfg!(target_feature = "avx2"));
+}
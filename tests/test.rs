@@ -108,7 +108,7 @@ fn crate_that_prints_argv() {
         )));
 }
 
-/// Checks that it can build a crate that prints its argv with a custom runner
+/// Checks that it can build a crate that prints its argv with a custom runner using the hooks of the runner
 #[test]
 fn crate_that_prints_argv_with_custom_runner() {
     let custom_runner = Path::new(env!("CARGO_MANIFEST_DIR"))
@@ -129,7 +129,30 @@ fn crate_that_prints_argv_with_custom_runner() {
     .stdout(predicate::str::ends_with(format!(
         "{}\n",
         expected_args.join(" ")
-    )));
+    )))
+    .stderr(predicate::str::contains(
+        "custom runner: executing build with CPU features: ",
+    ));
+}
+
+/// Checks that the runner is generated with the options set in the metadata of the package
+#[test]
+fn runner_metadata() {
+    let expected_args = ["runner", "metadata"];
+    build_and_run_crate("test-runner-metadata", None, |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3"]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )))
+    .stderr(predicate::str::contains(
+        "runner from metadata: executing build with CPU features: ",
+    ));
 }
 
 /// Checks that it fails to build with an invalid custom runner manifest path
@@ -227,6 +250,34 @@ fn rebuild_std_env() {
     )));
 }
 
+/// Checks that the variables of the environment that change the code generated for the runner are not used to build it
+#[test]
+fn runner_hermetic_env() {
+    let expected_args = ["test-argv", "env"];
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command
+            .args(["--cpus", "x86-64,x86-64-v3"])
+            .env("RUSTFLAGS", "-Cforce-frame-pointers=yes")
+            .env("CARGO_PROFILE_RELEASE_OPT_LEVEL", "3");
+    });
+    assert.success().stderr(predicate::str::contains(
+        "warning: ignoring `CARGO_PROFILE_RELEASE_OPT_LEVEL`, `RUSTFLAGS` to build the runner",
+    ));
+
+    Command::new(
+        out_dir
+            .path()
+            .join(format!("test-argv{}", std::env::consts::EXE_SUFFIX)),
+    )
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
 /// Checks that we can build using the dev profile
 #[test]
 fn profile_dev() {
@@ -287,3 +338,640 @@ fn correct_build_used() {
     .success()
     .stdout(predicate::eq(runtime_features));
 }
+
+/// Checks that we can build a crate with a baseline CPU that is not part of the list of CPUs
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn baseline_cpu() {
+    let expected_args = ["baseline", "foo2", "''"];
+    build_and_run_crate("test-argv", None, |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3", "--baseline-cpu", "x86-64-v2"]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that we can build a crate with a runner that checks the CPU features on all the CPUs
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn runner_all_cpus() {
+    let expected_args = ["all", "cpus", "''"];
+    build_and_run_crate("test-argv", None, |command| {
+        command.args([
+            "--cpus",
+            "x86-64,x86-64-v2,native",
+            "--runner-features",
+            "all-cpus",
+        ]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that the runner follows the selection rules of the package's metadata
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn selection_rules() {
+    build_and_run_crate("test-selection-rules", None, |command| {
+        command.args(["--cpus", "x86-64,x86-64-v2,x86-64-v3"]);
+    })
+    .0
+    .assert()
+    .success()
+    .stdout(predicate::eq("false"));
+}
+
+/// Checks that an error is returned when a selection rule refers to a CPU that is not built
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn selection_rules_unknown_cpu() {
+    build_crate("test-selection-rules", |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3"]);
+    })
+    .0
+    .failure()
+    .stderr(predicate::str::contains(
+        "The CPU `x86-64-v2` of a selection rule is not part of the CPUs to build with",
+    ));
+}
+
+/// Checks that we can build a crate with a version tuned for each CPU
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn tune_cpus() {
+    let expected_args = ["tuned", "''"];
+    build_and_run_crate("test-argv", None, |command| {
+        command.args(["--cpus", "x86-64-v2,nehalem,native", "--tune-cpus"]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that we can build a crate whose source build is stored uncompressed in the runner
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn uncompressed_source() {
+    let expected_args = ["uncompressed", "''"];
+    build_and_run_crate("test-argv", None, |command| {
+        command.args(["--cpus", "x86-64,x86-64-v4", "--uncompressed-source"]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that we can build a crate whose builds are compressed with each compression
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn compression() {
+    for compression in ["zstd", "xz", "none"] {
+        let expected_args = [compression, "''"];
+        build_and_run_crate("test-argv", None, |command| {
+            command.args(["--cpus", "x86-64,x86-64-v2", "--compression", compression]);
+        })
+        .0
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+    }
+}
+
+/// Checks that we can build a crate whose patches are only generated with a given delta encoder
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn delta_encoders() {
+    let expected_args = ["bsdiff", "''"];
+    build_and_run_crate("test-argv", None, |command| {
+        command.args(["--cpus", "x86-64,x86-64-v2", "--delta-encoders", "bsdiff"]);
+    })
+    .0
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that we can build a crate whose builds are patched from their nearest build,
+/// and that the sizes of both layouts are reported
+///
+/// The runner is then built again, with the patches reused from the cache, which reports the same layouts.
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn delta_layout_tree() {
+    let args = [
+        "--cpus",
+        "x86-64,x86-64-v3,haswell,znver3",
+        "--tune-cpus",
+        "--delta-encoders",
+        "gdelta",
+        "--delta-layout",
+        "tree",
+    ];
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args(args);
+    });
+    let assert = assert
+        .success()
+        .stdout(predicate::str::contains("star layout"))
+        .stdout(predicate::str::contains("tree layout"))
+        .stdout(predicate::str::contains("(selected)"));
+    let tree_layout = |stdout: &[u8]| {
+        String::from_utf8_lossy(stdout)
+            .lines()
+            .find(|line| line.contains("tree layout: "))
+            .map(ToOwned::to_owned)
+            .unwrap()
+    };
+    let first_tree_layout = tree_layout(&assert.get_output().stdout);
+
+    let expected_args = ["tree", "''"];
+    Command::new(out_dir.path().join("test-argv"))
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+
+    let assert = cargo_multivers()
+        .arg("--manifest-path")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test-argv/Cargo.toml"))
+        .arg("--target-dir")
+        .arg(out_dir.path().join("target"))
+        .args(args)
+        .assert()
+        .success();
+    assert_eq!(tree_layout(&assert.get_output().stdout), first_tree_layout);
+
+    let build_dir = out_dir
+        .path()
+        .join("target/x86_64-unknown-linux-gnu/release/build");
+    let cached_patches = std::fs::read_dir(build_dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter_map(|entry| std::fs::read_dir(entry.path().join("out/patch_cache")).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .count();
+    assert!(cached_patches > 0);
+}
+
+/// Checks that we can append the builds to a runner stub instead of compiling a runner that embeds them
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn runner_stub() {
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args([
+            "--cpus",
+            "x86-64,x86-64-v3",
+            "--delta-layout",
+            "tree",
+            "--runner-stub",
+        ]);
+    });
+    assert
+        .success()
+        .stdout(predicate::str::contains(
+            "Appending 2 versions to a runner stub",
+        ))
+        .stdout(predicate::str::contains("(selected)"));
+
+    let expected_args = ["stub", "''"];
+    Command::new(out_dir.path().join("test-argv"))
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+}
+
+/// Checks that the runner reads the builds written beside it, in a directory or an archive, and checks their hash
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn external_variants() {
+    let expected_args = ["external", "''"];
+    let run = |out_dir: &Path| {
+        Command::new(out_dir.join("test-argv"))
+            .args(expected_args)
+            .assert()
+    };
+
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3", "--external-variants"]);
+    });
+    assert.success().stdout(predicate::str::contains(
+        "Writing 2 versions beside the runner",
+    ));
+    let variants = std::fs::read_dir(out_dir.path().join("test-argv.variants"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(variants.len(), 2);
+    run(out_dir.path())
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+
+    for variant in &variants {
+        std::fs::write(variant, b"tampered").unwrap();
+    }
+    run(out_dir.path())
+        .failure()
+        .stderr(predicate::str::contains("the hash of the variant differs"));
+
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args([
+            "--cpus",
+            "x86-64,x86-64-v3",
+            "--external-variants",
+            "archive",
+        ]);
+    });
+    assert.success();
+    assert!(out_dir.path().join("test-argv.variants").is_file());
+    run(out_dir.path())
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+}
+
+/// Checks that the `cdylib` of a package is built for each x86-64 level in the glibc-hwcaps layout
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux", target_env = "gnu"))]
+fn glibc_hwcaps() {
+    let (assert, out_dir) = build_crate("test-cdylib", |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3,x86-64-v4", "--glibc-hwcaps"]);
+    });
+    assert.success().stdout(predicate::str::contains(
+        "Finished 2 versions in the glibc-hwcaps layout",
+    ));
+
+    let library = "libtest_cdylib.so";
+    let baseline = std::fs::read(out_dir.path().join(library)).unwrap();
+    let v3 = std::fs::read(out_dir.path().join("glibc-hwcaps/x86-64-v3").join(library)).unwrap();
+    assert_ne!(baseline, v3);
+    // The build of x86-64-v4 is identical to the one of x86-64-v3, so glibc loads the latter
+    assert!(!out_dir.path().join("glibc-hwcaps/x86-64-v4").exists());
+    assert!(!out_dir.path().join("glibc-hwcaps/x86-64-v2").exists());
+}
+
+/// Checks that the `cdylib` of a package is built for the x86-64 levels in the glibc-hwcaps layout when no list of CPUs is set
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux", target_env = "gnu"))]
+fn glibc_hwcaps_default_cpus() {
+    let (assert, out_dir) = build_crate("test-cdylib", |command| {
+        command.arg("--glibc-hwcaps");
+    });
+    assert
+        .success()
+        .stdout(predicate::str::contains("glibc-hwcaps layout"));
+
+    let library = "libtest_cdylib.so";
+    assert!(out_dir.path().join(library).is_file());
+    assert!(
+        out_dir
+            .path()
+            .join("glibc-hwcaps/x86-64-v3")
+            .join(library)
+            .is_file()
+    );
+}
+
+/// Checks that an error is returned when the glibc-hwcaps layout is requested for a package without a `cdylib`
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux", target_env = "gnu"))]
+fn glibc_hwcaps_no_cdylib() {
+    build_crate("test-argv", |command| {
+        command.arg("--glibc-hwcaps");
+    })
+    .0
+    .failure()
+    .stderr(predicate::str::contains("No cdylib package detected"));
+}
+
+/// Checks that the launcher executes the runner built for the architecture of the host, copied to the subdirectory of its target
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux", target_env = "gnu"))]
+fn launcher() {
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args([
+            "--cpus",
+            "x86-64,x86-64-v3",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--launcher",
+        ]);
+    });
+    assert
+        .success()
+        .stdout(predicate::str::contains("Finished launcher"));
+    assert!(
+        out_dir
+            .path()
+            .join("x86_64-unknown-linux-gnu/test-argv")
+            .is_file()
+    );
+
+    let expected_args = ["launched", "''"];
+    Command::new(out_dir.path().join("test-argv"))
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+
+    // The launcher finds the runners through a symbolic link from another directory
+    let link_dir = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(
+        out_dir.path().join("test-argv"),
+        link_dir.path().join("test-argv"),
+    )
+    .unwrap();
+    Command::new(link_dir.path().join("test-argv"))
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+}
+
+/// Checks that a package can be installed from a directory or a local Git repository, reinstalled, and uninstalled
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn install() {
+    let root = tempfile::tempdir().unwrap();
+    let bin = root
+        .path()
+        .join("bin")
+        .join(format!("test-argv{}", std::env::consts::EXE_SUFFIX));
+    let test_crate = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test-argv");
+    let install = |source: &[&std::ffi::OsStr]| {
+        cargo_multivers()
+            .arg("install")
+            .args(source)
+            .arg("--root")
+            .arg(root.path())
+            .args(["--cpus", "x86-64,x86-64-v3"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Installing test-argv v0.0.0"));
+
+        let expected_args = ["installed", "''"];
+        Command::new(&bin)
+            .args(expected_args)
+            .assert()
+            .success()
+            .stdout(predicate::str::ends_with(format!(
+                "{}\n",
+                expected_args.join(" ")
+            )));
+    };
+
+    install(&["--path".as_ref(), test_crate.as_os_str()]);
+    assert!(root.path().join(".multivers.json").is_file());
+    // Reinstalling replaces the binary of the package
+    install(&["--path".as_ref(), test_crate.as_os_str()]);
+
+    cargo_multivers()
+        .args(["uninstall", "test-argv", "--root"])
+        .arg(root.path())
+        .assert()
+        .success();
+    assert!(!bin.exists());
+    cargo_multivers()
+        .args(["uninstall", "test-argv", "--root"])
+        .arg(root.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "The package `test-argv` is not installed",
+        ));
+
+    let repository = tempfile::tempdir().unwrap();
+    std::fs::create_dir(repository.path().join("src")).unwrap();
+    for file in ["Cargo.toml", "Cargo.lock", "src/main.rs"] {
+        std::fs::copy(test_crate.join(file), repository.path().join(file)).unwrap();
+    }
+    for args in [
+        &["init", "--quiet"][..],
+        &["add", "."],
+        &[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "test-argv",
+        ],
+    ] {
+        Command::new("git")
+            .args(args)
+            .current_dir(repository.path())
+            .assert()
+            .success();
+    }
+    install(&["--git".as_ref(), repository.path().as_os_str()]);
+}
+
+/// Checks that we can pack the builds of a crate described by the `builds.json` written by `cargo multivers`
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn pack() {
+    let (assert, out_dir) = build_crate("test-argv", |command| {
+        command.args(["--cpus", "x86-64,x86-64-v3"]);
+    });
+    assert.success();
+
+    let pack_dir = tempfile::tempdir().unwrap();
+    cargo_multivers()
+        .args(["pack", "--name", "packed", "--description"])
+        .arg(out_dir.path().join("target/test-argv/builds.json"))
+        .arg("--target-dir")
+        .arg(pack_dir.path().join("target"))
+        .arg("--out-dir")
+        .arg(pack_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Compiling 2 versions compressed into a runner",
+        ));
+
+    let expected_args = ["packed", "''"];
+    Command::new(pack_dir.path().join("packed"))
+        .args(expected_args)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(format!(
+            "{}\n",
+            expected_args.join(" ")
+        )));
+}
+
+/// Checks that an error is returned when a binary to pack requires a CPU feature unknown to the target
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn pack_unknown_feature() {
+    cargo_multivers()
+        .args(["pack", "--target", "x86_64-unknown-linux-gnu", "binary=sse2,unknown"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "The CPU feature `unknown` of `binary` is not a CPU feature of the target `x86_64-unknown-linux-gnu`",
+        ));
+}
+
+/// Checks that the versions built by several shards can be merged into a runner
+#[test]
+fn shard_merge() {
+    let emit = |shard: &str| {
+        let variants_dir = tempfile::tempdir().unwrap();
+        build_crate("test-argv", |command| {
+            command
+                .args([
+                    "--cpus",
+                    "x86-64,x86-64-v3",
+                    "--shard",
+                    shard,
+                    "--emit-variants",
+                ])
+                .arg(variants_dir.path());
+        })
+        .0
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "Emitted 1 versions of shard {shard}"
+        )));
+        variants_dir
+    };
+    let shards = [emit("1/2"), emit("2/2")];
+
+    let merge_dir = tempfile::tempdir().unwrap();
+    cargo_multivers()
+        .arg("merge")
+        .args(shards.iter().map(|shard| shard.path()))
+        .arg("--target-dir")
+        .arg(merge_dir.path().join("target"))
+        .arg("--out-dir")
+        .arg(merge_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Merging 2 shards of test-argv"))
+        .stdout(predicate::str::contains(
+            "Compiling 2 versions compressed into a runner",
+        ));
+
+    let expected_args = ["test-argv", "''"];
+    Command::new(
+        merge_dir
+            .path()
+            .join(format!("test-argv{}", std::env::consts::EXE_SUFFIX)),
+    )
+    .args(expected_args)
+    .assert()
+    .success()
+    .stdout(predicate::str::ends_with(format!(
+        "{}\n",
+        expected_args.join(" ")
+    )));
+}
+
+/// Checks that an error is returned when the compression level is not supported by the compression
+#[test]
+fn compression_invalid_level() {
+    build_crate("test-argv", |command| {
+        command.args(["--compression", "zstd", "--compression-level", "23"]);
+    })
+    .0
+    .failure()
+    .stderr(predicate::str::contains(
+        "The level of the `zstd` compression must be between 1 and 22",
+    ));
+}
+
+/// Checks that the runner appends a report at each launch when `MULTIVERS_REPORT` is set, and that the reports can be summarized
+#[test]
+#[cfg(any(target_os = "linux", windows))]
+fn report() {
+    let (mut command, out_dir) = build_and_run_crate("test-argv", None, |_| {});
+    let report = out_dir.path().join("launches.jsonl");
+
+    for _ in 0..2 {
+        command.env("MULTIVERS_REPORT", &report).assert().success();
+    }
+
+    let reports = std::fs::read_to_string(&report).unwrap();
+    assert_eq!(reports.lines().count(), 2);
+
+    cargo_multivers()
+        .arg("report-summary")
+        .arg(&report)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("100.0%"));
+}
+
+/// Checks that the overhead of the runner can be measured, with the timings of its phases
+#[test]
+#[cfg(any(target_os = "linux", windows))]
+fn bench() {
+    let (command, _out_dir) = build_and_run_crate("test-argv", None, |command| {
+        command.args(["--runner-features", "extract"]);
+    });
+
+    cargo_multivers()
+        .args(["bench", "--runs", "3"])
+        .arg(command.get_program())
+        .args(["--", "bench"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Runs: 3")
+                .and(predicate::str::contains("Overhead"))
+                .and(predicate::str::contains("source_decompress"))
+                .and(predicate::str::contains("total")),
+        );
+}